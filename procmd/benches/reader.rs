@@ -0,0 +1,25 @@
+//! Compares the throughput of `PipeCommand::for_each_line` with different buffer capacities.
+//!
+//! Run with `cargo bench --bench reader`.
+
+use procmd::{cmd, ReaderConfig};
+use std::time::Instant;
+
+const BYTES: usize = 64 * 1024 * 1024;
+
+fn main() {
+    for &capacity in &[8 * 1024, 64 * 1024, 1024 * 1024] {
+        let mut pipe_cmd = cmd!("head", "-c", BYTES.to_string(), "/dev/zero" => "tr", "\\0", "a" => "fold", "-w", "100");
+        pipe_cmd.reader_config(ReaderConfig { capacity });
+        let mut total = 0;
+        let start = Instant::now();
+        pipe_cmd.for_each_line(|line| total += line.len()).unwrap();
+        let elapsed = start.elapsed();
+        assert_eq!(total, BYTES);
+        println!(
+            "capacity {:>8}: {:>8.1} MiB/s",
+            capacity,
+            BYTES as f64 / 1024.0 / 1024.0 / elapsed.as_secs_f64()
+        );
+    }
+}
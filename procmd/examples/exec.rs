@@ -0,0 +1,17 @@
+//! A launcher that replaces itself with `ls`, passing on its own arguments.
+
+#[cfg(unix)]
+fn main() {
+    use procmd::{cmd, CommandExt};
+
+    let mut command = cmd!("ls");
+    command.args(std::env::args_os().skip(1));
+    let err = command.exec_replace();
+    eprintln!("failed to execute ls: {}", err);
+    std::process::exit(1);
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("this example is only supported on Unix");
+}
@@ -0,0 +1,14 @@
+//! Pipes a directory listing into `less`, which keeps control of the terminal.
+//!
+//! This example is interactive and has to be run from a terminal.
+
+use procmd::cmd;
+
+fn main() {
+    let mut pipe_cmd = cmd!("ls", "-la" => "less");
+    match pipe_cmd.status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => eprintln!("less exited with {}", status),
+        Err(err) => eprintln!("failed to run the pipeline: {}", err),
+    }
+}
@@ -0,0 +1,57 @@
+//! Removal of ANSI escape sequences.
+
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+
+/// Removes ANSI escape sequences from `input`.
+///
+/// The following sequences are removed:
+///
+/// - CSI sequences (`ESC [`, parameter and intermediate bytes and a final byte), e.g. colors
+///   (`ESC [ 31 m`) and cursor movements.
+/// - OSC sequences (`ESC ]` up to `BEL` or `ESC \`), e.g. hyperlinks and window titles.
+/// - Other escape sequences consisting of `ESC`, intermediate bytes and a final byte, e.g.
+///   character set selections (`ESC ( B`).
+///
+/// An incomplete sequence at the end of `input` is removed as well.
+pub(crate) fn strip(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len());
+    let mut bytes = input.iter().copied().peekable();
+    while let Some(byte) = bytes.next() {
+        if byte != ESC {
+            output.push(byte);
+            continue;
+        }
+        match bytes.next() {
+            Some(b'[') => {
+                // Parameter and intermediate bytes are in 0x20..=0x3f, the final byte is in
+                // 0x40..=0x7e.
+                for byte in bytes.by_ref() {
+                    if (0x40..=0x7e).contains(&byte) {
+                        break;
+                    }
+                }
+            }
+            Some(b']') => {
+                while let Some(byte) = bytes.next() {
+                    if byte == BEL {
+                        break;
+                    }
+                    if byte == ESC && bytes.peek() == Some(&b'\\') {
+                        bytes.next();
+                        break;
+                    }
+                }
+            }
+            Some(0x20..=0x2f) => {
+                for byte in bytes.by_ref() {
+                    if !(0x20..=0x2f).contains(&byte) {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    output
+}
@@ -0,0 +1,64 @@
+use crate::pipeline;
+use std::convert::TryInto;
+use std::io;
+use std::pin::Pin;
+use std::process::{Child, ExitStatus};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::process::ChildStdout;
+use tokio::task::JoinHandle;
+
+/// An asynchronous reader for the stdout of the last command of a pipeline.
+///
+/// This is returned by [`PipeCommand::spawn_async`] and implements [`AsyncRead`]. The commands are
+/// waited for in a blocking task of the Tokio runtime, which is started when the pipeline is
+/// spawned, so all commands are reaped once they exit, even if the reader is dropped. Their exit
+/// statuses can be retrieved with [`wait`].
+///
+/// [`PipeCommand::spawn_async`]: crate::PipeCommand::spawn_async
+/// [`wait`]: Self::wait
+#[derive(Debug)]
+pub struct AsyncPipeReader<const N: usize> {
+    stdout: ChildStdout,
+    reaper: JoinHandle<io::Result<Vec<ExitStatus>>>,
+}
+
+impl<const N: usize> AsyncPipeReader<N> {
+    pub(crate) fn new(
+        stdout: ChildStdout,
+        reaper: JoinHandle<io::Result<Vec<ExitStatus>>>,
+    ) -> Self {
+        Self { stdout, reaper }
+    }
+
+    /// Closes the stdout and waits for all commands to exit, returning their exit statuses.
+    ///
+    /// If the last command is still writing to its stdout, it will receive an error (or `SIGPIPE`
+    /// on Unix), so the output should be read to the end first.
+    pub async fn wait(self) -> io::Result<[ExitStatus; N]> {
+        drop(self.stdout);
+        let statuses = self.reaper.await.map_err(io::Error::other)??;
+        Ok(statuses.try_into().unwrap())
+    }
+}
+
+/// Kills and reaps the children that it contains when it is dropped.
+///
+/// This is used to kill a pipeline when a future that waits for it is dropped.
+pub(crate) struct KillOnDrop(pub(crate) Vec<Child>);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        pipeline::kill_all(std::mem::take(&mut self.0));
+    }
+}
+
+impl<const N: usize> AsyncRead for AsyncPipeReader<N> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stdout).poll_read(cx, buf)
+    }
+}
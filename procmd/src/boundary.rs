@@ -0,0 +1,69 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{self, PipeWriter, Read, Write};
+use std::process::ChildStdout;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// A callback that observes a chunk of data.
+type TapFn = Box<dyn FnMut(&[u8]) + Send>;
+
+/// A callback that replaces a chunk of data.
+type TransformFn = Box<dyn FnMut(&[u8]) -> Vec<u8> + Send>;
+
+/// A callback that is invoked on the data between two commands of a pipeline.
+pub(crate) enum Hook {
+    /// Observes the data without modifying it.
+    Tap(TapFn),
+    /// Replaces the data with the returned data.
+    Transform(TransformFn),
+}
+
+impl fmt::Debug for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tap(_) => f.debug_tuple("Tap").finish_non_exhaustive(),
+            Self::Transform(_) => f.debug_tuple("Transform").finish_non_exhaustive(),
+        }
+    }
+}
+
+/// The hooks of a connection between two commands, in the order in which they were added.
+///
+/// The hooks are shared with the thread that copies the data, so they can be used every time the
+/// pipeline is spawned.
+pub(crate) type Hooks = Arc<Mutex<Vec<Hook>>>;
+
+/// Copies the data of `stdout` to `writer` in chunks of at most `capacity` bytes and invokes
+/// `hooks` on every chunk.
+///
+/// Every hook receives the chunk as it was returned by the previous transform.
+///
+/// The copy stops once `stdout` is closed or writing to `writer` fails.
+pub(crate) fn copy(
+    mut stdout: ChildStdout,
+    mut writer: PipeWriter,
+    hooks: &Hooks,
+    capacity: usize,
+) {
+    let mut buf = vec![0; capacity];
+    loop {
+        let len = match stdout.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+        let mut chunk = Cow::Borrowed(&buf[..len]);
+        let mut hooks = hooks.lock().unwrap_or_else(PoisonError::into_inner);
+        for hook in hooks.iter_mut() {
+            match hook {
+                Hook::Tap(f) => f(&chunk),
+                Hook::Transform(f) => chunk = Cow::Owned(f(&chunk)),
+            }
+        }
+        drop(hooks);
+        if writer.write_all(&chunk).is_err() {
+            break;
+        }
+    }
+}
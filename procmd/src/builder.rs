@@ -0,0 +1,89 @@
+use std::ffi::{OsStr, OsString};
+use std::process::Command;
+
+/// A builder for commands with subcommands, like `git commit -m <message>`.
+///
+/// This is an alternative to the [`cmd!`] macro that models the common shape of a program
+/// followed by subcommands and flags. It only collects the program and the arguments, so
+/// everything else (e.g. the working directory) is configured on the [`Command`] returned by
+/// [`build`].
+///
+/// # Examples
+///
+/// ```rust
+/// use procmd::Cmd;
+///
+/// let message = "Fix typo";
+/// let command = Cmd::program("git").sub("commit").flag("-m", message).arg("--quiet").build();
+/// assert_eq!(command.get_program(), "git");
+/// assert!(command.get_args().eq(["commit", "-m", "Fix typo", "--quiet"]));
+/// ```
+///
+/// [`cmd!`]: crate::cmd
+/// [`build`]: Self::build
+#[derive(Debug, Clone)]
+pub struct Cmd {
+    program: OsString,
+    args: Vec<OsString>,
+}
+
+impl Cmd {
+    /// Creates a new builder for `program`.
+    pub fn program<S: AsRef<OsStr>>(program: S) -> Self {
+        Self {
+            program: program.as_ref().to_owned(),
+            args: Vec::new(),
+        }
+    }
+
+    /// Adds a subcommand, e.g. `commit` in `git commit`.
+    ///
+    /// This is the same as [`arg`](Self::arg), but makes the structure of the command line
+    /// easier to read.
+    // This is not a subtraction, so implementing `std::ops::Sub` would be misleading.
+    #[allow(clippy::should_implement_trait)]
+    pub fn sub<S: AsRef<OsStr>>(self, subcommand: S) -> Self {
+        self.arg(subcommand)
+    }
+
+    /// Adds a flag followed by its value as two arguments, e.g. `-m <message>`.
+    ///
+    /// Flags without a value can be added with [`arg`](Self::arg).
+    pub fn flag<F, V>(self, flag: F, value: V) -> Self
+    where
+        F: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.arg(flag).arg(value)
+    }
+
+    /// Adds an argument.
+    pub fn arg<S: AsRef<OsStr>>(mut self, arg: S) -> Self {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    /// Adds multiple arguments.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+        self
+    }
+
+    /// Builds the [`Command`].
+    pub fn build(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args);
+        command
+    }
+}
+
+impl From<Cmd> for Command {
+    fn from(cmd: Cmd) -> Self {
+        cmd.build()
+    }
+}
@@ -0,0 +1,261 @@
+use crate::parse::{self, ParseError};
+use crate::Flag;
+use std::ffi::{OsStr, OsString};
+use std::fmt::Display;
+#[cfg(unix)]
+use std::io;
+use std::process::Command;
+
+/// Extension methods for [`Command`].
+pub trait CommandExt {
+    /// Splits `s` into words and adds each word as an argument.
+    ///
+    /// The words are split like a POSIX shell would split them, i.e. they are separated by
+    /// whitespace and quotes and backslashes are interpreted (see the [`FromStr`] implementation
+    /// of [`DynPipeCommand`] for details, except that `|` has no special meaning here). Note that
+    /// this does **not** invoke a shell, so there are no expansions and no other commands can be
+    /// injected through `s`.
+    ///
+    /// This is useful for reading options from a configuration file.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::{cmd, CommandExt};
+    ///
+    /// # fn main() -> Result<(), procmd::ParseError> {
+    /// let mut command = cmd!("grep");
+    /// command.args_from_str("-i -e 'my pattern'")?;
+    /// assert!(command.get_args().eq(["-i", "-e", "my pattern"]));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`FromStr`]: std::str::FromStr
+    /// [`DynPipeCommand`]: crate::DynPipeCommand
+    fn args_from_str(&mut self, s: &str) -> Result<&mut Self, ParseError>;
+
+    /// Splits `bytes` at NUL bytes and adds each part as an argument.
+    ///
+    /// This is the robust way to pass a list of file names that was produced by another program,
+    /// like the output of `find -print0` or `git ls-files -z` (similar to `xargs -0`). In contrast
+    /// to [`args_from_str`], file names can contain any character except NUL, including spaces,
+    /// quotes and newlines, and nothing is interpreted. A single trailing NUL byte terminates the
+    /// last argument and doesn't add an empty argument, so both NUL-separated and NUL-terminated
+    /// input is supported.
+    ///
+    /// On Unix the arguments are passed exactly as in `bytes`. On other platforms the arguments
+    /// must be UTF-8 and invalid sequences are replaced with `U+FFFD`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::{cmd, CommandExt};
+    ///
+    /// let mut command = cmd!("rm", "--");
+    /// command.args_from_nul_separated(b"my file.txt\0-rf\0");
+    /// assert!(command.get_args().eq(["--", "my file.txt", "-rf"]));
+    /// ```
+    ///
+    /// [`args_from_str`]: Self::args_from_str
+    fn args_from_nul_separated<B: AsRef<[u8]>>(&mut self, bytes: B) -> &mut Self;
+
+    /// Adds a `--` argument followed by `args`.
+    ///
+    /// Most programs stop parsing options after `--`, so this prevents arguments that come from
+    /// an untrusted source (e.g. user-provided file names) from being interpreted as options,
+    /// even if they start with `-`. Note that this relies on the program honoring `--`, which
+    /// not all programs do.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::{cmd, CommandExt};
+    ///
+    /// let file_name = "-rf";
+    /// let mut command = cmd!("rm", "-i");
+    /// command.args_after_separator([file_name]);
+    /// assert!(command.get_args().eq(["-i", "--", "-rf"]));
+    /// ```
+    fn args_after_separator<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>;
+
+    /// Appends the trailing arguments of a wrapper program.
+    ///
+    /// This is useful for wrapper programs that forward all arguments after their own arguments to
+    /// an inner program, like `my-tool --verbose -- <inner args>`. With `clap`, such arguments can
+    /// be captured with a positional argument that has [`trailing_var_arg`] enabled and the result
+    /// can be passed to this method. The arguments are added as they are, so they can't be
+    /// interpreted as options of the wrapper or as shell syntax.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// use clap::{Arg, ArgAction, Command};
+    /// use procmd::{cmd, CommandExt};
+    /// use std::ffi::OsString;
+    ///
+    /// let matches = Command::new("my-tool")
+    ///     .arg(Arg::new("verbose").long("verbose").action(ArgAction::SetTrue))
+    ///     .arg(
+    ///         Arg::new("inner")
+    ///             .num_args(..)
+    ///             .trailing_var_arg(true)
+    ///             .allow_hyphen_values(true)
+    ///             .value_parser(clap::value_parser!(OsString)),
+    ///     )
+    ///     .get_matches();
+    /// let trailing = matches.get_many::<OsString>("inner").into_iter().flatten().cloned();
+    /// let status = cmd!("cargo", "test").with_trailing(trailing).status()?;
+    /// ```
+    ///
+    /// [`trailing_var_arg`]: https://docs.rs/clap/latest/clap/struct.Arg.html#method.trailing_var_arg
+    fn with_trailing<I>(&mut self, trailing: I) -> &mut Self
+    where
+        I: IntoIterator<Item = OsString>;
+
+    /// Adds the arguments of a typed flag (see [`Flag`]).
+    ///
+    /// [`Flag`]: crate::Flag
+    fn flag<F: Flag>(&mut self, flag: F) -> &mut Self;
+
+    /// Adds the arguments of multiple typed flags in order (see [`Flag`]).
+    ///
+    /// [`Flag`]: crate::Flag
+    fn flags<I>(&mut self, flags: I) -> &mut Self
+    where
+        I: IntoIterator,
+        I::Item: Flag;
+
+    /// Adds a `--key=value` argument for every entry of `map`.
+    ///
+    /// The arguments are added in the iteration order of `map`. A [`BTreeMap`] iterates in the
+    /// order of its keys, so the command line is deterministic, which matters for reproducible
+    /// command lines in tests or for using command lines as cache keys. The iteration order of a
+    /// [`HashMap`] is unspecified and can differ between runs, so a [`BTreeMap`] should be used
+    /// if the order matters (note that most programs don't care about the order of options).
+    ///
+    /// Every entry is added as a single argument like with [`flag!`], so values with spaces don't
+    /// need to be quoted.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::{cmd, CommandExt};
+    /// use std::collections::BTreeMap;
+    ///
+    /// let mut options = BTreeMap::new();
+    /// options.insert("jobs", "4");
+    /// options.insert("color", "never");
+    /// let mut command = cmd!("cargo", "build");
+    /// command.args_map(&options);
+    /// assert!(command.get_args().eq(["build", "--color=never", "--jobs=4"]));
+    /// ```
+    ///
+    /// Since environment variables are unordered, [`Command::envs`] can be used with any map.
+    ///
+    /// [`BTreeMap`]: std::collections::BTreeMap
+    /// [`HashMap`]: std::collections::HashMap
+    /// [`flag!`]: crate::flag
+    fn args_map<I, K, V>(&mut self, map: I) -> &mut Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Display,
+        V: Display;
+
+    /// Replaces the current process with the command (see [`exec`]).
+    ///
+    /// On success, this method never returns, since the current process image is replaced by the
+    /// program of the command. If it returns, an error occurred (e.g. the program was not found)
+    /// and the current process continues to run. This is useful for launcher or shim binaries
+    /// that only set up the command.
+    ///
+    /// This method is only available on Unix. It can only be used with a single [`Command`], not
+    /// with a pipeline.
+    ///
+    /// [`exec`]: std::os::unix::process::CommandExt::exec
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use procmd::{cmd, CommandExt};
+    ///
+    /// let err = cmd!("ls", "-a").exec_replace();
+    /// eprintln!("failed to execute ls: {}", err);
+    /// ```
+    #[cfg(unix)]
+    fn exec_replace(&mut self) -> io::Error;
+}
+
+impl CommandExt for Command {
+    fn args_from_str(&mut self, s: &str) -> Result<&mut Self, ParseError> {
+        let words = parse::split(s, false)?.into_iter().flatten();
+        Ok(self.args(words))
+    }
+
+    fn args_from_nul_separated<B: AsRef<[u8]>>(&mut self, bytes: B) -> &mut Self {
+        let bytes = bytes.as_ref();
+        let bytes = bytes.strip_suffix(b"\0").unwrap_or(bytes);
+        if bytes.is_empty() {
+            return self;
+        }
+        self.args(bytes.split(|&b| b == 0).map(os_string_from_bytes))
+    }
+
+    fn args_after_separator<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.arg("--").args(args)
+    }
+
+    fn with_trailing<I>(&mut self, trailing: I) -> &mut Self
+    where
+        I: IntoIterator<Item = OsString>,
+    {
+        self.args(trailing)
+    }
+
+    fn flag<F: Flag>(&mut self, flag: F) -> &mut Self {
+        self.args(flag.to_args())
+    }
+
+    fn flags<I>(&mut self, flags: I) -> &mut Self
+    where
+        I: IntoIterator,
+        I::Item: Flag,
+    {
+        for flag in flags {
+            self.flag(flag);
+        }
+        self
+    }
+
+    fn args_map<I, K, V>(&mut self, map: I) -> &mut Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Display,
+        V: Display,
+    {
+        self.args(map.into_iter().map(|(key, value)| crate::flag!(key, value)))
+    }
+
+    #[cfg(unix)]
+    fn exec_replace(&mut self) -> io::Error {
+        std::os::unix::process::CommandExt::exec(self)
+    }
+}
+
+#[cfg(unix)]
+fn os_string_from_bytes(bytes: &[u8]) -> OsString {
+    use std::os::unix::ffi::OsStrExt;
+    OsStr::from_bytes(bytes).to_owned()
+}
+
+#[cfg(not(unix))]
+fn os_string_from_bytes(bytes: &[u8]) -> OsString {
+    String::from_utf8_lossy(bytes).into_owned().into()
+}
@@ -0,0 +1,527 @@
+use crate::parse::{self, ParseError};
+use crate::pipeline::{self, AllowedExit, ExitPolicy, Pipeline, SpawnOptions, Tail};
+use crate::PipeReport;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::io;
+use std::iter::FromIterator;
+use std::process::{Child, Command, ExitStatus, Output};
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Multiple commands that will be piped, where the number of commands is only known at runtime.
+///
+/// This is the dynamic counterpart of [`PipeCommand`]. Commands can be added with the [`push`]
+/// method or via the [`Extend`] and [`FromIterator`] implementations. A [`DynPipeCommand`] can also
+/// be parsed from a command line with [`str::parse`].
+///
+/// Like a [`PipeCommand`], the exit status of the pipeline respects [pipefail mode] and the allowed
+/// exit codes. The stdin of the first command is not changed, so unlike with the
+/// [stdin policy](crate::PipeCommand::set_stdin_inherit_policy) of a [`PipeCommand`], it's
+/// inherited by all methods unless it's set on the first command.
+///
+/// # Examples
+///
+/// ```rust
+/// use procmd::{cmd, DynPipeCommand};
+///
+/// # fn main() -> Result<(), std::io::Error> {
+/// let mut pipe_cmd = DynPipeCommand::new(vec![cmd!("ls")]);
+/// pipe_cmd.push(cmd!("grep", "example"));
+/// let exit_status = pipe_cmd.status()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`PipeCommand`]: crate::PipeCommand
+/// [`push`]: Self::push
+/// [pipefail mode]: Self::pipefail
+#[derive(Debug, Default)]
+pub struct DynPipeCommand {
+    /// The commands.
+    pub commands: Vec<Command>,
+    exit_policy: ExitPolicy,
+    timeouts: BTreeMap<usize, Duration>,
+    labels: BTreeMap<usize, String>,
+}
+
+impl DynPipeCommand {
+    /// The maximum number of commands of a pipeline that is parsed with [`str::parse`] or run as
+    /// a `PipelineJob`.
+    ///
+    /// This bounds the number of processes that a command line or job from an untrusted source
+    /// can spawn. Use [`parse_with_max_commands`](Self::parse_with_max_commands) or
+    /// `PipelineJob::run_with_max_commands` to use a different limit.
+    pub const DEFAULT_MAX_COMMANDS: usize = 256;
+
+    /// Creates a new [`DynPipeCommand`].
+    pub fn new(commands: Vec<Command>) -> Self {
+        Self {
+            commands,
+            exit_policy: ExitPolicy::default(),
+            timeouts: BTreeMap::new(),
+            labels: BTreeMap::new(),
+        }
+    }
+
+    /// Sets whether pipefail mode is enabled (see [`PipeCommand::pipefail`]).
+    ///
+    /// [`PipeCommand::pipefail`]: crate::PipeCommand::pipefail
+    pub fn pipefail(&mut self, enabled: bool) -> &mut Self {
+        self.exit_policy.pipefail = enabled;
+        self
+    }
+
+    /// Never considers the command at `index` to have failed, regardless of its exit status (see
+    /// [`PipeCommand::allow_failure`]).
+    ///
+    /// Since commands can be added later, `index` may be out of bounds. It only takes effect
+    /// once the pipeline contains a command at `index`.
+    ///
+    /// [`PipeCommand::allow_failure`]: crate::PipeCommand::allow_failure
+    pub fn allow_failure(&mut self, index: usize) -> &mut Self {
+        self.exit_policy.allowed.insert(index, AllowedExit::Any);
+        self
+    }
+
+    /// Considers the command at `index` to have succeeded only if it exits with one of the given
+    /// exit codes (see [`PipeCommand::allow_exit_codes`]).
+    ///
+    /// Like with [`allow_failure`], `index` may be out of bounds.
+    ///
+    /// [`PipeCommand::allow_exit_codes`]: crate::PipeCommand::allow_exit_codes
+    /// [`allow_failure`]: Self::allow_failure
+    pub fn allow_exit_codes<I>(&mut self, index: usize, codes: I) -> &mut Self
+    where
+        I: IntoIterator<Item = i32>,
+    {
+        let codes = codes.into_iter().collect();
+        self.exit_policy
+            .allowed
+            .insert(index, AllowedExit::Codes(codes));
+        self
+    }
+
+    /// Sets the time limit for the command at `index` (see [`PipeCommand::stage_timeout`]).
+    ///
+    /// The time limits are enforced by [`status`] and [`run_detailed`]: if a command is still
+    /// running `timeout` after the pipeline was spawned, all commands are killed and an error of
+    /// kind [`io::ErrorKind::TimedOut`] is returned. Like with [`allow_failure`], `index` may be
+    /// out of bounds.
+    ///
+    /// [`PipeCommand::stage_timeout`]: crate::PipeCommand::stage_timeout
+    /// [`status`]: Self::status
+    /// [`run_detailed`]: Self::run_detailed
+    /// [`allow_failure`]: Self::allow_failure
+    pub fn stage_timeout(&mut self, index: usize, timeout: Duration) -> &mut Self {
+        self.timeouts.insert(index, timeout);
+        self
+    }
+
+    /// Sets a label for the command at `index`, which is used to identify the command in a
+    /// [`PipeReport`].
+    ///
+    /// Like with [`allow_failure`], `index` may be out of bounds.
+    ///
+    /// [`allow_failure`]: Self::allow_failure
+    pub fn label<S: Into<String>>(&mut self, index: usize, label: S) -> &mut Self {
+        self.labels.insert(index, label.into());
+        self
+    }
+
+    /// Returns the time limit of every command.
+    fn timeouts(&self) -> Vec<Option<Duration>> {
+        (0..self.len())
+            .map(|i| self.timeouts.get(&i).copied())
+            .collect()
+    }
+
+    /// Creates a new [`DynPipeCommand`] from the program and arguments of every command.
+    ///
+    /// Every element of `stages` is a slice that starts with the program of a command, followed
+    /// by its arguments. Since the elements are trait objects, the program and arguments of a
+    /// command can come from values of different types (e.g. a [`&str`](str), a [`String`] and a
+    /// [`PathBuf`]) without converting them to a common type first. The values are only borrowed
+    /// for the duration of this call; they are copied into the [`Command`]s.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::DynPipeCommand;
+    /// use std::path::PathBuf;
+    ///
+    /// let path = PathBuf::from("/");
+    /// let pattern = String::from("example");
+    /// let pipe_cmd = DynPipeCommand::from_specs(&[&[&"ls", &"-a", &path], &[&"grep", &pattern]]);
+    /// assert_eq!(pipe_cmd.len(), 2);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This method panics if an element of `stages` is empty.
+    ///
+    /// [`PathBuf`]: std::path::PathBuf
+    pub fn from_specs(stages: &[&[&dyn AsRef<OsStr>]]) -> Self {
+        stages
+            .iter()
+            .map(|stage| {
+                let (program, args) = stage
+                    .split_first()
+                    .expect("every stage must contain a program");
+                let mut command = Command::new(program);
+                command.args(args);
+                command
+            })
+            .collect()
+    }
+
+    /// Parses a command line like the [`FromStr`] implementation, but with at most `max`
+    /// commands instead of [`DEFAULT_MAX_COMMANDS`].
+    ///
+    /// # Errors
+    ///
+    /// [`ParseError::TooManyCommands`] is returned if the command line contains more than `max`
+    /// commands. The other errors are the same as for [`str::parse`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::{DynPipeCommand, ParseError};
+    ///
+    /// let err = DynPipeCommand::parse_with_max_commands("ls | sort | uniq", 2).unwrap_err();
+    /// assert_eq!(err, ParseError::TooManyCommands(2));
+    /// ```
+    ///
+    /// [`DEFAULT_MAX_COMMANDS`]: Self::DEFAULT_MAX_COMMANDS
+    pub fn parse_with_max_commands(s: &str, max: usize) -> Result<Self, ParseError> {
+        let commands = parse::split(s, true)?;
+        if commands.len() > max {
+            return Err(ParseError::TooManyCommands(max));
+        }
+        Ok(commands
+            .into_iter()
+            .map(|words| {
+                let mut words = words.into_iter();
+                let mut command = Command::new(words.next().unwrap());
+                command.args(words);
+                command
+            })
+            .collect())
+    }
+
+    /// Appends a command to the end of the pipeline.
+    pub fn push(&mut self, command: Command) -> &mut Self {
+        self.commands.push(command);
+        self
+    }
+
+    /// Inserts a command at the start of the pipeline.
+    ///
+    /// The command becomes the first command, so its stdout is connected to the stdin of the
+    /// previously first command.
+    pub fn prepend_stage(&mut self, command: Command) -> &mut Self {
+        self.commands.insert(0, command);
+        self
+    }
+
+    /// Appends a command to the end of the pipeline.
+    ///
+    /// This is the same as [`push`].
+    ///
+    /// [`push`]: Self::push
+    pub fn append_stage(&mut self, command: Command) -> &mut Self {
+        self.push(command)
+    }
+
+    /// Inserts a command with the given program and arguments at the start of the pipeline (see
+    /// [`prepend_stage`]).
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::{cmd, DynPipeCommand};
+    ///
+    /// let mut pipe_cmd = DynPipeCommand::new(vec![cmd!("grep", "example")]);
+    /// pipe_cmd
+    ///     .prepend_program("cat", ["input.txt"])
+    ///     .append_program("tee", ["output.txt"]);
+    /// assert_eq!(pipe_cmd.len(), 3);
+    /// ```
+    ///
+    /// [`prepend_stage`]: Self::prepend_stage
+    pub fn prepend_program<S, I, A>(&mut self, program: S, args: I) -> &mut Self
+    where
+        S: AsRef<OsStr>,
+        I: IntoIterator<Item = A>,
+        A: AsRef<OsStr>,
+    {
+        let mut command = Command::new(program);
+        command.args(args);
+        self.prepend_stage(command)
+    }
+
+    /// Appends a command with the given program and arguments to the end of the pipeline (see
+    /// [`append_stage`]).
+    ///
+    /// [`append_stage`]: Self::append_stage
+    pub fn append_program<S, I, A>(&mut self, program: S, args: I) -> &mut Self
+    where
+        S: AsRef<OsStr>,
+        I: IntoIterator<Item = A>,
+        A: AsRef<OsStr>,
+    {
+        let mut command = Command::new(program);
+        command.args(args);
+        self.append_stage(command)
+    }
+
+    /// Appends a command to the end of the pipeline and returns the pipeline.
+    ///
+    /// This is the same as [`push`], but takes and returns the pipeline by value, so it can be
+    /// chained like `pipe` in the `duct` crate.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::{cmd, DynPipeCommand};
+    ///
+    /// let pipe_cmd = DynPipeCommand::new(vec![cmd!("ls")]).pipe(cmd!("grep", "example"));
+    /// assert_eq!(pipe_cmd.len(), 2);
+    /// ```
+    ///
+    /// [`push`]: Self::push
+    pub fn pipe(mut self, command: Command) -> Self {
+        self.commands.push(command);
+        self
+    }
+
+    /// Returns the number of commands.
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    /// Returns `true` if there are no commands.
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Spawns all commands and returns the [`Child`] of the last command.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// [`commands`]: Self::commands
+    pub fn spawn(&mut self) -> io::Result<Child> {
+        pipeline::run(&mut self.commands, &SpawnOptions::default(), |command| {
+            command.spawn()
+        })
+        .map(|(_, child)| child)
+    }
+
+    /// Returns the [`Output`] of the last command.
+    ///
+    /// The [`status`](Output::status) is the exit status of the whole pipeline, which depends on
+    /// [pipefail mode]. Note that this method still calls [`Command::spawn`] on all commands except
+    /// the last one and waits for them to exit.
+    ///
+    /// The stdout and stderr of the last command are always captured, even if they were
+    /// configured on the command, since methods like [`run_detailed`] reset them after piping
+    /// them.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// [pipefail mode]: Self::pipefail
+    /// [`run_detailed`]: Self::run_detailed
+    /// [`commands`]: Self::commands
+    pub fn output(&mut self) -> io::Result<Output> {
+        let (children, mut output) =
+            pipeline::run(&mut self.commands, &SpawnOptions::default(), |command| {
+                pipeline::with_piped_tail(command, Tail::StdoutStderr, Command::output)
+            })?;
+        let mut statuses = pipeline::wait_all(children)?;
+        statuses.push(output.status);
+        output.status = self.exit_policy.status(&statuses);
+        Ok(output)
+    }
+
+    /// Returns the [`ExitStatus`] of the whole pipeline, which depends on [pipefail mode].
+    ///
+    /// Note that this method still calls [`Command::spawn`] on all commands except the last one and
+    /// waits for them to exit.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// [pipefail mode]: Self::pipefail
+    /// [`commands`]: Self::commands
+    pub fn status(&mut self) -> io::Result<ExitStatus> {
+        let timeouts = self.timeouts();
+        if timeouts.iter().all(Option::is_none) {
+            let (children, status) =
+                pipeline::run(&mut self.commands, &SpawnOptions::default(), |command| {
+                    command.status()
+                })?;
+            let mut statuses = pipeline::wait_all(children)?;
+            statuses.push(status);
+            return Ok(self.exit_policy.status(&statuses));
+        }
+        let start = Instant::now();
+        let deadlines = pipeline::deadlines(&timeouts, start);
+        let (mut children, child) =
+            pipeline::run(&mut self.commands, &SpawnOptions::default(), Command::spawn)?;
+        children.push(child);
+        let results = pipeline::wait_within(&self.commands, children, start, &deadlines)?;
+        let statuses = results
+            .into_iter()
+            .map(|(status, _)| status)
+            .collect::<Vec<_>>();
+        Ok(self.exit_policy.status(&statuses))
+    }
+
+    /// Spawns all commands, waits for them to exit and returns a detailed report.
+    ///
+    /// This is the dynamic counterpart of [`PipeCommand::run_detailed`], which builds the report
+    /// the same way. A pipeline that failed is not an error, which can be checked with the
+    /// [`success`] field of the report.
+    ///
+    /// # Errors
+    ///
+    /// If a command can't be spawned, the commands that were already spawned are killed and an
+    /// error that contains a [`PartialReport`] is returned, like with
+    /// [`PipeCommand::run_detailed`]. An error of kind [`io::ErrorKind::TimedOut`] is returned if
+    /// a command exceeded its time limit (see [`stage_timeout`]).
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::{cmd, DynPipeCommand};
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let mut pipe_cmd = DynPipeCommand::new(vec![cmd!("sh", "-c", "echo hello; exit 3")]);
+    /// pipe_cmd.push(cmd!("cat"));
+    /// pipe_cmd.pipefail(true).label(0, "greet");
+    /// let report = pipe_cmd.run_detailed()?;
+    /// assert!(!report.success);
+    /// assert_eq!(report.stages[0].label.as_deref(), Some("greet"));
+    /// assert_eq!(report.output.stdout, b"hello\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`PipeCommand::run_detailed`]: crate::PipeCommand::run_detailed
+    /// [`success`]: PipeReport::success
+    /// [`PartialReport`]: crate::PartialReport
+    /// [`stage_timeout`]: Self::stage_timeout
+    /// [`commands`]: Self::commands
+    pub fn run_detailed(&mut self) -> io::Result<PipeReport> {
+        let timeouts = self.timeouts();
+        let labels = (0..self.len())
+            .map(|i| self.labels.get(&i).cloned())
+            .collect::<Vec<_>>();
+        let start = Instant::now();
+        let started_at = SystemTime::now();
+        let spawned = pipeline::run_partial(&mut self.commands, &SpawnOptions::default(), |c| {
+            pipeline::with_piped_tail(c, Tail::StdoutStderr, Command::spawn)
+        });
+        let reporter = pipeline::Reporter {
+            commands: &self.commands,
+            labels: &labels,
+            timeouts: &timeouts,
+            exit_policy: &self.exit_policy,
+        };
+        reporter.report(start, started_at, spawned)
+    }
+}
+
+impl Extend<Command> for DynPipeCommand {
+    fn extend<T: IntoIterator<Item = Command>>(&mut self, iter: T) {
+        self.commands.extend(iter);
+    }
+}
+
+/// Creates a pipeline that only consists of `command`.
+///
+/// More commands can then be added with [`push`](DynPipeCommand::push).
+///
+/// # Examples
+///
+/// ```rust
+/// use procmd::{cmd, DynPipeCommand};
+///
+/// let mut pipe_cmd = DynPipeCommand::from(cmd!("ls"));
+/// pipe_cmd.push(cmd!("grep", "example"));
+/// assert_eq!(pipe_cmd.len(), 2);
+/// ```
+impl From<Command> for DynPipeCommand {
+    fn from(command: Command) -> Self {
+        Self::new(vec![command])
+    }
+}
+
+impl FromIterator<Command> for DynPipeCommand {
+    fn from_iter<T: IntoIterator<Item = Command>>(iter: T) -> Self {
+        Self::new(iter.into_iter().collect())
+    }
+}
+
+/// Parses a pipeline from a command line like `ls -la | grep x`.
+///
+/// The command line is split into words similar to a POSIX shell, but no shell is invoked:
+///
+/// - Words are separated by whitespace and commands are separated by `|`.
+/// - Characters inside single quotes are taken literally.
+/// - Inside double quotes, a backslash only escapes `"`, `\`, `$`, `` ` `` and newlines.
+/// - Outside of quotes, a backslash escapes the next character.
+/// - There are no expansions (variables, globs, `~`, ...), redirections or other operators. Such
+///   characters are passed to the program as they are.
+///
+/// The first word of every command is the program, the remaining words are the arguments.
+///
+/// # Examples
+///
+/// ```rust
+/// use procmd::DynPipeCommand;
+///
+/// # fn main() -> Result<(), procmd::ParseError> {
+/// let pipe_cmd: DynPipeCommand = "ls -la | grep 'my file'".parse()?;
+/// assert_eq!(pipe_cmd.len(), 2);
+/// # Ok(())
+/// # }
+/// ```
+impl FromStr for DynPipeCommand {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_with_max_commands(s, Self::DEFAULT_MAX_COMMANDS)
+    }
+}
+
+impl Pipeline for DynPipeCommand {
+    fn commands(&self) -> &[Command] {
+        &self.commands
+    }
+
+    fn commands_mut(&mut self) -> &mut [Command] {
+        &mut self.commands
+    }
+
+    fn spawn(&mut self) -> io::Result<Child> {
+        DynPipeCommand::spawn(self)
+    }
+
+    fn output(&mut self) -> io::Result<Output> {
+        DynPipeCommand::output(self)
+    }
+
+    fn status(&mut self) -> io::Result<ExitStatus> {
+        DynPipeCommand::status(self)
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::path::PathBuf;
+use std::process::Command;
+
+/// A difference between the environment variables configured for two commands.
+///
+/// A value of `None` means that the variable is explicitly removed (see [`Command::env_remove`]).
+///
+/// This is returned by [`PipeCommand::env_diff_between`].
+///
+/// [`PipeCommand::env_diff_between`]: crate::PipeCommand::env_diff_between
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvDelta {
+    /// The variable is only configured for the first command.
+    OnlyFirst(Option<OsString>),
+    /// The variable is only configured for the second command.
+    OnlySecond(Option<OsString>),
+    /// The variable is configured differently for the two commands.
+    Changed(Option<OsString>, Option<OsString>),
+}
+
+/// Returns the environment variables configured for `command`, keyed by their normalized name
+/// (see [`normalize`]).
+fn envs(command: &Command) -> BTreeMap<OsString, (&OsStr, Option<&OsStr>)> {
+    command
+        .get_envs()
+        .map(|(key, value)| (normalize(key), (key, value)))
+        .collect()
+}
+
+/// Normalizes the name of an environment variable, so that names referring to the same variable
+/// are equal.
+///
+/// On Windows, the names of environment variables are case-insensitive (e.g. `PATH` and `Path`
+/// are the same variable), so the name is converted to uppercase. On other platforms, the name is
+/// returned unchanged.
+fn normalize(key: &OsStr) -> OsString {
+    #[cfg(windows)]
+    {
+        key.to_ascii_uppercase()
+    }
+    #[cfg(not(windows))]
+    {
+        key.to_owned()
+    }
+}
+
+/// Returns the value of the variable `key` that `command` will be spawned with.
+///
+/// This is the value configured for `command`, or the value of the current process if `command`
+/// doesn't configure the variable. Note that a [`Command`] doesn't expose whether its environment
+/// was cleared, so the value of the current process is returned in that case as well.
+pub(crate) fn effective(command: &Command, key: &OsStr) -> Option<OsString> {
+    let normalized = normalize(key);
+    match command
+        .get_envs()
+        .find(|(name, _)| normalize(name) == normalized)
+    {
+        Some((_, value)) => value.map(OsStr::to_owned),
+        None => env::var_os(key),
+    }
+}
+
+/// Returns all environment variables that `command` will be spawned with, sorted by their
+/// normalized name.
+///
+/// If `inherit` is `true`, the variables of the current process are included unless `command`
+/// removes or overrides them.
+pub(crate) fn materialize(command: &Command, inherit: bool) -> Vec<(OsString, OsString)> {
+    let mut vars = BTreeMap::new();
+    if inherit {
+        for (key, value) in env::vars_os() {
+            vars.insert(normalize(&key), (key, value));
+        }
+    }
+    for (key, value) in command.get_envs() {
+        match value {
+            Some(value) => vars.insert(normalize(key), (key.to_owned(), value.to_owned())),
+            None => vars.remove(&normalize(key)),
+        };
+    }
+    vars.into_values().collect()
+}
+
+/// Removes duplicate entries from a list of paths like `PATH`, keeping the first occurrence of
+/// every entry.
+pub(crate) fn dedup_paths(value: &OsStr) -> OsString {
+    let mut seen = HashSet::new();
+    let paths = env::split_paths(value)
+        .filter(|path| seen.insert(path.clone()))
+        .collect::<Vec<PathBuf>>();
+    // The entries were split from `value`, so none of them contains a separator.
+    env::join_paths(paths).unwrap()
+}
+
+/// Returns the differences between the environment variables configured for `a` and `b`.
+///
+/// The name of a variable is returned as it was configured for `a`, or for `b` if it's only
+/// configured for `b`.
+pub(crate) fn diff(a: &Command, b: &Command) -> Vec<(OsString, EnvDelta)> {
+    let (a, mut b) = (envs(a), envs(b));
+    let owned = |value: Option<&OsStr>| value.map(OsStr::to_owned);
+    let mut deltas = Vec::new();
+    for (normalized, (key, a_value)) in a {
+        let delta = match b.remove(&normalized) {
+            Some((_, b_value)) if b_value == a_value => continue,
+            Some((_, b_value)) => EnvDelta::Changed(owned(a_value), owned(b_value)),
+            None => EnvDelta::OnlyFirst(owned(a_value)),
+        };
+        deltas.push((normalized, key.to_owned(), delta));
+    }
+    for (normalized, (key, b_value)) in b {
+        deltas.push((
+            normalized,
+            key.to_owned(),
+            EnvDelta::OnlySecond(owned(b_value)),
+        ));
+    }
+    deltas.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+    deltas
+        .into_iter()
+        .map(|(_, key, delta)| (key, delta))
+        .collect()
+}
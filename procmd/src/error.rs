@@ -0,0 +1,122 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt;
+use std::io;
+use std::process::ExitStatus;
+use std::sync::Arc;
+
+/// A function that formats the message of a [`CommandError`] (see
+/// [`PipeCommand::error_formatter`]).
+///
+/// [`PipeCommand::error_formatter`]: crate::PipeCommand::error_formatter
+pub type ErrorFormatter = Arc<dyn Fn(&CommandError) -> String + Send + Sync>;
+
+/// An error indicating that a command failed.
+///
+/// This error is returned by the checked methods of [`PipeCommand`], e.g. [`status_checked`].
+///
+/// [`PipeCommand`]: crate::PipeCommand
+/// [`status_checked`]: crate::PipeCommand::status_checked
+#[derive(Clone)]
+pub struct CommandError {
+    /// The index of the command that failed.
+    pub index: usize,
+    /// The program of the command that failed.
+    pub program: OsString,
+    /// The exit status of the command that failed.
+    pub status: ExitStatus,
+    /// The stderr of the pipeline, if it was captured (see [`PipeCommand::run_quiet`]).
+    ///
+    /// This is empty for methods that don't capture the stderr.
+    ///
+    /// [`PipeCommand::run_quiet`]: crate::PipeCommand::run_quiet
+    pub stderr: Vec<u8>,
+    /// The formatter of the pipeline that failed.
+    pub(crate) formatter: Option<ErrorFormatter>,
+}
+
+impl fmt::Debug for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CommandError")
+            .field("index", &self.index)
+            .field("program", &self.program)
+            .field("status", &self.status)
+            .field("stderr", &self.stderr)
+            .finish()
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(formatter) = &self.formatter {
+            // The formatter receives an error without the formatter, so it can use the default
+            // message.
+            let error = CommandError {
+                formatter: None,
+                ..self.clone()
+            };
+            return f.write_str(&formatter(&error));
+        }
+        write!(
+            f,
+            "command `{}` at index {} failed with {}",
+            self.program.to_string_lossy(),
+            self.index,
+            self.status
+        )?;
+        if !self.stderr.is_empty() {
+            write!(f, ":\n{}", String::from_utf8_lossy(&self.stderr).trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for CommandError {}
+
+/// An error returned by [`PipeCommand::run_quiet`].
+///
+/// Unlike an [`io::Error`] that wraps a [`CommandError`], this can be matched on directly. It can
+/// be converted into an [`io::Error`], in which case a [`CommandError`] is wrapped with
+/// [`io::Error::other`].
+///
+/// [`PipeCommand::run_quiet`]: crate::PipeCommand::run_quiet
+#[derive(Debug)]
+pub enum QuietError {
+    /// The pipeline couldn't be run, e.g. because a command couldn't be spawned.
+    Io(io::Error),
+    /// A command failed. The error contains the captured stderr.
+    Command(CommandError),
+}
+
+impl fmt::Display for QuietError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => e.fmt(f),
+            Self::Command(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for QuietError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Command(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for QuietError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<QuietError> for io::Error {
+    fn from(e: QuietError) -> Self {
+        match e {
+            QuietError::Io(e) => e,
+            QuietError::Command(e) => io::Error::other(e),
+        }
+    }
+}
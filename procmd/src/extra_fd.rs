@@ -0,0 +1,76 @@
+use nix::fcntl::{self, FcntlArg};
+use nix::unistd;
+use std::io;
+use std::os::unix::io::{BorrowedFd, IntoRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// The extra file descriptors that are passed to the process spawned by a command.
+///
+/// All file descriptors of a command are passed by a single `pre_exec` hook, so a file descriptor
+/// that is the target of another one isn't replaced before it was duplicated. Hooks can't be
+/// removed from a [`Command`], so every call to [`pass`](Self::pass) installs a new hook for all
+/// file descriptors and deactivates the previous one.
+#[derive(Debug, Default)]
+pub(crate) struct ExtraFds {
+    fds: Vec<(Arc<OwnedFd>, RawFd)>,
+    active: Option<Arc<AtomicBool>>,
+}
+
+impl ExtraFds {
+    /// Makes `fd` available as the file descriptor `target` in the process spawned by `command`,
+    /// in addition to the file descriptors that were passed before.
+    ///
+    /// If a file descriptor was already passed as `target`, it's replaced. `fd` is moved into the
+    /// `pre_exec` hook of `command`, so it stays open in the current process for as long as
+    /// `command` exists.
+    #[allow(unsafe_code)]
+    pub(crate) fn pass(&mut self, command: &mut Command, fd: OwnedFd, target: RawFd) {
+        self.fds.retain(|(_, t)| *t != target);
+        self.fds.push((Arc::new(fd), target));
+        if let Some(previous) = self.active.take() {
+            previous.store(false, Ordering::Relaxed);
+        }
+        let active = Arc::new(AtomicBool::new(true));
+        self.active = Some(Arc::clone(&active));
+        let fds = self.fds.clone();
+        // The temporary file descriptors are stored in a buffer that is allocated up front, since
+        // the hook must not allocate.
+        let mut temps = vec![-1; fds.len()];
+        let hook = move || {
+            if active.load(Ordering::Relaxed) {
+                dup_all(&fds, &mut temps)?;
+            }
+            Ok(())
+        };
+        // SAFETY: The hook only reads an atomic and calls `fcntl` and `dup2`, which are
+        // async-signal-safe, and it doesn't allocate, so it can run between `fork` and `exec`.
+        unsafe {
+            command.pre_exec(hook);
+        }
+    }
+}
+
+/// Duplicates every file descriptor of `fds` to its target without the close-on-exec flag.
+///
+/// Every file descriptor is first duplicated (with `F_DUPFD_CLOEXEC`) above the highest target
+/// into `temps`, which must be as long as `fds`, so no target is overwritten while it's still
+/// needed as a source. The temporary file descriptors are closed on `exec`.
+#[allow(unsafe_code)]
+fn dup_all(fds: &[(Arc<OwnedFd>, RawFd)], temps: &mut [RawFd]) -> io::Result<()> {
+    let min = fds.iter().map(|(_, target)| *target).max().unwrap_or(0) + 1;
+    for ((fd, _), temp) in fds.iter().zip(temps.iter_mut()) {
+        *temp = fcntl::fcntl(&**fd, FcntlArg::F_DUPFD_CLOEXEC(min))?;
+    }
+    for ((_, target), temp) in fds.iter().zip(temps.iter()) {
+        // SAFETY: `temp` was opened above and stays open until `exec`.
+        let temp = unsafe { BorrowedFd::borrow_raw(*temp) };
+        // SAFETY: The returned file descriptor is released immediately, so it isn't closed when
+        // the `OwnedFd` is dropped and it remains open across `exec`.
+        let new = unsafe { unistd::dup2_raw(temp, *target) }?;
+        let _ = new.into_raw_fd();
+    }
+    Ok(())
+}
@@ -0,0 +1,26 @@
+use nix::sys::stat::Mode;
+use nix::unistd;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+
+/// Opens the FIFO at `path` for writing, creating it if it doesn't exist.
+///
+/// This blocks until the FIFO is opened for reading.
+pub(crate) fn open(path: &Path) -> io::Result<File> {
+    match fs::metadata(path) {
+        Ok(metadata) if !metadata.file_type().is_fifo() => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("`{}` is not a FIFO", path.display()),
+            ));
+        }
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            unistd::mkfifo(path, Mode::S_IRUSR | Mode::S_IWUSR)?;
+        }
+        Err(e) => return Err(e),
+    }
+    OpenOptions::new().write(true).open(path)
+}
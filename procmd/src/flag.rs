@@ -0,0 +1,53 @@
+use std::ffi::OsString;
+
+/// A command line flag that knows how it is passed to a program.
+///
+/// This trait can be implemented for an enum of the options of a program, so commands can be
+/// built from typed values instead of strings (see [`CommandExt::flag`]). Misspelled options are
+/// then caught by the compiler and the formatting of every option is defined in a single place.
+///
+/// # Examples
+///
+/// ```rust
+/// use procmd::{cmd, flag, CommandExt, Flag};
+/// use std::ffi::OsString;
+///
+/// enum Grep {
+///     IgnoreCase,
+///     MaxCount(u32),
+///     Regexp(String),
+/// }
+///
+/// impl Flag for Grep {
+///     fn to_args(&self) -> Vec<OsString> {
+///         match self {
+///             Self::IgnoreCase => vec!["--ignore-case".into()],
+///             Self::MaxCount(count) => vec![flag!("max-count", count).into()],
+///             Self::Regexp(pattern) => vec!["--regexp".into(), pattern.into()],
+///         }
+///     }
+/// }
+///
+/// let mut command = cmd!("grep");
+/// command
+///     .flag(Grep::IgnoreCase)
+///     .flags([Grep::MaxCount(1), Grep::Regexp("a b".to_owned())]);
+/// assert!(command
+///     .get_args()
+///     .eq(["--ignore-case", "--max-count=1", "--regexp", "a b"]));
+/// ```
+///
+/// [`CommandExt::flag`]: crate::CommandExt::flag
+pub trait Flag {
+    /// Returns the arguments that represent the flag.
+    ///
+    /// A flag can consist of any number of arguments, e.g. `--verbose`, `--jobs=4` or
+    /// `--output` followed by a path.
+    fn to_args(&self) -> Vec<OsString>;
+}
+
+impl<F: Flag + ?Sized> Flag for &F {
+    fn to_args(&self) -> Vec<OsString> {
+        (**self).to_args()
+    }
+}
@@ -0,0 +1,230 @@
+use crate::pipeline;
+use std::fmt;
+use std::io::{self, PipeReader, Read, Write};
+use std::process::{Child, ChildStdout, Command, Output, Stdio};
+use std::thread::{self, ScopedJoinHandle};
+
+/// A closure that filters the data between two commands.
+type Filter = Box<dyn FnMut(&[u8]) -> Vec<u8> + Send>;
+
+/// A stage of a [`HybridPipeline`].
+enum Stage {
+    Command(Command),
+    Filter(Filter),
+}
+
+/// A pipeline that consists of commands and Rust closures that filter the data between them.
+///
+/// A filter is a closure that is called with the data written by the previous command and
+/// returns the data that is written to the next command. Every filter runs in a separate thread
+/// that is connected to the commands with pipes, so the commands and filters run concurrently like
+/// in a regular pipeline.
+///
+/// The data is passed to a filter in chunks as it is read, so the chunk boundaries are arbitrary
+/// (e.g. a chunk might end in the middle of a line or a UTF-8 character). A filter should therefore
+/// only perform transformations that work on arbitrary chunks, or buffer the data itself.
+///
+/// # Examples
+///
+/// ```rust
+/// use procmd::{cmd, HybridPipeline};
+///
+/// # fn main() -> Result<(), std::io::Error> {
+/// let mut pipeline = HybridPipeline::new();
+/// pipeline
+///     .command(cmd!("echo", "hello"))
+///     .filter(|chunk| chunk.to_ascii_uppercase())
+///     .command(cmd!("cat"));
+/// let output = pipeline.output()?;
+/// assert_eq!(output.stdout, b"HELLO\n");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct HybridPipeline {
+    stages: Vec<Stage>,
+}
+
+impl HybridPipeline {
+    /// Creates a new, empty [`HybridPipeline`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a command to the end of the pipeline.
+    pub fn command(&mut self, command: Command) -> &mut Self {
+        self.stages.push(Stage::Command(command));
+        self
+    }
+
+    /// Appends a filter to the end of the pipeline.
+    ///
+    /// `f` is called with every chunk of data that the previous command writes, and the returned
+    /// bytes are written to the next command.
+    pub fn filter<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnMut(&[u8]) -> Vec<u8> + Send + 'static,
+    {
+        self.stages.push(Stage::Filter(Box::new(f)));
+        self
+    }
+
+    /// Returns the number of stages (commands and filters).
+    pub fn len(&self) -> usize {
+        self.stages.len()
+    }
+
+    /// Returns `true` if there are no stages.
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// Returns the [`Output`] of the last command.
+    ///
+    /// This method waits for all commands and filters to finish.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if spawning or waiting for any of the commands fails, or if a filter
+    /// fails to read or write its data (except if the next command stopped reading its input).
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the pipeline is empty, if the first or the last stage is a filter, or
+    /// if a filter panics.
+    pub fn output(&mut self) -> io::Result<Output> {
+        let is_command = |stage: Option<&Stage>| matches!(stage, Some(Stage::Command(_)));
+        assert!(
+            is_command(self.stages.first()) && is_command(self.stages.last()),
+            "the first and the last stage of a pipeline must be commands"
+        );
+        let (last, rest) = self.stages.split_last_mut().unwrap();
+        let last = match last {
+            Stage::Command(command) => command,
+            Stage::Filter(_) => unreachable!(),
+        };
+
+        thread::scope(|scope| {
+            let mut children = Vec::new();
+            let mut filters = Vec::new();
+            let mut source = None;
+            let result = (|| {
+                for stage in rest {
+                    match stage {
+                        Stage::Command(command) => {
+                            let bridged = source.is_some();
+                            if let Some(source) = source.take() {
+                                command.stdin(source);
+                            }
+                            let result = command.stdout(Stdio::piped()).spawn();
+                            if bridged {
+                                // Only reset a stdin that was set here, any other one is kept.
+                                command.stdin(Stdio::inherit());
+                            }
+                            let mut child = result?;
+                            source = Some(Source::Child(child.stdout.take().unwrap()));
+                            children.push(child);
+                        }
+                        Stage::Filter(f) => {
+                            let input = source.take().unwrap();
+                            let (reader, writer) = io::pipe()?;
+                            filters.push(scope.spawn(move || run_filter(f, input, writer)));
+                            source = Some(Source::Pipe(reader));
+                        }
+                    }
+                }
+                let bridged = source.is_some();
+                if let Some(source) = source.take() {
+                    last.stdin(source);
+                }
+                let output = last.output();
+                if bridged {
+                    last.stdin(Stdio::inherit());
+                }
+                output
+            })();
+            finish(children, filters, result)
+        })
+    }
+}
+
+/// The stdout of the previous stage.
+enum Source {
+    Child(ChildStdout),
+    Pipe(PipeReader),
+}
+
+impl From<Source> for Stdio {
+    fn from(source: Source) -> Self {
+        match source {
+            Source::Child(stdout) => stdout.into(),
+            Source::Pipe(reader) => reader.into(),
+        }
+    }
+}
+
+impl Read for Source {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Child(stdout) => stdout.read(buf),
+            Self::Pipe(reader) => reader.read(buf),
+        }
+    }
+}
+
+/// Passes the data read from `input` through `f` and writes the result to `output`.
+fn run_filter<F, W>(f: &mut F, mut input: Source, mut output: W) -> io::Result<()>
+where
+    F: FnMut(&[u8]) -> Vec<u8> + ?Sized,
+    W: Write,
+{
+    let mut buf = vec![0; pipeline::MEDIATED_CAPACITY];
+    loop {
+        let len = match input.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        match output.write_all(&f(&buf[..len])) {
+            Ok(()) => {}
+            // The next command stopped reading, like `head` does.
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Waits for `children` and `filters` and returns the output of the last command or the first
+/// error.
+fn finish(
+    children: Vec<Child>,
+    filters: Vec<ScopedJoinHandle<'_, io::Result<()>>>,
+    result: io::Result<Output>,
+) -> io::Result<Output> {
+    let output = match result {
+        Ok(output) => output,
+        Err(e) => {
+            pipeline::kill_all(children);
+            return Err(e);
+        }
+    };
+    pipeline::wait_all(children)?;
+    for filter in filters {
+        filter.join().unwrap()?;
+    }
+    Ok(output)
+}
+
+impl fmt::Debug for HybridPipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut list = f.debug_list();
+        for stage in &self.stages {
+            match stage {
+                Stage::Command(command) => list.entry(command),
+                Stage::Filter(_) => list.entry(&format_args!("<filter>")),
+            };
+        }
+        list.finish()
+    }
+}
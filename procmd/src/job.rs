@@ -0,0 +1,150 @@
+use crate::pipeline;
+use crate::{DynPipeCommand, PipeReport, PipelineOptions};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// A declarative description of the commands of a pipeline.
+///
+/// This can be deserialized with `serde`, e.g. from a configuration file, and is run as part of a
+/// [`PipelineJob`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PipelineSpec {
+    /// The commands of the pipeline, in the order in which they are piped.
+    pub stages: Vec<StageSpec>,
+}
+
+/// A declarative description of a single command of a [`PipelineSpec`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StageSpec {
+    /// The program of the command.
+    pub program: String,
+    /// The arguments of the command.
+    pub args: Vec<String>,
+    /// Environment variables that are set for the command in addition to the inherited ones.
+    pub env: BTreeMap<String, String>,
+    /// The working directory of the command, or `None` to use the one of the current process.
+    pub cwd: Option<PathBuf>,
+    /// The label that identifies the command in the report (see [`PipeCommand::label`]).
+    ///
+    /// [`PipeCommand::label`]: crate::PipeCommand::label
+    pub label: Option<String>,
+}
+
+impl StageSpec {
+    fn to_command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args).envs(&self.env);
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+        command
+    }
+}
+
+/// A pipeline that is described by data, together with the options it is run with.
+///
+/// This is the top-level API for running pipelines that are loaded from a configuration: the job
+/// can be deserialized with `serde` and [`run`] builds a fresh [`DynPipeCommand`] from the
+/// [`spec`], runs it with the [`options`] and returns a [`PipeReport`]. Since the commands are
+/// built every time, a job can be run any number of times, and the options only apply to that
+/// run. The stdin of the first command is connected to the null device, since a job isn't
+/// interactive.
+///
+/// This struct is only available with the `serde` feature.
+///
+/// # Examples
+///
+/// ```rust
+/// use procmd::PipelineJob;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let job: PipelineJob = serde_json::from_str(
+///     r#"{
+///         "spec": {
+///             "stages": [
+///                 { "program": "printf", "args": ["b\\na\\n"] },
+///                 { "program": "sort" }
+///             ]
+///         },
+///         "options": {
+///             "pipefail": true,
+///             "stage_timeouts": [[0, { "secs": 10, "nanos": 0 }]],
+///             "env": { "LC_ALL": "C" }
+///         }
+///     }"#,
+/// )?;
+/// let report = job.run()?;
+/// assert!(report.success);
+/// assert_eq!(report.output.stdout, b"a\nb\n");
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`run`]: Self::run
+/// [`spec`]: Self::spec
+/// [`options`]: Self::options
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PipelineJob {
+    /// The commands of the pipeline.
+    pub spec: PipelineSpec,
+    /// The options that the pipeline is run with (see [`PipelineOptions::apply_dyn`]).
+    #[serde(default)]
+    pub options: PipelineOptions,
+}
+
+impl PipelineJob {
+    /// Builds the pipeline, runs it and returns a detailed report.
+    ///
+    /// This is the same as [`DynPipeCommand::run_detailed`] on a pipeline that contains the
+    /// commands of the [`spec`](Self::spec), after the [`options`](Self::options) were
+    /// [applied](PipelineOptions::apply_dyn) to it. A pipeline that failed is not an error, which
+    /// can be checked with the [`success`] field of the report.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::InvalidInput`] is returned if the spec contains no
+    /// commands or more than [`DynPipeCommand::DEFAULT_MAX_COMMANDS`]. Otherwise, the errors are the same as in [`DynPipeCommand::run_detailed`], e.g.
+    /// an error of kind [`io::ErrorKind::TimedOut`] is returned if a command exceeded its time
+    /// limit.
+    ///
+    /// [`success`]: PipeReport::success
+    pub fn run(&self) -> io::Result<PipeReport> {
+        self.run_with_max_commands(DynPipeCommand::DEFAULT_MAX_COMMANDS)
+    }
+
+    /// Like [`run`](Self::run), but the spec can contain at most `max` commands instead of
+    /// [`DynPipeCommand::DEFAULT_MAX_COMMANDS`].
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::InvalidInput`] is returned if the spec contains more
+    /// than `max` commands, before any command is spawned. The other errors are the same as in
+    /// [`run`](Self::run).
+    pub fn run_with_max_commands(&self, max: usize) -> io::Result<PipeReport> {
+        if self.spec.stages.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the job contains no commands",
+            ));
+        }
+        pipeline::check_max_commands(self.spec.stages.len(), max)?;
+        let mut pipe_cmd = self
+            .spec
+            .stages
+            .iter()
+            .map(StageSpec::to_command)
+            .collect::<DynPipeCommand>();
+        pipe_cmd.commands[0].stdin(Stdio::null());
+        for (i, stage) in self.spec.stages.iter().enumerate() {
+            if let Some(label) = &stage.label {
+                pipe_cmd.label(i, label.as_str());
+            }
+        }
+        self.options.apply_dyn(&mut pipe_cmd);
+        pipe_cmd.run_detailed()
+    }
+}
@@ -0,0 +1,174 @@
+use crate::DynPipeCommand;
+use std::fmt;
+use std::io;
+use std::iter::FromIterator;
+use std::process::{Child, Command, ExitStatus, Output};
+
+/// A command of a pipeline that is only constructed when it's needed.
+///
+/// This is useful if computing the arguments of a command is expensive and the command might not
+/// be part of the pipeline, e.g. because it depends on a condition (see [`when`]).
+///
+/// A [`LazyPipeline`] can be built from [`LazyStage`]s with [`FromIterator`] or [`Extend`]. The
+/// closures are only called when the pipeline is run, right before its commands are spawned, in
+/// the order of the stages. The closure of a disabled stage is never called and the stage is left
+/// out of the pipeline.
+///
+/// # Examples
+///
+/// ```rust
+/// use procmd::{cmd, LazyPipeline, LazyStage};
+///
+/// # fn main() -> Result<(), std::io::Error> {
+/// let filter: Option<&str> = None;
+/// let pipeline = vec![
+///     LazyStage::new(|| cmd!("ls")),
+///     LazyStage::new(|| cmd!("grep", filter.unwrap())).when(filter.is_some()),
+///     LazyStage::new(|| cmd!("wc", "-l")),
+/// ]
+/// .into_iter()
+/// .collect::<LazyPipeline>();
+/// assert_eq!(pipeline.len(), 2);
+/// let exit_status = pipeline.status()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`when`]: Self::when
+pub struct LazyStage<'a> {
+    f: Box<dyn FnOnce() -> Command + 'a>,
+    enabled: bool,
+}
+
+impl<'a> LazyStage<'a> {
+    /// Creates a new [`LazyStage`] that constructs the command with `f`.
+    pub fn new<F>(f: F) -> Self
+    where
+        F: FnOnce() -> Command + 'a,
+    {
+        Self {
+            f: Box::new(f),
+            enabled: true,
+        }
+    }
+
+    /// Sets whether the stage is part of the pipeline.
+    pub fn when(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    /// Constructs the command, or returns `None` if the stage is disabled.
+    pub fn evaluate(self) -> Option<Command> {
+        if self.enabled {
+            Some((self.f)())
+        } else {
+            None
+        }
+    }
+}
+
+impl From<Command> for LazyStage<'_> {
+    fn from(command: Command) -> Self {
+        Self::new(move || command)
+    }
+}
+
+impl fmt::Debug for LazyStage<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LazyStage")
+            .field("enabled", &self.enabled)
+            .finish()
+    }
+}
+
+/// A pipeline of [`LazyStage`]s whose commands are constructed when it's run.
+///
+/// Since the closure of a stage can only be called once, the methods that run the pipeline take
+/// it by value. They construct the commands of the enabled stages (see [`into_pipe_command`]) and
+/// run them like a [`DynPipeCommand`].
+///
+/// See [`LazyStage`] for an example.
+///
+/// [`into_pipe_command`]: Self::into_pipe_command
+#[derive(Debug, Default)]
+pub struct LazyPipeline<'a> {
+    stages: Vec<LazyStage<'a>>,
+}
+
+impl<'a> LazyPipeline<'a> {
+    /// Creates a new, empty [`LazyPipeline`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a stage to the end of the pipeline.
+    pub fn push(&mut self, stage: LazyStage<'a>) -> &mut Self {
+        self.stages.push(stage);
+        self
+    }
+
+    /// Returns the number of enabled stages, without constructing their commands.
+    pub fn len(&self) -> usize {
+        self.stages.iter().filter(|stage| stage.enabled).count()
+    }
+
+    /// Returns `true` if there are no enabled stages.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Constructs the commands of the enabled stages and returns them as a [`DynPipeCommand`].
+    ///
+    /// This calls the closure of every enabled stage, in the order of the stages.
+    pub fn into_pipe_command(self) -> DynPipeCommand {
+        self.stages
+            .into_iter()
+            .filter_map(LazyStage::evaluate)
+            .collect()
+    }
+
+    /// Constructs the commands, spawns them and returns the [`Child`] of the last command (see
+    /// [`DynPipeCommand::spawn`]).
+    ///
+    /// # Panics
+    ///
+    /// This method panics if there are no enabled stages.
+    pub fn spawn(self) -> io::Result<Child> {
+        self.into_pipe_command().spawn()
+    }
+
+    /// Constructs the commands, runs them and returns the [`Output`] of the last command (see
+    /// [`DynPipeCommand::output`]).
+    ///
+    /// # Panics
+    ///
+    /// This method panics if there are no enabled stages.
+    pub fn output(self) -> io::Result<Output> {
+        self.into_pipe_command().output()
+    }
+
+    /// Constructs the commands, runs them and returns the [`ExitStatus`] of the last command (see
+    /// [`DynPipeCommand::status`]).
+    ///
+    /// # Panics
+    ///
+    /// This method panics if there are no enabled stages.
+    pub fn status(self) -> io::Result<ExitStatus> {
+        self.into_pipe_command().status()
+    }
+}
+
+impl<'a> Extend<LazyStage<'a>> for LazyPipeline<'a> {
+    fn extend<T: IntoIterator<Item = LazyStage<'a>>>(&mut self, iter: T) {
+        self.stages.extend(iter);
+    }
+}
+
+impl<'a> FromIterator<LazyStage<'a>> for LazyPipeline<'a> {
+    fn from_iter<T: IntoIterator<Item = LazyStage<'a>>>(iter: T) -> Self {
+        Self {
+            stages: iter.into_iter().collect(),
+        }
+    }
+}
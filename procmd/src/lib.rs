@@ -1,7 +1,43 @@
 //! A helper library for building commands.
 //!
 //! The [`cmd!`] macro can be used to generate [`std::process::Command`] (or [`PipeCommand`]). Refer
-//! to its documentation for more information.
+//! to its documentation for more information. Commands with subcommands can also be built with
+//! the [`Cmd`] builder.
+//!
+//! Pipelines whose number of commands is only known at runtime can be built with
+//! [`DynPipeCommand`]. Both pipeline types implement the [`Pipeline`] trait.
+//!
+//! Pipelines that filter the data between commands with Rust closures can be built with
+//! [`HybridPipeline`].
+//!
+//! For quick one-off pipelines in scripts, [`sh`] runs a command line and returns its output.
+//! The outputs of two pipelines can be merged into one stream with [`merge_outputs`].
+//!
+//! # Migrating from `duct`
+//!
+//! Most concepts of the `duct` crate have a direct equivalent:
+//!
+//! | `duct`                 | `procmd`                                                    |
+//! |------------------------|-------------------------------------------------------------|
+//! | `cmd!("ls", "-a")`     | `cmd!("ls", "-a")`                                          |
+//! | `a.pipe(b)`            | `cmd!(a => b)` or [`DynPipeCommand::pipe`]                  |
+//! | `expression.read()`    | [`PipeCommand::read`]                                       |
+//! | `expression.run()`     | [`PipeCommand::run`]                                        |
+//! | `expression.unchecked()` | [`PipeCommand::status`] and [`PipeCommand::output`], which don't check the exit status |
+//!
+//! Note that `cmd!` generates a [`std::process::Command`] if there are no pipes, which doesn't
+//! have these methods. Such a command can be wrapped with [`PipeCommand::new`].
+//!
+//! # Crate features
+//!
+//! - `async`: Enables `PipeCommand::spawn_async` for reading the output with Tokio.
+//! - `encoding`: Enables `PipeCommand::output_string_with` and `PipeCommand::input_encoded` for
+//!   text in encodings other than UTF-8. The `encoding_rs` crate is re-exported.
+//! - `serde`: Enables `PipeCommand::output_ndjson` for reading newline-delimited JSON and
+//!   `PipelineJob` for running pipelines that are loaded from a configuration.
+//! - `testing`: Enables helpers for testing code that uses this crate, like
+//!   `PipeCommand::set_spawn_delay`, `PipeCommand::set_load_source` and
+//!   `PipeCommand::assert_stdout_eq`.
 //!
 //! # Examples
 //!
@@ -16,12 +52,81 @@
 //! # }
 //! ```
 
-#![feature(min_const_generics)]
-#![forbid(unsafe_code)]
+// Unsafe code is only allowed in the Unix modules that need it to install `pre_exec` hooks or to
+// call libc functions: `extra_fd`, `nice` and `umask`.
+#![deny(unsafe_code)]
 #![warn(rust_2018_idioms, missing_docs, missing_debug_implementations)]
 
-use std::io;
-use std::process::{Child, Command, ExitStatus, Output, Stdio};
+mod ansi;
+#[cfg(feature = "async")]
+mod async_reader;
+mod boundary;
+mod builder;
+mod command_ext;
+mod dyn_pipe_command;
+mod env;
+mod error;
+#[cfg(unix)]
+mod extra_fd;
+#[cfg(unix)]
+mod fifo;
+mod flag;
+mod hybrid;
+#[cfg(feature = "serde")]
+mod job;
+mod lazy;
+mod merge;
+#[cfg(feature = "serde")]
+mod ndjson;
+#[cfg(unix)]
+mod nice;
+mod options;
+mod parse;
+mod pipe_child;
+mod pipe_command;
+mod pipeline;
+mod plan;
+mod reader;
+mod report;
+mod resolve;
+mod sh;
+mod shell;
+mod stall;
+mod stdin;
+#[cfg(feature = "testing")]
+mod testing;
+mod throttle;
+#[cfg(unix)]
+mod umask;
+
+#[cfg(feature = "async")]
+pub use async_reader::AsyncPipeReader;
+pub use builder::Cmd;
+pub use command_ext::CommandExt;
+pub use dyn_pipe_command::DynPipeCommand;
+#[cfg(feature = "encoding")]
+pub use encoding_rs;
+pub use env::EnvDelta;
+pub use error::{CommandError, ErrorFormatter, QuietError};
+pub use flag::Flag;
+pub use hybrid::HybridPipeline;
+#[cfg(feature = "serde")]
+pub use job::{PipelineJob, PipelineSpec, StageSpec};
+pub use lazy::{LazyPipeline, LazyStage};
+pub use merge::merge_outputs;
+#[cfg(feature = "serde")]
+pub use ndjson::JsonLines;
+pub use options::PipelineOptions;
+pub use parse::ParseError;
+pub use pipe_child::PipeChild;
+pub use pipe_command::PipeCommand;
+pub use pipeline::Pipeline;
+pub use plan::ExecPlan;
+pub use reader::ReaderConfig;
+pub use report::{FullOutput, PartialOutput, PartialReport, PipeReport, StageReport};
+pub use sh::sh;
+pub use shell::ScriptFlavor;
+pub use stdin::StdinPolicy;
 
 /// A macro for building commands.
 ///
@@ -87,117 +192,253 @@ use std::process::{Child, Command, ExitStatus, Output, Stdio};
 ///     },
 /// ]);
 /// ```
-pub use procmd_macro::cmd;
-
-/// Multiple commands that will be piped.
 ///
-/// A [`PipeCommand`] can be created by either using the [`new`] method or by using the [`cmd!`]
-/// macro.
+/// # Clauses
 ///
-/// # Examples
+/// Additional configuration can be passed to a command with clauses of the form `name: value`
+/// after the program. In a piped command, a clause only applies to the command it is attached to.
+///
+/// ## `args`
 ///
-/// Using the [`new`] method combined with [`Command::new`] and a simple use of the [`cmd!`] macro:
+/// The `args` clause adds multiple arguments at once (see [`Command::args`]). The value can be any
+/// expression that implements `IntoIterator`, e.g. an array, a slice or a [`Vec`], and all
+/// elements must implement `AsRef<OsStr>`. The arguments are added at the position of the clause,
+/// so it can be mixed with other arguments and used multiple times.
+///
+/// ### Example
+///
+/// The invocation:
 ///
 /// ```rust
-/// use procmd::{cmd, PipeCommand};
-/// use std::process::Command;
+/// # use procmd::cmd;
+/// # let path = "/";
+/// let cmd = cmd!("ls", args: ["-a", "-l"], path);
+/// ```
+///
+/// expands to:
 ///
-/// # fn main() -> Result<(), std::io::Error> {
-/// let mut pipe_cmd = PipeCommand::new([Command::new("ls"), cmd!("grep", "example")]);
-/// let child = pipe_cmd.spawn()?;
-/// # Ok(())
-/// # }
+/// ```rust
+/// # let path = "/";
+/// let cmd = {
+///     let mut cmd = ::std::process::Command::new("ls");
+///     cmd.args(["-a", "-l"]);
+///     cmd.arg(path);
+///     cmd
+/// };
 /// ```
 ///
-/// Using the [`cmd!`] macro with the `=>` token to generate a [`PipeCommand`] and calling the
-/// [`status`] method to get the exit status:
+/// [`Command::args`]: std::process::Command::args
+///
+/// ## `cwd`
+///
+/// The `cwd` clause sets the working directory of a command (see [`Command::current_dir`]). The
+/// value can be any expression that implements `AsRef<Path>`. Commands without this clause inherit
+/// the working directory of the current process.
+///
+/// ### Example
+///
+/// The invocation:
 ///
 /// ```rust
-/// use procmd::cmd;
+/// # use procmd::cmd;
+/// # let repo = "/";
+/// let pipe_cmd = cmd!("git", "log", cwd: repo => "wc", "-l");
+/// ```
+///
+/// expands to:
 ///
-/// # fn main() -> Result<(), std::io::Error> {
-/// let mut pipe_cmd = cmd!("ls" => "grep", "example");
-/// let exit_status = pipe_cmd.status()?;
-/// # Ok(())
-/// # }
+/// ```rust
+/// # let repo = "/";
+/// let pipe_cmd = ::procmd::PipeCommand::new([
+///     {
+///         let mut cmd = ::std::process::Command::new("git");
+///         cmd.arg("log");
+///         cmd.current_dir(repo);
+///         cmd
+///     },
+///     {
+///         let mut cmd = ::std::process::Command::new("wc");
+///         cmd.arg("-l");
+///         cmd
+///     },
+/// ]);
 /// ```
 ///
-/// [`new`]: Self::new
-/// [`status`]: Self::status
-/// [`cmd!`]: crate::cmd
-#[derive(Debug)]
-pub struct PipeCommand<const N: usize> {
-    /// The commands.
-    pub commands: [Command; N],
-}
-
-impl<const N: usize> PipeCommand<N> {
-    /// Creates a new [`PipeCommand`].
-    pub fn new(commands: [Command; N]) -> Self {
-        Self { commands }
-    }
-
-    /// Spawns all commands except the last one and calls `f` on the last command.
-    ///
-    /// # Panics
-    ///
-    /// This method panics if [`commands`] is empty.
-    ///
-    /// [`commands`]: Self::commands
-    fn run<F, U>(&mut self, f: F) -> io::Result<U>
-    where
-        F: Fn(&mut Command) -> io::Result<U>,
-    {
-        let mut child: Option<Child> = None;
-        let commands_len = self.commands.len();
-        for (i, command) in self.commands[..commands_len - 1].iter_mut().enumerate() {
-            if let Some(child) = child {
-                command.stdin(child.stdout.unwrap());
-            }
-            if i == commands_len - 1 {
-                break;
-            } else {
-                command.stdout(Stdio::piped());
-                child = Some(command.spawn()?);
-            }
-        }
-        f(&mut self.commands[commands_len - 1])
-    }
-
-    /// Spawns all commands and returns the [`Child`] of the last command.
-    ///
-    /// # Panics
-    ///
-    /// This method panics if [`commands`] is empty.
-    ///
-    /// [`commands`]: Self::commands
-    pub fn spawn(&mut self) -> io::Result<Child> {
-        self.run(|command| command.spawn())
-    }
-
-    /// Returns the [`Output`] of the last command.
-    ///
-    /// Note that this method still calls [`Command::spawn`] on all commands except the last one.
-    ///
-    /// # Panics
-    ///
-    /// This method panics if [`commands`] is empty.
-    ///
-    /// [`commands`]: Self::commands
-    pub fn output(&mut self) -> io::Result<Output> {
-        self.run(|command| command.output())
-    }
+/// [`Command::current_dir`]: std::process::Command::current_dir
+///
+/// ## `env`
+///
+/// The `env` clause sets environment variables of a command (see [`Command::envs`]). The value can
+/// be any expression that implements `IntoIterator<Item = (K, V)>`, where `K` and `V` implement
+/// `AsRef<OsStr>`, e.g. an array or a [`Vec`] of pairs. The variables are set in order, so if a
+/// variable is set multiple times (in one clause or in multiple clauses), the last value wins. Use
+/// an ordered container like an array, a [`Vec`] or a [`BTreeMap`] if this matters, since the
+/// iteration order of a [`HashMap`] is unspecified.
+///
+/// ### Example
+///
+/// The invocation:
+///
+/// ```rust
+/// # use procmd::cmd;
+/// let cmd = cmd!("make", env: [("CC", "clang"), ("CFLAGS", "-O2")]);
+/// ```
+///
+/// expands to:
+///
+/// ```rust
+/// let cmd = {
+///     let mut cmd = ::std::process::Command::new("make");
+///     cmd.envs([("CC", "clang"), ("CFLAGS", "-O2")]);
+///     cmd
+/// };
+/// ```
+///
+/// [`Command::envs`]: std::process::Command::envs
+/// [`BTreeMap`]: std::collections::BTreeMap
+/// [`HashMap`]: std::collections::HashMap
+///
+/// ## `ok_codes`
+///
+/// The `ok_codes` clause sets the exit codes that are considered a success for a command (see
+/// [`PipeCommand::allow_exit_codes`]). The value can be any expression that implements
+/// `IntoIterator<Item = i32>`. The exit codes are respected by [`PipeCommand::status_checked`] and
+/// [`PipeCommand::require_success`] (and by [`PipeCommand::status`] in pipefail mode).
+///
+/// Since a [`std::process::Command`] can't store allowed exit codes, using this clause always
+/// generates a [`PipeCommand`], even if only a single command is passed.
+///
+/// ### Example
+///
+/// The invocation:
+///
+/// ```rust
+/// # use procmd::cmd;
+/// let pipe_cmd = cmd!("grep", "test", ok_codes: [0, 1]);
+/// ```
+///
+/// expands to:
+///
+/// ```rust
+/// let pipe_cmd = {
+///     let mut pipe_cmd = ::procmd::PipeCommand::new([{
+///         let mut cmd = ::std::process::Command::new("grep");
+///         cmd.arg("test");
+///         cmd
+///     }]);
+///     pipe_cmd.allow_exit_codes(0, [0, 1]);
+///     pipe_cmd
+/// };
+/// ```
+///
+/// ## `stdin`
+///
+/// The `stdin` clause sets the stdin of a command (see [`Command::stdin`]). The value can be any
+/// expression that implements `Into<Stdio>`, e.g. [`Stdio::null`] or a [`File`]. In a piped
+/// command, the stdin of every command except the first one is the stdout of the previous command,
+/// so this clause can only be used on the first command. Using it on another command fails to
+/// compile.
+///
+/// ### Example
+///
+/// The invocation:
+///
+/// ```rust
+/// # use procmd::cmd;
+/// use std::process::Stdio;
+///
+/// let pipe_cmd = cmd!("cat", stdin: Stdio::null() => "wc", "-c");
+/// ```
+///
+/// expands to:
+///
+/// ```rust
+/// use std::process::Stdio;
+///
+/// let pipe_cmd = ::procmd::PipeCommand::new([
+///     {
+///         let mut cmd = ::std::process::Command::new("cat");
+///         cmd.stdin(Stdio::null());
+///         cmd
+///     },
+///     {
+///         let mut cmd = ::std::process::Command::new("wc");
+///         cmd.arg("-c");
+///         cmd
+///     },
+/// ]);
+/// ```
+///
+/// [`Command::stdin`]: std::process::Command::stdin
+/// [`Stdio::null`]: std::process::Stdio::null
+/// [`File`]: std::fs::File
+///
+/// ## `timeout`
+///
+/// The `timeout` clause sets the time limit of a command (see [`PipeCommand::stage_timeout`]). The
+/// value can be any expression of type [`Duration`]. In a piped command, every command can have
+/// its own time limit.
+///
+/// Like the `ok_codes` clause, using this clause always generates a [`PipeCommand`].
+///
+/// ### Example
+///
+/// The invocation:
+///
+/// ```rust
+/// # use procmd::cmd;
+/// use std::time::Duration;
+///
+/// let pipe_cmd = cmd!("slow-tool", timeout: Duration::from_secs(5) => "wc", "-l");
+/// ```
+///
+/// expands to:
+///
+/// ```rust
+/// use std::time::Duration;
+///
+/// let pipe_cmd = {
+///     let mut pipe_cmd = ::procmd::PipeCommand::new([
+///         {
+///             let mut cmd = ::std::process::Command::new("slow-tool");
+///             cmd
+///         },
+///         {
+///             let mut cmd = ::std::process::Command::new("wc");
+///             cmd.arg("-l");
+///             cmd
+///         },
+///     ]);
+///     pipe_cmd.stage_timeout(0, Duration::from_secs(5));
+///     pipe_cmd
+/// };
+/// ```
+///
+/// [`Duration`]: std::time::Duration
+pub use procmd_macro::cmd;
 
-    /// Returns the [`ExitStatus`] of the last command.
-    ///
-    /// Note that this method still calls [`Command::spawn`] on all commands except the last one.
-    ///
-    /// # Panics
-    ///
-    /// This method panics if [`commands`] is empty.
-    ///
-    /// [`commands`]: Self::commands
-    pub fn status(&mut self) -> io::Result<ExitStatus> {
-        self.run(|command| command.status())
-    }
+/// A macro for building long command line flags.
+///
+/// `flag!(name)` expands to `--name` and `flag!(name, value)` expands to `--name=value`, where
+/// `name` and `value` can be any expressions that implement [`Display`](std::fmt::Display). The
+/// result is a [`String`], so it is always passed to a command as a single argument, even if the
+/// value contains spaces or other characters that are special to a shell.
+///
+/// # Examples
+///
+/// ```rust
+/// use procmd::{cmd, flag};
+///
+/// let feature = "serde";
+/// let cmd = cmd!("cargo", "build", flag!("features", feature), flag!("release"));
+/// assert!(cmd.get_args().eq(["build", "--features=serde", "--release"]));
+/// ```
+#[macro_export]
+macro_rules! flag {
+    ($name:expr $(,)?) => {
+        ::std::format!("--{}", $name)
+    };
+    ($name:expr, $value:expr $(,)?) => {
+        ::std::format!("--{}={}", $name, $value)
+    };
 }
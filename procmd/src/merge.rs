@@ -0,0 +1,107 @@
+use crate::pipeline::{self, Pipeline, SpawnOptions, Tail};
+use std::io::{self, BufRead, BufReader, PipeWriter, Write};
+use std::process::{Child, ChildStdout, Command};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Runs two pipelines concurrently and merges the stdout of their last commands into one reader.
+///
+/// This is the equivalent of `{ p1 & p2; } | consumer` in a shell. The stdout of both pipelines is
+/// forwarded line by line while it is produced, so the lines of the two pipelines are interleaved
+/// in the order in which they are written. The order between the pipelines is best-effort and can
+/// differ between runs, but every line is forwarded as a whole. A final line without a trailing
+/// newline is forwarded as well, so it can be directly followed by a line of the other pipeline.
+///
+/// The returned reader reaches the end of the file when both pipelines have closed their stdout
+/// and all of their commands have exited. The commands of each pipeline are waited for in a
+/// background thread, and errors while forwarding (other than the reader being dropped) are
+/// ignored. The commands are spawned like the commands of a [`DynPipeCommand`], so options of a
+/// [`PipeCommand`] (like [`PipeCommand::head_stdin_from_reader`]) are not applied.
+///
+/// While the pipelines are spawned, the stdout of their last commands is set to a pipe. Since the
+/// stdio configuration of a [`Command`] can't be read back, it is reset to the default
+/// ([`Stdio::inherit`]) afterwards.
+///
+/// # Errors
+///
+/// An error of kind [`io::ErrorKind::InvalidInput`] is returned if either pipeline is empty.
+/// Otherwise, an error is returned if spawning either pipeline fails. If the second pipeline fails
+/// to spawn, the commands of the first pipeline are killed.
+///
+/// # Examples
+///
+/// ```rust
+/// use procmd::{cmd, merge_outputs};
+/// use std::io::Read;
+///
+/// # fn main() -> Result<(), std::io::Error> {
+/// let (mut a, mut b) = (cmd!("echo", "a" => "cat"), cmd!("echo", "b" => "cat"));
+/// let mut merged = merge_outputs(&mut a, &mut b)?;
+/// let mut output = String::new();
+/// merged.read_to_string(&mut output)?;
+/// assert!(output == "a\nb\n" || output == "b\na\n");
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`DynPipeCommand`]: crate::DynPipeCommand
+/// [`PipeCommand`]: crate::PipeCommand
+/// [`PipeCommand::head_stdin_from_reader`]: crate::PipeCommand::head_stdin_from_reader
+/// [`Stdio::inherit`]: std::process::Stdio::inherit
+pub fn merge_outputs(p1: &mut dyn Pipeline, p2: &mut dyn Pipeline) -> io::Result<impl io::Read> {
+    if p1.is_empty() || p2.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "cannot merge the output of an empty pipeline",
+        ));
+    }
+    let (reader, writer) = io::pipe()?;
+    let writer = Arc::new(Mutex::new(writer));
+    let first = spawn_piped(p1)?;
+    let second = match spawn_piped(p2) {
+        Ok(second) => second,
+        Err(e) => {
+            pipeline::kill_all(first.0);
+            return Err(e);
+        }
+    };
+    for (children, stdout) in [first, second] {
+        let writer = Arc::clone(&writer);
+        thread::spawn(move || forward(children, stdout, &writer));
+    }
+    Ok(reader)
+}
+
+/// Spawns all commands of `p` with the stdout of the last command piped.
+///
+/// The returned children include the child of the last command.
+fn spawn_piped(p: &mut dyn Pipeline) -> io::Result<(Vec<Child>, ChildStdout)> {
+    let (mut children, mut child) =
+        pipeline::run(p.commands_mut(), &SpawnOptions::default(), |command| {
+            pipeline::with_piped_tail(command, Tail::Stdout, Command::spawn)
+        })?;
+    let stdout = child.stdout.take().unwrap();
+    children.push(child);
+    Ok((children, stdout))
+}
+
+/// Forwards `stdout` to `writer` line by line and waits for `children`.
+fn forward(children: Vec<Child>, stdout: ChildStdout, writer: &Mutex<PipeWriter>) {
+    let mut stdout = BufReader::new(stdout);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match stdout.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) => {
+                if writer.lock().unwrap().write_all(&line).is_err() {
+                    break;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => break,
+        }
+    }
+    drop(stdout);
+    let _ = pipeline::wait_all(children);
+}
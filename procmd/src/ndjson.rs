@@ -0,0 +1,76 @@
+use crate::pipeline;
+use crate::ReaderConfig;
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::io::{self, BufRead, BufReader};
+use std::marker::PhantomData;
+use std::mem;
+use std::process::{Child, ChildStdout};
+
+/// An iterator over the newline-delimited JSON values written to the stdout of a pipeline.
+///
+/// This struct is created by [`PipeCommand::output_ndjson`]. Refer to its documentation for more
+/// information.
+///
+/// [`PipeCommand::output_ndjson`]: crate::PipeCommand::output_ndjson
+pub struct JsonLines<T> {
+    reader: BufReader<ChildStdout>,
+    children: Vec<Child>,
+    done: bool,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> JsonLines<T> {
+    pub(crate) fn new(stdout: ChildStdout, children: Vec<Child>, config: &ReaderConfig) -> Self {
+        Self {
+            reader: BufReader::with_capacity(config.capacity, stdout),
+            children,
+            done: false,
+            marker: PhantomData,
+        }
+    }
+
+    /// Waits for all commands to exit once the end of the output is reached.
+    fn finish(&mut self) -> Option<io::Result<T>> {
+        self.done = true;
+        pipeline::wait_all(mem::take(&mut self.children))
+            .err()
+            .map(Err)
+    }
+}
+
+impl<T: DeserializeOwned> Iterator for JsonLines<T> {
+    type Item = io::Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut line = Vec::new();
+        while !self.done {
+            line.clear();
+            match self.reader.read_until(b'\n', &mut line) {
+                Ok(0) => return self.finish(),
+                Ok(_) if line.iter().all(u8::is_ascii_whitespace) => continue,
+                Ok(_) => return Some(serde_json::from_slice(&line).map_err(io::Error::from)),
+                Err(e) => {
+                    self.finish();
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<T> Drop for JsonLines<T> {
+    fn drop(&mut self) {
+        pipeline::kill_all(mem::take(&mut self.children));
+    }
+}
+
+impl<T> fmt::Debug for JsonLines<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JsonLines")
+            .field("children", &self.children)
+            .field("done", &self.done)
+            .finish()
+    }
+}
@@ -0,0 +1,70 @@
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// The value that is added to the nice value of the commands if the load is too high.
+pub(crate) const INCREMENT: i32 = 10;
+
+/// Lowers the priority of the commands of a pipeline if the system load is above a threshold.
+#[derive(Debug, Clone)]
+pub(crate) struct AutoNice {
+    /// The load above which the priority is lowered.
+    pub(crate) threshold: f64,
+    /// The function that returns the current load.
+    pub(crate) load: fn() -> io::Result<f64>,
+    /// Whether the commands that are spawned next lower their priority.
+    active: Arc<AtomicBool>,
+}
+
+impl AutoNice {
+    pub(crate) fn new(threshold: f64) -> Self {
+        Self {
+            threshold,
+            load: system_load,
+            active: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Makes `command` lower its priority when it's spawned while the load is too high.
+    #[allow(unsafe_code)]
+    pub(crate) fn install(&self, command: &mut Command) {
+        let active = Arc::clone(&self.active);
+        let hook = move || {
+            if active.load(Ordering::Relaxed) {
+                // SAFETY: `nice` only changes the priority of the calling process. A failure is
+                // ignored, since the command can still run with its current priority.
+                unsafe {
+                    libc::nice(INCREMENT);
+                }
+            }
+            Ok(())
+        };
+        // SAFETY: The hook only reads an atomic and calls `nice`, which is async-signal-safe, and
+        // it doesn't allocate, so it can run between `fork` and `exec`.
+        unsafe {
+            command.pre_exec(hook);
+        }
+    }
+
+    /// Queries the load and decides whether the commands that are spawned next lower their
+    /// priority.
+    ///
+    /// If the load can't be queried, the priority is not lowered.
+    pub(crate) fn update(&self) {
+        let high = (self.load)().is_ok_and(|load| load > self.threshold);
+        self.active.store(high, Ordering::Relaxed);
+    }
+}
+
+/// Returns the load average of the system over the last minute.
+#[allow(unsafe_code)]
+fn system_load() -> io::Result<f64> {
+    let mut load = [0.0];
+    // SAFETY: `load` has space for the one sample that is requested.
+    if unsafe { libc::getloadavg(load.as_mut_ptr(), 1) } != 1 {
+        return Err(io::Error::other("failed to query the load average"));
+    }
+    Ok(load[0])
+}
@@ -0,0 +1,122 @@
+use crate::{DynPipeCommand, ErrorFormatter, PipeCommand};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Options that can be applied to multiple pipelines.
+///
+/// This can be used to share the same failure policy, time limits and environment between many
+/// [`PipeCommand`]s and [`DynPipeCommand`]s instead of configuring each of them separately. The indices of the commands
+/// refer to the position in the pipeline. Indices that are out of bounds for a pipeline are
+/// ignored by [`apply`], so the same options can be applied to pipelines with a different number
+/// of commands. With the `serde` feature, the options can be serialized and deserialized, e.g. as
+/// part of a `PipelineJob`.
+///
+/// # Examples
+///
+/// ```rust
+/// use procmd::{cmd, PipelineOptions};
+///
+/// let options = PipelineOptions {
+///     pipefail: true,
+///     allowed_exit_codes: vec![(1, vec![0, 1])],
+///     ..PipelineOptions::default()
+/// };
+/// let mut a = cmd!("ls" => "grep", "example");
+/// let mut b = cmd!("ls", "-a" => "grep", "test" => "wc", "-l");
+/// options.apply(&mut a);
+/// options.apply(&mut b);
+/// ```
+///
+/// [`apply`]: Self::apply
+#[derive(Clone, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct PipelineOptions {
+    /// Whether pipefail mode is enabled (see [`PipeCommand::pipefail`]).
+    pub pipefail: bool,
+    /// The indices of the commands that are allowed to fail (see [`PipeCommand::allow_failure`]).
+    pub allowed_failures: Vec<usize>,
+    /// The exit codes that are considered a success for the command at the given index (see
+    /// [`PipeCommand::allow_exit_codes`]).
+    pub allowed_exit_codes: Vec<(usize, Vec<i32>)>,
+    /// The time limits of the commands at the given indices (see [`PipeCommand::stage_timeout`]).
+    pub stage_timeouts: Vec<(usize, Duration)>,
+    /// Environment variables that are set on every command (see [`PipeCommand::env_all`]).
+    pub env: BTreeMap<String, String>,
+    /// The function that formats the message of the errors returned if a pipeline failed (see
+    /// [`PipeCommand::error_formatter`]).
+    ///
+    /// This is not serialized, since a function can't be represented as data.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub error_formatter: Option<ErrorFormatter>,
+}
+
+impl fmt::Debug for PipelineOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PipelineOptions")
+            .field("pipefail", &self.pipefail)
+            .field("allowed_failures", &self.allowed_failures)
+            .field("allowed_exit_codes", &self.allowed_exit_codes)
+            .field("stage_timeouts", &self.stage_timeouts)
+            .field("env", &self.env)
+            .field(
+                "error_formatter",
+                &self.error_formatter.as_ref().map(|_| ..),
+            )
+            .finish()
+    }
+}
+
+impl PipelineOptions {
+    /// Applies the options to `pipeline`.
+    ///
+    /// Indices that are out of bounds for `pipeline` are silently ignored, so the same options can
+    /// be applied to pipelines with a different number of commands, e.g. options that allow the
+    /// command at index 2 to fail can also be applied to a pipeline with 2 commands.
+    pub fn apply<const N: usize>(&self, pipeline: &mut PipeCommand<N>) {
+        pipeline.pipefail(self.pipefail);
+        for &index in self.allowed_failures.iter().filter(|i| **i < N) {
+            pipeline.allow_failure(index);
+        }
+        for (index, codes) in self.allowed_exit_codes.iter().filter(|(i, _)| *i < N) {
+            pipeline.allow_exit_codes(*index, codes.iter().copied());
+        }
+        for &(index, timeout) in self.stage_timeouts.iter().filter(|(i, _)| *i < N) {
+            pipeline.stage_timeout(index, timeout);
+        }
+        pipeline.env_all(&self.env);
+        if let Some(formatter) = &self.error_formatter {
+            let formatter = Arc::clone(formatter);
+            pipeline.error_formatter(move |e| formatter(e));
+        }
+    }
+
+    /// Applies the options to a [`DynPipeCommand`].
+    ///
+    /// Unlike with [`apply`](Self::apply), indices that are out of bounds are kept, so they take
+    /// effect once the pipeline contains a command at that index. The environment variables are
+    /// set on the commands that the pipeline contains when this method is called. The error
+    /// formatter is not used, since a [`DynPipeCommand`] doesn't return a [`CommandError`].
+    ///
+    /// [`CommandError`]: crate::CommandError
+    pub fn apply_dyn(&self, pipeline: &mut DynPipeCommand) {
+        pipeline.pipefail(self.pipefail);
+        for &index in &self.allowed_failures {
+            pipeline.allow_failure(index);
+        }
+        for (index, codes) in &self.allowed_exit_codes {
+            pipeline.allow_exit_codes(*index, codes.iter().copied());
+        }
+        for &(index, timeout) in &self.stage_timeouts {
+            pipeline.stage_timeout(index, timeout);
+        }
+        for command in &mut pipeline.commands {
+            command.envs(&self.env);
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::error::Error;
+use std::fmt;
+
+/// An error that can occur when parsing a command line.
+///
+/// This error is returned by the [`FromStr`] implementation of [`DynPipeCommand`].
+///
+/// [`FromStr`]: std::str::FromStr
+/// [`DynPipeCommand`]: crate::DynPipeCommand
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// A single or double quote was not closed.
+    UnterminatedQuote,
+    /// The input ended with a backslash that doesn't escape anything.
+    TrailingBackslash,
+    /// A command of a pipeline contains no words (e.g. `ls |`).
+    EmptyCommand,
+    /// The pipeline contains more commands than the given maximum (see
+    /// [`DynPipeCommand::parse_with_max_commands`]).
+    ///
+    /// [`DynPipeCommand::parse_with_max_commands`]: crate::DynPipeCommand::parse_with_max_commands
+    TooManyCommands(usize),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnterminatedQuote => f.write_str("unterminated quote"),
+            Self::TrailingBackslash => f.write_str("trailing backslash"),
+            Self::EmptyCommand => f.write_str("empty command"),
+            Self::TooManyCommands(max) => write!(f, "more than {} commands", max),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// Splits `s` into words like a POSIX shell would, without performing any expansions.
+///
+/// If `pipes` is `true`, an unquoted `|` ends the current command and the words of every command
+/// are returned separately. Otherwise `|` is treated like any other character and a single
+/// command is returned.
+pub(crate) fn split(s: &str, pipes: bool) -> Result<Vec<Vec<String>>, ParseError> {
+    let mut commands = Vec::new();
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or(ParseError::UnterminatedQuote)? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or(ParseError::UnterminatedQuote)? {
+                        '"' => break,
+                        '\\' => match chars.next().ok_or(ParseError::UnterminatedQuote)? {
+                            c @ ('"' | '\\' | '$' | '`') => word.push(c),
+                            '\n' => {}
+                            c => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => match chars.next().ok_or(ParseError::TrailingBackslash)? {
+                '\n' => {}
+                c => word.get_or_insert_with(String::new).push(c),
+            },
+            '|' if pipes => {
+                words.extend(word.take());
+                if words.is_empty() {
+                    return Err(ParseError::EmptyCommand);
+                }
+                commands.push(std::mem::take(&mut words));
+            }
+            c if c.is_whitespace() => words.extend(word.take()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    if pipes && words.is_empty() {
+        return Err(ParseError::EmptyCommand);
+    }
+    commands.push(words);
+    Ok(commands)
+}
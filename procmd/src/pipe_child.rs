@@ -0,0 +1,126 @@
+use crate::pipeline;
+use std::convert::TryInto;
+use std::io;
+use std::process::{Child, ChildStdin, ChildStdout, ExitStatus};
+
+/// The running commands of a spawned [`PipeCommand`].
+///
+/// This is returned by [`PipeCommand::spawn_all`]. In contrast to [`PipeCommand::spawn`], it gives
+/// access to every command of the pipeline, not just the last one.
+///
+/// Like [`Child`], dropping a [`PipeChild`] doesn't kill or wait for the commands.
+///
+/// [`PipeCommand`]: crate::PipeCommand
+/// [`PipeCommand::spawn_all`]: crate::PipeCommand::spawn_all
+/// [`PipeCommand::spawn`]: crate::PipeCommand::spawn
+#[derive(Debug)]
+pub struct PipeChild<const N: usize> {
+    /// The children, in the order of the commands.
+    pub children: [Child; N],
+}
+
+impl<const N: usize> PipeChild<N> {
+    /// Takes the stdin of the first command, if it was piped (see [`PipeCommand::spawn_duplex`]).
+    ///
+    /// [`PipeCommand::spawn_duplex`]: crate::PipeCommand::spawn_duplex
+    ///
+    /// # Panics
+    ///
+    /// This method panics if there are no commands.
+    pub fn take_stdin(&mut self) -> Option<ChildStdin> {
+        self.children[0].stdin.take()
+    }
+
+    /// Takes the stdout of the last command, if it was piped (see [`PipeCommand::spawn_duplex`]).
+    ///
+    /// [`PipeCommand::spawn_duplex`]: crate::PipeCommand::spawn_duplex
+    ///
+    /// # Panics
+    ///
+    /// This method panics if there are no commands.
+    pub fn take_stdout(&mut self) -> Option<ChildStdout> {
+        self.children[N - 1].stdout.take()
+    }
+
+    /// Returns the exit status of every command if all of them have exited, without blocking.
+    ///
+    /// `None` means that at least one command is still running. This calls [`Child::try_wait`] on
+    /// every command, so it can be called repeatedly, e.g. from an event loop.
+    pub fn try_wait_all(&mut self) -> io::Result<Option<[ExitStatus; N]>> {
+        let mut statuses = Vec::with_capacity(N);
+        for child in &mut self.children {
+            match child.try_wait()? {
+                Some(status) => statuses.push(status),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(statuses.try_into().unwrap()))
+    }
+
+    /// Asks all commands to terminate gracefully.
+    ///
+    /// On Unix, `SIGTERM` is sent to every command that is still running. On other platforms, the
+    /// commands are killed (see [`kill`]). The commands are not waited for.
+    ///
+    /// This is meant to be called from a signal handler, e.g. to forward Ctrl-C to a pipeline. No
+    /// handler is installed by this crate; install one with a crate like `ctrlc` or
+    /// `signal-hook` and share the [`PipeChild`] with it, e.g. in an `Arc<Mutex<_>>`. Note that
+    /// the handler can't lock the mutex while another thread holds it in [`wait_all`], so
+    /// [`try_wait_all`] should be used to wait in that case.
+    ///
+    /// # Errors
+    ///
+    /// All commands are signaled even if signaling one of them fails. The first error is
+    /// returned.
+    ///
+    /// [`kill`]: Self::kill
+    /// [`wait_all`]: Self::wait_all
+    /// [`try_wait_all`]: Self::try_wait_all
+    pub fn terminate(&mut self) -> io::Result<()> {
+        self.children
+            .iter_mut()
+            .map(pipeline::terminate)
+            .fold(Ok(()), Result::and)
+    }
+
+    /// Kills all commands (see [`Child::kill`]).
+    ///
+    /// The commands are not waited for. Like [`terminate`], all commands are killed even if
+    /// killing one of them fails and the first error is returned.
+    ///
+    /// [`terminate`]: Self::terminate
+    pub fn kill(&mut self) -> io::Result<()> {
+        self.children
+            .iter_mut()
+            .map(Child::kill)
+            .fold(Ok(()), Result::and)
+    }
+
+    /// Waits for all commands to exit and returns their exit statuses.
+    pub fn wait_all(&mut self) -> io::Result<[ExitStatus; N]> {
+        let statuses = self
+            .children
+            .iter_mut()
+            .map(Child::wait)
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(statuses.try_into().unwrap())
+    }
+}
+
+/// Kills the commands that are still running and waits for all commands when it is dropped.
+pub(crate) struct Reaper<'a, const N: usize>(pub(crate) &'a mut PipeChild<N>);
+
+impl<const N: usize> Drop for Reaper<'_, N> {
+    fn drop(&mut self) {
+        for child in &mut self.0.children {
+            // Close the pipes of the current process, so commands that are blocked on them exit.
+            drop(child.stdin.take());
+            drop(child.stdout.take());
+            drop(child.stderr.take());
+            if let Ok(None) = child.try_wait() {
+                let _ = child.kill();
+            }
+            let _ = child.wait();
+        }
+    }
+}
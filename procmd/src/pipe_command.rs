@@ -0,0 +1,3496 @@
+#[cfg(feature = "async")]
+use crate::async_reader::{AsyncPipeReader, KillOnDrop};
+use crate::boundary::Hook;
+use crate::error::{CommandError, ErrorFormatter, QuietError};
+#[cfg(unix)]
+use crate::extra_fd::ExtraFds;
+#[cfg(feature = "serde")]
+use crate::ndjson::JsonLines;
+use crate::pipe_child::Reaper;
+use crate::pipeline::{self, AllowedExit, ExitPolicy, Pipeline, SpawnOptions, Tail};
+use crate::stall::{StallHandler, StallReader};
+use crate::throttle::ThrottledReader;
+use crate::{
+    ansi, env, resolve, shell, EnvDelta, ExecPlan, FullOutput, PartialOutput, PipeChild,
+    PipeReport, ReaderConfig, ScriptFlavor, StdinPolicy,
+};
+#[cfg(unix)]
+use crate::{fifo, nice, umask};
+use std::convert::TryInto;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::process::{Child, ChildStderr, ChildStdout, Command, ExitStatus, Output, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Multiple commands that will be piped.
+///
+/// A [`PipeCommand`] can be created by either using the [`new`] method or by using the [`cmd!`]
+/// macro.
+///
+/// # Examples
+///
+/// Using the [`new`] method combined with [`Command::new`] and a simple use of the [`cmd!`] macro:
+///
+/// ```rust
+/// use procmd::{cmd, PipeCommand};
+/// use std::process::Command;
+///
+/// # fn main() -> Result<(), std::io::Error> {
+/// let mut pipe_cmd = PipeCommand::new([Command::new("ls"), cmd!("grep", "example")]);
+/// let child = pipe_cmd.spawn()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Using the [`cmd!`] macro with the `=>` token to generate a [`PipeCommand`] and calling the
+/// [`status`] method to get the exit status:
+///
+/// ```rust
+/// use procmd::cmd;
+///
+/// # fn main() -> Result<(), std::io::Error> {
+/// let mut pipe_cmd = cmd!("ls" => "grep", "example");
+/// let exit_status = pipe_cmd.status()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Enabling pipefail mode and allowing `grep` to exit with code 1 if it finds no match:
+///
+/// ```rust
+/// use procmd::cmd;
+///
+/// # fn main() -> Result<(), std::io::Error> {
+/// let mut pipe_cmd = cmd!("ls" => "grep", "example" => "wc", "-l");
+/// pipe_cmd.pipefail(true).allow_exit_codes(1, [0, 1]);
+/// let exit_status = pipe_cmd.status()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Standard streams
+///
+/// The stdout of every command except the last one is connected to the stdin of the next command.
+/// Unless configured otherwise, the stderr of every command is inherited from the parent process.
+/// The stdin of the first command is inherited by the methods that return running commands, like
+/// [`spawn`], and connected to the null device by all other methods (see
+/// [`set_stdin_inherit_policy`]). [`spawn`] and [`status`] also let the last command inherit
+/// stdout, so a pipeline that ends in a pager like `less` works interactively: the pager reads the
+/// piped data from its stdin and the keystrokes from the controlling terminal.
+///
+/// ```rust,no_run
+/// use procmd::cmd;
+///
+/// # fn main() -> Result<(), std::io::Error> {
+/// let mut pipe_cmd = cmd!("ls", "-la" => "less");
+/// let exit_status = pipe_cmd.status()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Number of commands
+///
+/// Methods that only make sense for pipelines with multiple commands fail to compile if `N` is too
+/// small. [`intermediate_stderr`] requires `N >= 2`.
+///
+/// [`new`]: Self::new
+/// [`intermediate_stderr`]: Self::intermediate_stderr
+/// [`spawn`]: Self::spawn
+/// [`set_stdin_inherit_policy`]: Self::set_stdin_inherit_policy
+/// [`status`]: Self::status
+/// [`cmd!`]: crate::cmd
+#[derive(Debug)]
+pub struct PipeCommand<const N: usize> {
+    /// The commands.
+    pub commands: [Command; N],
+    exit_policy: ExitPolicy,
+    error_on_failure: bool,
+    inherit_env: bool,
+    stdin_policy: Option<StdinPolicy>,
+    head_stdin_set: bool,
+    timeouts: [Option<Duration>; N],
+    labels: [Option<String>; N],
+    reader_config: ReaderConfig,
+    spawn_options: SpawnOptions,
+    head_stdin: Option<HeadStdin>,
+    tail_stdout: Option<TailStdout>,
+    stall_handler: Option<StallHandler>,
+    throttle: Option<u64>,
+    stderr_sink: Option<Arc<Mutex<Vec<u8>>>>,
+    stderr_forwarder: Option<thread::JoinHandle<()>>,
+    #[cfg(unix)]
+    extra_fds: [ExtraFds; N],
+    tracer: Option<Tracer>,
+    error_formatter: Option<ErrorFormat>,
+}
+
+/// The formatter of the errors of a pipeline (see [`PipeCommand::error_formatter`]).
+struct ErrorFormat(ErrorFormatter);
+
+impl fmt::Debug for ErrorFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ErrorFormat").finish_non_exhaustive()
+    }
+}
+
+/// The reader that is copied to the stdin of the first command the next time the pipeline is
+/// spawned.
+struct HeadStdin(Box<dyn Read + Send>);
+
+/// How a method that spawns the pipeline sets up the stdin of the first command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HeadMode {
+    /// The [stdin policy](PipeCommand::set_stdin_inherit_policy) is applied, which defaults to
+    /// [`StdinPolicy::Null`].
+    Batch,
+    /// The [stdin policy](PipeCommand::set_stdin_inherit_policy) is applied, which defaults to
+    /// [`StdinPolicy::Inherit`].
+    Interactive,
+    /// The stdin is piped, regardless of how it is configured.
+    Piped,
+}
+
+impl fmt::Debug for HeadStdin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("HeadStdin").finish_non_exhaustive()
+    }
+}
+
+/// The writer that the pipeline is written to before it is spawned.
+struct Tracer(Box<dyn Write + Send>);
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Tracer").finish_non_exhaustive()
+    }
+}
+
+/// The stdout of the last command that is set up every time the pipeline is spawned.
+#[derive(Debug)]
+enum TailStdout {
+    #[cfg(unix)]
+    Fifo(PathBuf),
+    File(File),
+}
+
+/// Compile-time assertions about the number of commands of a pipeline.
+struct StageCount<const N: usize>;
+
+impl<const N: usize> StageCount<N> {
+    /// Fails to compile if the pipeline contains fewer than two commands.
+    const AT_LEAST_TWO: () = assert!(N >= 2, "the pipeline must contain at least 2 commands");
+}
+
+impl<const N: usize> PipeCommand<N> {
+    /// Creates a new [`PipeCommand`].
+    pub fn new(commands: [Command; N]) -> Self {
+        Self {
+            commands,
+            exit_policy: ExitPolicy::default(),
+            error_on_failure: false,
+            inherit_env: true,
+            stdin_policy: None,
+            head_stdin_set: false,
+            timeouts: [None; N],
+            labels: std::array::from_fn(|_| None),
+            reader_config: ReaderConfig::default(),
+            spawn_options: SpawnOptions::default(),
+            head_stdin: None,
+            tail_stdout: None,
+            stall_handler: None,
+            throttle: None,
+            stderr_sink: None,
+            stderr_forwarder: None,
+            #[cfg(unix)]
+            extra_fds: std::array::from_fn(|_| ExtraFds::default()),
+            tracer: None,
+            error_formatter: None,
+        }
+    }
+
+    /// Sets whether pipefail mode is enabled.
+    ///
+    /// By default, the exit status of the pipeline is the exit status of the last command. In
+    /// pipefail mode, the exit status of the pipeline is the exit status of the last (rightmost)
+    /// command that failed, or the exit status of the last command if no command failed. This
+    /// matches the behaviour of `set -o pipefail` in shells.
+    ///
+    /// Whether a command failed can be configured with [`allow_failure`] and
+    /// [`allow_exit_codes`]. On Unix, a command other than the last one that was killed by
+    /// `SIGPIPE` is never considered to have failed, since this only means that a later command
+    /// stopped reading its input early, as `head` does in `yes | head -n 5`.
+    ///
+    /// [`allow_failure`]: Self::allow_failure
+    /// [`allow_exit_codes`]: Self::allow_exit_codes
+    pub fn pipefail(&mut self, enabled: bool) -> &mut Self {
+        self.exit_policy.pipefail = enabled;
+        self
+    }
+
+    /// Sets whether [`output`], [`output_tee`] and [`status`] return an error if the pipeline
+    /// failed.
+    ///
+    /// By default, these methods behave like the methods of [`Command`] and return the exit
+    /// status even if it indicates a failure. If `enabled` is `true`, they return an error if the
+    /// pipeline failed (as determined by [`require_success`], so [pipefail mode] and the allowed
+    /// exit codes are respected), so `?` can be used without checking the exit status. The
+    /// [`CommandError`] is converted into an [`io::Error`] with [`io::Error::other`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::{cmd, CommandError};
+    ///
+    /// let mut pipe_cmd = cmd!("echo", "test" => "false");
+    /// pipe_cmd.error_on_failure(true);
+    /// let err = pipe_cmd.output().unwrap_err();
+    /// let err = err.get_ref().unwrap().downcast_ref::<CommandError>().unwrap();
+    /// assert_eq!(err.index, 1);
+    /// ```
+    ///
+    /// [`output`]: Self::output
+    /// [`output_tee`]: Self::output_tee
+    /// [`status`]: Self::status
+    /// [`require_success`]: Self::require_success
+    /// [pipefail mode]: Self::pipefail
+    pub fn error_on_failure(&mut self, enabled: bool) -> &mut Self {
+        self.error_on_failure = enabled;
+        self
+    }
+
+    /// Sets a function that formats the message of the errors returned if the pipeline failed.
+    ///
+    /// The function is called whenever a [`CommandError`] of this pipeline is displayed (e.g.
+    /// when it's printed or converted with [`ToString`]), including the errors that are
+    /// converted into an [`io::Error`]. The error itself is not changed, so its fields can still
+    /// be inspected. The error that is passed to the function displays the default message, so
+    /// the function can extend it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// let mut pipe_cmd = cmd!("echo", "test" => "false");
+    /// pipe_cmd.error_formatter(|e| format!("deploy step failed: {}", e));
+    /// let err = pipe_cmd.status_checked().unwrap_err();
+    /// assert_eq!(
+    ///     err.to_string(),
+    ///     "deploy step failed: command `false` at index 1 failed with exit status: 1"
+    /// );
+    /// ```
+    pub fn error_formatter<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&CommandError) -> String + Send + Sync + 'static,
+    {
+        self.error_formatter = Some(ErrorFormat(Arc::new(f)));
+        self
+    }
+
+    /// Returns the exit status of the pipeline, or an error if the pipeline failed and
+    /// [`error_on_failure`](Self::error_on_failure) is enabled.
+    fn checked_status(&self, statuses: &[ExitStatus; N]) -> io::Result<ExitStatus> {
+        if self.error_on_failure {
+            self.require_success(statuses).map_err(io::Error::other)?;
+        }
+        Ok(self.pipeline_status(statuses))
+    }
+
+    /// Never considers the command at `index` to have failed, regardless of its exit status.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `index` is out of bounds.
+    pub fn allow_failure(&mut self, index: usize) -> &mut Self {
+        assert!(
+            index < N,
+            "index out of bounds: the len is {} but the index is {}",
+            N,
+            index
+        );
+        self.exit_policy.allowed.insert(index, AllowedExit::Any);
+        self
+    }
+
+    /// Considers the command at `index` to have succeeded only if it exits with one of the given
+    /// exit codes.
+    ///
+    /// Note that `0` has to be included in `codes` if it should still be considered a success.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `index` is out of bounds.
+    pub fn allow_exit_codes<I>(&mut self, index: usize, codes: I) -> &mut Self
+    where
+        I: IntoIterator<Item = i32>,
+    {
+        assert!(
+            index < N,
+            "index out of bounds: the len is {} but the index is {}",
+            N,
+            index
+        );
+        let codes = codes.into_iter().collect();
+        self.exit_policy
+            .allowed
+            .insert(index, AllowedExit::Codes(codes));
+        self
+    }
+
+    /// Sets the time limit for the command at `index`.
+    ///
+    /// If the command is still running `timeout` after the pipeline was spawned, all commands are
+    /// killed. Every command can have its own time limit, and the `timeout` clause of [`cmd!`]
+    /// sets it where the command is defined.
+    ///
+    /// The time limits are enforced by the methods that only wait for the exit statuses, i.e.
+    /// [`status`], [`statuses`], [`status_checked`], [`exit_code`], [`status_timeout`] and
+    /// [`status_timeout_graceful`], as well as by [`run_detailed`]. The other methods ignore them. If a command exceeds its time
+    /// limit, [`status_timeout`] and [`status_timeout_graceful`] behave as if their own `limit`
+    /// was exceeded, i.e. the commands are asked to terminate and `None` is returned, so the
+    /// effective limit of a command is the shorter of the two. The other methods kill all
+    /// commands and return an error of kind [`io::ErrorKind::TimedOut`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    /// use std::time::Duration;
+    ///
+    /// let mut pipe_cmd = cmd!("sleep", "10" => "cat");
+    /// pipe_cmd.stage_timeout(0, Duration::from_millis(100));
+    /// let err = pipe_cmd.status().unwrap_err();
+    /// assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `index` is out of bounds.
+    ///
+    /// [`cmd!`]: crate::cmd
+    /// [`status`]: Self::status
+    /// [`statuses`]: Self::statuses
+    /// [`status_checked`]: Self::status_checked
+    /// [`exit_code`]: Self::exit_code
+    /// [`status_timeout`]: Self::status_timeout
+    /// [`status_timeout_graceful`]: Self::status_timeout_graceful
+    /// [`run_detailed`]: Self::run_detailed
+    pub fn stage_timeout(&mut self, index: usize, timeout: Duration) -> &mut Self {
+        self.timeouts[index] = Some(timeout);
+        self
+    }
+
+    /// Returns the deadline of every command if it was spawned at `start`.
+    fn deadlines(&self, start: Instant) -> [Option<Instant>; N] {
+        self.timeouts
+            .map(|timeout| timeout.map(|timeout| start + timeout))
+    }
+
+    /// Sets a label for the command at `index`.
+    ///
+    /// The label is used to identify the command in a [`PipeReport`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `index` is out of bounds.
+    pub fn label<S: Into<String>>(&mut self, index: usize, label: S) -> &mut Self {
+        self.labels[index] = Some(label.into());
+        self
+    }
+
+    /// Returns `true` if `status` is considered a success for the command at `index`.
+    fn is_success(&self, index: usize, status: ExitStatus) -> bool {
+        self.exit_policy.is_success(index, N, status)
+    }
+
+    /// Returns an error if the pipeline failed given the exit status of each command.
+    ///
+    /// If [pipefail mode] is enabled, the last command that failed is reported. Otherwise only the
+    /// last command is checked. Exit codes allowed with [`allow_failure`] and
+    /// [`allow_exit_codes`] are not considered a failure.
+    ///
+    /// [pipefail mode]: Self::pipefail
+    /// [`allow_failure`]: Self::allow_failure
+    /// [`allow_exit_codes`]: Self::allow_exit_codes
+    pub fn require_success(&self, statuses: &[ExitStatus; N]) -> Result<(), CommandError> {
+        self.require_success_from(statuses, if self.exit_policy.pipefail { 0 } else { N - 1 })
+    }
+
+    /// Returns an error if any of the commands from index `first` on failed.
+    fn require_success_from(
+        &self,
+        statuses: &[ExitStatus; N],
+        first: usize,
+    ) -> Result<(), CommandError> {
+        match self.exit_policy.failure_from(statuses, first) {
+            Some(index) => Err(CommandError {
+                index,
+                program: self.commands[index].get_program().to_owned(),
+                status: statuses[index],
+                stderr: Vec::new(),
+                formatter: self.error_formatter.as_ref().map(|f| Arc::clone(&f.0)),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the exit status of the whole pipeline given the exit status of each command.
+    fn pipeline_status(&self, statuses: &[ExitStatus; N]) -> ExitStatus {
+        self.exit_policy.status(statuses)
+    }
+
+    /// Spawns all commands except the last one and calls `f` on the last command (see
+    /// [`pipeline::run`]), applying the configuration of the pipeline to the commands.
+    ///
+    /// The stdin of the first command is set up for a non-interactive pipeline.
+    fn spawn_with<F, U>(&mut self, f: F) -> io::Result<(Vec<Child>, U)>
+    where
+        F: FnOnce(&mut Command) -> io::Result<U>,
+    {
+        self.spawn_with_head(HeadMode::Batch, f)
+    }
+
+    /// Like [`spawn_with`](Self::spawn_with), but the stdin of the first command is set up as
+    /// described by `head`.
+    fn spawn_with_head<F, U>(&mut self, head: HeadMode, f: F) -> io::Result<(Vec<Child>, U)>
+    where
+        F: FnOnce(&mut Command) -> io::Result<U>,
+    {
+        self.spawn_with_partial(head, f).map_err(|(e, children)| {
+            pipeline::kill_all(children);
+            e
+        })
+    }
+
+    /// Like [`spawn_with_head`](Self::spawn_with_head), but if spawning fails, the children that
+    /// were already spawned are returned together with the error (see [`pipeline::run_partial`]).
+    fn spawn_with_partial<F, U>(
+        &mut self,
+        head: HeadMode,
+        f: F,
+    ) -> Result<(Vec<Child>, U), (io::Error, Vec<Child>)>
+    where
+        F: FnOnce(&mut Command) -> io::Result<U>,
+    {
+        let no_children = |e| (e, Vec::new());
+        if let Some(Tracer(writer)) = &mut self.tracer {
+            // Like with `set -x`, a failure to write the trace doesn't stop the pipeline.
+            let _ = writeln!(writer, "+ {}", shell::render(&self.commands));
+            let _ = writer.flush();
+        }
+        let head_stdin = match head {
+            HeadMode::Piped => None,
+            _ => self.head_stdin.take(),
+        };
+        let stdin = if let Some(HeadStdin(mut reader)) = head_stdin {
+            let (pipe_reader, mut pipe_writer) = io::pipe().map_err(no_children)?;
+            thread::spawn(move || {
+                // A broken pipe only means that the first command stopped reading its stdin.
+                let _ = io::copy(&mut reader, &mut pipe_writer);
+            });
+            Some(Stdio::from(pipe_reader))
+        } else {
+            match head {
+                HeadMode::Piped => Some(Stdio::piped()),
+                _ if self.head_stdin_set => None,
+                HeadMode::Batch => Some(self.stdin_policy.unwrap_or(StdinPolicy::Null).stdio()),
+                HeadMode::Interactive => {
+                    Some(self.stdin_policy.unwrap_or(StdinPolicy::Inherit).stdio())
+                }
+            }
+        };
+        let has_stdin = stdin.is_some();
+        if let Some(stdin) = stdin {
+            // A stdin that was set explicitly is replaced, so it doesn't outlive this spawn.
+            self.head_stdin_set = false;
+            self.commands[0].stdin(stdin);
+        }
+        if let Some(tail_stdout) = &self.tail_stdout {
+            let file = match tail_stdout {
+                #[cfg(unix)]
+                TailStdout::Fifo(path) => fifo::open(path).map_err(no_children)?,
+                TailStdout::File(file) => file.try_clone().map_err(no_children)?,
+            };
+            self.commands[N - 1].stdout(file);
+        }
+        if let Some(sink) = self.stderr_sink.clone() {
+            let (mut pipe_reader, pipe_writer) = io::pipe().map_err(no_children)?;
+            for command in &mut self.commands {
+                command.stderr(pipe_writer.try_clone().map_err(no_children)?);
+            }
+            // Finish appending the stderr of the previous run, so it isn't mixed with this one.
+            self.join_stderr();
+            let mut buf = vec![0; self.reader_config.capacity];
+            self.stderr_forwarder = Some(thread::spawn(move || loop {
+                match pipe_reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(len) => sink
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .extend_from_slice(&buf[..len]),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(_) => break,
+                }
+            }));
+        }
+        let result = pipeline::run_partial(&mut self.commands, &self.spawn_options, f);
+        if self.stderr_sink.is_some() {
+            // Close the copies of the write end, so the forwarding thread receives an EOF.
+            for command in &mut self.commands {
+                command.stderr(Stdio::inherit());
+            }
+        }
+        if has_stdin {
+            // Close the read end of a pipe, so the copy stops if the first command exits early.
+            self.commands[0].stdin(Stdio::inherit());
+        }
+        if self.tail_stdout.is_some() {
+            // Close the copy of the file, e.g. so the reader of a FIFO receives an EOF.
+            self.commands[N - 1].stdout(Stdio::inherit());
+        }
+        result
+    }
+
+    /// Waits for all `children` to exit (see [`pipeline::wait_all`]) and for their stderr to be
+    /// appended to the [sink](Self::stderr_all_to).
+    fn wait_all(&mut self, children: Vec<Child>) -> io::Result<Vec<ExitStatus>> {
+        let statuses = pipeline::wait_all(children);
+        self.join_stderr();
+        statuses
+    }
+
+    /// Waits until the stderr of the last run was appended to the [sink](Self::stderr_all_to).
+    ///
+    /// This returns once all commands closed their stderr, which usually happens when they exit.
+    fn join_stderr(&mut self) {
+        if let Some(forwarder) = self.stderr_forwarder.take() {
+            let _ = forwarder.join();
+        }
+    }
+
+    /// Spawns all commands with the stdout of the last command piped.
+    ///
+    /// The returned children include the child of the last command.
+    fn spawn_piped(&mut self) -> io::Result<(Vec<Child>, ChildStdout)> {
+        self.spawn_piped_with_head(HeadMode::Batch)
+    }
+
+    /// Like [`spawn_piped`](Self::spawn_piped), but the stdin of the first command is set up as
+    /// described by `head`.
+    fn spawn_piped_with_head(&mut self, head: HeadMode) -> io::Result<(Vec<Child>, ChildStdout)> {
+        let (mut children, mut child) = self.spawn_with_head(head, |command| {
+            pipeline::with_piped_tail(command, Tail::Stdout, Command::spawn)
+        })?;
+        let stdout = child.stdout.take().unwrap();
+        children.push(child);
+        Ok((children, stdout))
+    }
+
+    /// Returns a reader for the stdout of the last command that respects the configured
+    /// [stall handler](Self::on_stall) and the [throttle](Self::throttle).
+    fn tail_reader(&mut self, stdout: ChildStdout) -> Box<dyn Read + '_> {
+        let reader: Box<dyn Read + '_> = match &mut self.stall_handler {
+            Some(handler) => Box::new(StallReader::new(
+                stdout,
+                self.reader_config.capacity,
+                handler,
+            )),
+            None => Box::new(stdout),
+        };
+        match self.throttle {
+            Some(bytes_per_sec) => Box::new(ThrottledReader::new(reader, bytes_per_sec)),
+            None => reader,
+        }
+    }
+
+    /// Returns the number of commands.
+    pub fn len(&self) -> usize {
+        N
+    }
+
+    /// Returns `true` if there are no commands.
+    pub fn is_empty(&self) -> bool {
+        N == 0
+    }
+
+    /// Spawns all commands and returns the [`Child`] of the last command.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// [`commands`]: Self::commands
+    pub fn spawn(&mut self) -> io::Result<Child> {
+        self.spawn_with_head(HeadMode::Interactive, Command::spawn)
+            .map(|(_, child)| child)
+    }
+
+    /// Spawns all commands and returns the [`Child`] of every command.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let mut pipe_child = cmd!("ls" => "grep", "example").spawn_all()?;
+    /// let statuses = loop {
+    ///     if let Some(statuses) = pipe_child.try_wait_all()? {
+    ///         break statuses;
+    ///     }
+    ///     // Do something else in the meantime.
+    /// };
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// [`commands`]: Self::commands
+    pub fn spawn_all(&mut self) -> io::Result<PipeChild<N>> {
+        let (mut children, child) = self.spawn_with_head(HeadMode::Interactive, Command::spawn)?;
+        children.push(child);
+        Ok(PipeChild {
+            children: children.try_into().unwrap(),
+        })
+    }
+
+    /// Spawns all commands, calls `f` with the running commands and cleans them up afterwards.
+    ///
+    /// When `f` returns (or panics), the commands that are still running are killed and all
+    /// commands are waited for, so no child process outlives the scope and no zombie processes are
+    /// left behind. The pipes of the [`Child`] handles that `f` didn't take are closed before. To
+    /// let the commands finish, wait for them in `f`, e.g. with [`PipeChild::wait_all`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    /// use std::time::Duration;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let mut pipe_cmd = cmd!("sleep", "10" => "cat");
+    /// let exited = pipe_cmd.scope(|pipe_child| {
+    ///     std::thread::sleep(Duration::from_millis(100));
+    ///     pipe_child.try_wait_all()
+    /// })??;
+    /// assert!(exited.is_none());
+    /// // The commands were killed when the scope ended.
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// [`commands`]: Self::commands
+    pub fn scope<F, R>(&mut self, f: F) -> io::Result<R>
+    where
+        F: FnOnce(&mut PipeChild<N>) -> R,
+    {
+        let mut pipe_child = self.spawn_all()?;
+        let reaper = Reaper(&mut pipe_child);
+        Ok(f(reaper.0))
+    }
+
+    /// Spawns all commands with the stdin of the first command and the stdout of the last command
+    /// piped.
+    ///
+    /// This can be used to interact with a pipeline, e.g. to write a request and read the
+    /// response. The pipes can be taken with [`PipeChild::take_stdin`] and
+    /// [`PipeChild::take_stdout`]. The stdin is a [`ChildStdin`], which doesn't buffer, so every
+    /// write is passed to the first command right away and [`flush`] has nothing to do. To
+    /// write a line with multiple writes, wrap the stdin in a [`LineWriter`] or a [`BufWriter`]
+    /// and call [`flush`] once the request is complete. Dropping the stdin closes the pipe, so
+    /// the first command receives an EOF.
+    ///
+    /// Note that many programs buffer their stdout if it is not a terminal, so their output may
+    /// only arrive once the buffer is full or they exit. Such programs often have an option to
+    /// flush after every line, e.g. `grep --line-buffered` or `sed -u`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    /// use std::io::{BufRead, BufReader, Write};
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let mut pipe_child = cmd!("cat" => "sed", "-u", "s/^/> /").spawn_duplex()?;
+    /// let mut stdin = pipe_child.take_stdin().unwrap();
+    /// let mut stdout = BufReader::new(pipe_child.take_stdout().unwrap());
+    /// let mut line = String::new();
+    /// for request in ["a", "b"] {
+    ///     writeln!(stdin, "{}", request)?;
+    ///     stdin.flush()?;
+    ///     line.clear();
+    ///     stdout.read_line(&mut line)?;
+    ///     assert_eq!(line, format!("> {}\n", request));
+    /// }
+    /// drop(stdin);
+    /// pipe_child.wait_all()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`flush`]: Write::flush
+    /// [`LineWriter`]: io::LineWriter
+    /// [`BufWriter`]: io::BufWriter
+    /// [`commands`]: Self::commands
+    pub fn spawn_duplex(&mut self) -> io::Result<PipeChild<N>> {
+        let (mut children, child) = self.spawn_with_head(HeadMode::Piped, |command| {
+            pipeline::with_piped_tail(command, Tail::Stdout, Command::spawn)
+        })?;
+        children.push(child);
+        Ok(PipeChild {
+            children: children.try_into().unwrap(),
+        })
+    }
+
+    /// Returns the [`Output`] of the last command.
+    ///
+    /// Note that this method still calls [`Command::spawn`] on all commands except the last one and
+    /// waits for them to exit.
+    ///
+    /// If [pipefail mode] is enabled, the [`status`] field of the returned [`Output`] is the exit
+    /// status of the last command that failed. If [`error_on_failure`] is enabled, an error is
+    /// returned if the pipeline failed.
+    ///
+    /// The stdout and stderr of the last command are always captured, even if they were
+    /// configured on the command, since methods like [`read`] reset them after piping them. If
+    /// [`stderr_all_to`] is set, the stderr is appended to the sink instead.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// [`commands`]: Self::commands
+    /// [pipefail mode]: Self::pipefail
+    /// [`status`]: Output::status
+    /// [`error_on_failure`]: Self::error_on_failure
+    /// [`read`]: Self::read
+    /// [`stderr_all_to`]: Self::stderr_all_to
+    pub fn output(&mut self) -> io::Result<Output> {
+        let tail = if self.stderr_sink.is_some() {
+            Tail::Stdout
+        } else {
+            Tail::StdoutStderr
+        };
+        let (children, mut output) =
+            self.spawn_with(|command| pipeline::with_piped_tail(command, tail, Command::output))?;
+        let mut statuses = self.wait_all(children)?;
+        statuses.push(output.status);
+        output.status = self.checked_status(&statuses.try_into().unwrap())?;
+        Ok(output)
+    }
+
+    /// Returns the [`Output`] of the last command and also writes its stdout to `writer`.
+    ///
+    /// This works like the `tee` program: the stdout of the last command is written to `writer`
+    /// (e.g. a [`File`]) while it is read, and it is also returned in the [`stdout`] field of the
+    /// [`Output`]. Otherwise this method behaves like [`output`], including [`error_on_failure`].
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if spawning, reading from or waiting for any of the commands fails, or
+    /// if the pipeline failed and [`error_on_failure`] is enabled. If
+    /// writing to `writer` fails, all commands are killed and an error with the same
+    /// [`io::ErrorKind`] is returned, whose message starts with `failed to write the output`, so it
+    /// can be told apart from an error of the pipeline itself.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use procmd::cmd;
+    /// use std::fs::File;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let log = File::create("build.log")?;
+    /// let output = cmd!("make" => "grep", "-v", "^make").output_tee(log)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`stdout`]: Output::stdout
+    /// [`output`]: Self::output
+    /// [`error_on_failure`]: Self::error_on_failure
+    /// [`commands`]: Self::commands
+    pub fn output_tee<W: Write>(&mut self, mut writer: W) -> io::Result<Output> {
+        let (mut children, mut child) = self.spawn_with(|command| {
+            pipeline::with_piped_tail(command, Tail::StdoutStderr, Command::spawn)
+        })?;
+        let mut stdout = child.stdout.take().unwrap();
+        let mut stderr = child.stderr.take().unwrap();
+        children.push(child);
+        let stderr_thread = thread::spawn(move || {
+            let mut buf = Vec::new();
+            stderr.read_to_end(&mut buf).map(|_| buf)
+        });
+
+        let write_error =
+            |e: io::Error| io::Error::new(e.kind(), format!("failed to write the output: {}", e));
+        let mut buf = vec![0; self.reader_config.capacity];
+        let mut captured = Vec::new();
+        let result = loop {
+            match stdout.read(&mut buf) {
+                Ok(0) => break writer.flush().map_err(write_error),
+                Ok(len) => {
+                    captured.extend_from_slice(&buf[..len]);
+                    if let Err(e) = writer.write_all(&buf[..len]) {
+                        break Err(write_error(e));
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => break Err(e),
+            }
+        };
+        drop(stdout);
+        if let Err(e) = result {
+            pipeline::kill_all(children);
+            let _ = stderr_thread.join();
+            return Err(e);
+        }
+        let stderr = stderr_thread.join().unwrap();
+        let statuses = self.wait_all(children)?;
+        Ok(Output {
+            status: self.checked_status(&statuses.try_into().unwrap())?,
+            stdout: captured,
+            stderr: stderr?,
+        })
+    }
+
+    /// Returns the stdout of the last command, the stderr of all commands and the exit status of
+    /// every command.
+    ///
+    /// This captures everything a pipeline outputs, e.g. for diagnostics. The stderr of all
+    /// commands is written to a single pipe, so it is interleaved in the order in which it was
+    /// written, and any stderr configured for the commands is ignored. The stdout of every command
+    /// except the last one is connected to the stdin of the next command, so only the stdout of
+    /// the last command is captured.
+    ///
+    /// Like with [`run_quiet`], the stderr of every command is reset to be inherited after
+    /// spawning, so a stderr that was configured before (e.g. with [`intermediate_stderr`]) has to
+    /// be configured again for the next run of the pipeline.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let output = cmd!("ls", "/nonexistent" => "wc", "-l").output_full()?;
+    /// assert!(!output.statuses[0].success());
+    /// assert!(!output.stderr.is_empty());
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`run_quiet`]: Self::run_quiet
+    /// [`intermediate_stderr`]: Self::intermediate_stderr
+    /// [`commands`]: Self::commands
+    pub fn output_full(&mut self) -> io::Result<FullOutput<N>> {
+        let (mut stderr, writer) = io::pipe()?;
+        let result = (|| {
+            for command in &mut self.commands {
+                command.stderr(writer.try_clone()?);
+            }
+            self.spawn_piped()
+        })();
+        for command in &mut self.commands {
+            // Close the copies of the write end, so the reader receives an EOF.
+            command.stderr(Stdio::inherit());
+        }
+        drop(writer);
+        let (children, mut stdout) = result?;
+        let stderr_thread = thread::spawn(move || {
+            let mut buf = Vec::new();
+            stderr.read_to_end(&mut buf).map(|_| buf)
+        });
+        let mut captured = Vec::new();
+        let result = stdout.read_to_end(&mut captured);
+        drop(stdout);
+        if let Err(e) = result {
+            pipeline::kill_all(children);
+            let _ = stderr_thread.join();
+            return Err(e);
+        }
+        let statuses = self.wait_all(children)?;
+        Ok(FullOutput {
+            stdout: captured,
+            stderr: stderr_thread.join().unwrap()?,
+            statuses: statuses.try_into().unwrap(),
+        })
+    }
+
+    /// Returns the [`Output`] of the last command and also writes its stdout to the stdout of the
+    /// current process.
+    ///
+    /// This is useful for showing the output to the user while it is produced and also capturing
+    /// it, e.g. to write it to a log. This is the same as [`output_tee`] with [`io::stdout`], so
+    /// the stdout is written to the current process while it is read and is returned in the
+    /// [`stdout`] field of the [`Output`]. The stderr is captured like in [`output`].
+    ///
+    /// # Errors
+    ///
+    /// The errors are the same as in [`output_tee`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// [`output_tee`]: Self::output_tee
+    /// [`stdout`]: Output::stdout
+    /// [`output`]: Self::output
+    /// [`commands`]: Self::commands
+    pub fn output_and_inherit(&mut self) -> io::Result<Output> {
+        self.output_tee(io::stdout())
+    }
+
+    /// Returns the [`ExitStatus`] of the last command.
+    ///
+    /// Note that this method still calls [`Command::spawn`] on all commands except the last one and
+    /// waits for them to exit.
+    ///
+    /// If [pipefail mode] is enabled, the exit status of the last command that failed is returned
+    /// instead. If [`error_on_failure`] is enabled, an error is returned if the pipeline failed.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// [`commands`]: Self::commands
+    /// [pipefail mode]: Self::pipefail
+    /// [`error_on_failure`]: Self::error_on_failure
+    pub fn status(&mut self) -> io::Result<ExitStatus> {
+        let statuses = self.statuses()?;
+        self.checked_status(&statuses)
+    }
+
+    /// Spawns all commands and returns an iterator over the newline-delimited JSON values written
+    /// to the stdout of the last command.
+    ///
+    /// Each line is deserialized as it arrives, so this method can be used with commands that
+    /// stream their output (e.g. `kubectl get --watch -o json`). Empty lines are skipped and the
+    /// last line is deserialized even if it's not terminated by a newline.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if spawning any of the commands fails. Errors that occur while
+    /// reading are yielded by the iterator:
+    ///
+    /// - A line that can't be deserialized yields an [`io::Error`] of kind
+    ///   [`io::ErrorKind::InvalidData`] (or [`io::ErrorKind::UnexpectedEof`] for a truncated
+    ///   value). The iterator continues with the next line afterwards.
+    /// - An error while reading the stdout or while waiting for the commands to exit is yielded
+    ///   once and ends the iteration.
+    ///
+    /// The exit statuses of the commands are not checked. Dropping the iterator before it is
+    /// exhausted kills all commands.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// [`commands`]: Self::commands
+    #[cfg(feature = "serde")]
+    pub fn output_ndjson<T>(&mut self) -> io::Result<JsonLines<T>>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let (children, stdout) = self.spawn_piped()?;
+        Ok(JsonLines::new(stdout, children, &self.reader_config))
+    }
+
+    /// Spawns all commands and returns an asynchronous reader for the stdout of the last command.
+    ///
+    /// The commands are spawned as regular processes and waited for in a blocking task (see
+    /// [`tokio::task::spawn_blocking`]), so every command is reaped once it exits. The exit
+    /// statuses can be retrieved with [`AsyncPipeReader::wait`]. This method is only available
+    /// with the `async` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    /// use tokio::io::AsyncReadExt;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// # let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    /// # runtime.block_on(async {
+    /// let mut reader = cmd!("ls" => "grep", "example").spawn_async()?;
+    /// let mut output = String::new();
+    /// reader.read_to_string(&mut output).await?;
+    /// let statuses = reader.wait().await?;
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This method panics if it's not called from within a Tokio runtime with I/O enabled, or if
+    /// [`commands`] is empty.
+    ///
+    /// [`commands`]: Self::commands
+    #[cfg(feature = "async")]
+    pub fn spawn_async(&mut self) -> io::Result<AsyncPipeReader<N>> {
+        let (children, stdout) = self.spawn_piped_with_head(HeadMode::Interactive)?;
+        let stdout = match tokio::process::ChildStdout::from_std(stdout) {
+            Ok(stdout) => stdout,
+            Err(e) => {
+                pipeline::kill_all(children);
+                return Err(e);
+            }
+        };
+        let reaper = tokio::task::spawn_blocking(move || pipeline::wait_all(children));
+        Ok(AsyncPipeReader::new(stdout, reaper))
+    }
+
+    /// Runs the pipeline asynchronously and returns the [`Output`] of the last command.
+    ///
+    /// This is the asynchronous counterpart of [`output`] for when only the result is needed (use
+    /// [`spawn_async`] to stream the output instead). The commands are spawned when the future is
+    /// first polled. The stdout and stderr of the last command are read with Tokio and all
+    /// commands are waited for in a blocking task, so the future resolves once every command has
+    /// exited. Like with [`output`], [pipefail mode] and [`error_on_failure`] are respected. This
+    /// method is only available with the `async` feature.
+    ///
+    /// # Cancellation
+    ///
+    /// If the future is dropped before the output of the last command was read to the end, all
+    /// commands are killed and reaped, like with the `kill_on_drop` option of a Tokio
+    /// [`Command`](tokio::process::Command). If it's dropped while the commands are waited for,
+    /// they are still reaped in the background, but not killed.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// # let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    /// # runtime.block_on(async {
+    /// let output = cmd!("ls" => "grep", "example").run_async().await?;
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// The future panics if it's not polled from within a Tokio runtime with I/O enabled, or if
+    /// [`commands`] is empty.
+    ///
+    /// [`output`]: Self::output
+    /// [`spawn_async`]: Self::spawn_async
+    /// [pipefail mode]: Self::pipefail
+    /// [`error_on_failure`]: Self::error_on_failure
+    /// [`commands`]: Self::commands
+    #[cfg(feature = "async")]
+    pub async fn run_async(&mut self) -> io::Result<Output> {
+        use tokio::io::AsyncReadExt;
+        use tokio::process::{ChildStderr, ChildStdout};
+
+        let (mut children, mut child) = self.spawn_with(|command| {
+            pipeline::with_piped_tail(command, Tail::StdoutStderr, Command::spawn)
+        })?;
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        children.push(child);
+        let mut guard = KillOnDrop(children);
+        let mut stdout = ChildStdout::from_std(stdout)?;
+        let mut stderr = ChildStderr::from_std(stderr)?;
+        let stderr_task = tokio::spawn(async move {
+            let mut buf = Vec::new();
+            stderr.read_to_end(&mut buf).await.map(|_| buf)
+        });
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).await?;
+        drop(stdout);
+        let stderr = stderr_task.await.map_err(io::Error::other)??;
+        let children = std::mem::take(&mut guard.0);
+        let forwarder = self.stderr_forwarder.take();
+        let statuses = tokio::task::spawn_blocking(move || {
+            let statuses = pipeline::wait_all(children);
+            if let Some(forwarder) = forwarder {
+                let _ = forwarder.join();
+            }
+            statuses
+        })
+        .await
+        .map_err(io::Error::other)??;
+        Ok(Output {
+            status: self.checked_status(&statuses.try_into().unwrap())?,
+            stdout: output,
+            stderr,
+        })
+    }
+
+    /// Sets the configuration for reading the stdout of the last command.
+    ///
+    /// This is used by [`for_each_line`] and the other methods that read the stdout while the
+    /// commands are running.
+    ///
+    /// [`for_each_line`]: Self::for_each_line
+    pub fn reader_config(&mut self, config: ReaderConfig) -> &mut Self {
+        self.reader_config = config;
+        self
+    }
+
+    /// Calls `f` whenever the last command didn't write to its stdout for `interval`.
+    ///
+    /// This is used by [`for_each_line`], [`for_each_chunk`] and [`stream_to_channel`] and can be
+    /// used to show progress or to detect hangs. `f` is called repeatedly, once per `interval`,
+    /// for as long as the stall lasts, and the pipeline keeps running. The detection is advisory
+    /// and best-effort: the time is measured between chunks that are read from the stdout, so
+    /// output that a command buffers internally is not noticed until it's written.
+    ///
+    /// If a stall handler is set, the stdout is read in a separate thread.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    /// use std::time::Duration;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let mut pipe_cmd = cmd!("ls" => "grep", "example");
+    /// pipe_cmd.on_stall(Duration::from_secs(1), || eprintln!("still working..."));
+    /// pipe_cmd.for_each_line(|line| println!("{}", line))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`for_each_line`]: Self::for_each_line
+    /// [`for_each_chunk`]: Self::for_each_chunk
+    /// [`stream_to_channel`]: Self::stream_to_channel
+    pub fn on_stall<F>(&mut self, interval: Duration, f: F) -> &mut Self
+    where
+        F: FnMut() + Send + 'static,
+    {
+        self.stall_handler = Some(StallHandler {
+            interval,
+            f: Box::new(f),
+        });
+        self
+    }
+
+    /// Limits the rate at which the stdout of the last command is read to `bytes_per_sec`.
+    ///
+    /// This is used by [`for_each_line`], [`for_each_chunk`] and [`stream_to_channel`], e.g. to
+    /// ship logs to a rate-limited consumer. The reads are paced with sleeps, so once the pipe
+    /// buffer of the last command is full, it blocks on writing, and the backpressure propagates
+    /// through the pipes to the other commands.
+    ///
+    /// The limit is approximate: the average rate since the first read is kept at or below
+    /// `bytes_per_sec`, but the output is read in small bursts and the delivery can fall behind
+    /// the rate if the commands are slower.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `bytes_per_sec` is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let mut pipe_cmd = cmd!("ls" => "grep", "example");
+    /// pipe_cmd.throttle(64 * 1024);
+    /// pipe_cmd.for_each_line(|line| println!("{}", line))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`for_each_line`]: Self::for_each_line
+    /// [`for_each_chunk`]: Self::for_each_chunk
+    /// [`stream_to_channel`]: Self::stream_to_channel
+    pub fn throttle(&mut self, bytes_per_sec: u64) -> &mut Self {
+        assert!(bytes_per_sec > 0, "the throttle rate must not be zero");
+        self.throttle = Some(bytes_per_sec);
+        self
+    }
+
+    /// Spawns all commands and calls `f` on each line written to the stdout of the last command.
+    ///
+    /// The line is passed to `f` without the trailing newline. After the stdout is closed, this
+    /// method waits for all commands to exit and returns their exit statuses.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if spawning or waiting for any of the commands fails, or if the output
+    /// is not valid UTF-8. If an error occurs while reading, all commands are killed.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// [`commands`]: Self::commands
+    pub fn for_each_line<F>(&mut self, mut f: F) -> io::Result<[ExitStatus; N]>
+    where
+        F: FnMut(&str),
+    {
+        let (children, stdout) = self.spawn_piped()?;
+        let capacity = self.reader_config.capacity;
+        let reader = BufReader::with_capacity(capacity, self.tail_reader(stdout));
+        for line in reader.lines() {
+            match line {
+                Ok(line) => f(&line),
+                Err(e) => {
+                    pipeline::kill_all(children);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(self.wait_all(children)?.try_into().unwrap())
+    }
+
+    /// Spawns all commands and calls `f` on each chunk of bytes read from the stdout of the last
+    /// command.
+    ///
+    /// This is the binary counterpart of [`for_each_line`]. The chunks are passed to `f` as soon as
+    /// they are read and are at most [`ReaderConfig::capacity`] bytes long (see
+    /// [`reader_config`]). After the stdout is closed, this method waits for all commands to exit
+    /// and returns their exit statuses.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if spawning or waiting for any of the commands fails. If an error
+    /// occurs while reading, all commands are killed.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// [`for_each_line`]: Self::for_each_line
+    /// [`reader_config`]: Self::reader_config
+    /// [`commands`]: Self::commands
+    pub fn for_each_chunk<F>(&mut self, mut f: F) -> io::Result<[ExitStatus; N]>
+    where
+        F: FnMut(&[u8]),
+    {
+        let (children, stdout) = self.spawn_piped()?;
+        let mut buf = vec![0; self.reader_config.capacity];
+        let mut stdout = self.tail_reader(stdout);
+        loop {
+            match stdout.read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => f(&buf[..len]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    pipeline::kill_all(children);
+                    return Err(e);
+                }
+            }
+        }
+        drop(stdout);
+        Ok(self.wait_all(children)?.try_into().unwrap())
+    }
+
+    /// Spawns all commands and sends each line written to the stdout of the last command to `tx`.
+    ///
+    /// The lines are sent without the trailing newline. If the channel is full, this method blocks
+    /// until the receiver makes room, so a slow consumer also throttles the pipeline. If the
+    /// receiver is disconnected, all commands are killed. In both cases, this method waits for all
+    /// commands to exit and returns their exit statuses.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if spawning or waiting for any of the commands fails, or if the output
+    /// is not valid UTF-8. If an error occurs while reading, all commands are killed.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    /// use std::sync::mpsc;
+    /// use std::thread;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let (tx, rx) = mpsc::sync_channel(16);
+    /// let consumer = thread::spawn(move || rx.into_iter().count());
+    /// cmd!("ls" => "grep", "example").stream_to_channel(tx)?;
+    /// let count = consumer.join().unwrap();
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`commands`]: Self::commands
+    pub fn stream_to_channel(&mut self, tx: SyncSender<String>) -> io::Result<[ExitStatus; N]> {
+        let (mut children, stdout) = self.spawn_piped()?;
+        let capacity = self.reader_config.capacity;
+        let reader = BufReader::with_capacity(capacity, self.tail_reader(stdout));
+        for line in reader.lines() {
+            match line {
+                Ok(line) => {
+                    if tx.send(line).is_err() {
+                        for child in &mut children {
+                            let _ = child.kill();
+                        }
+                        break;
+                    }
+                }
+                Err(e) => {
+                    pipeline::kill_all(children);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(self.wait_all(children)?.try_into().unwrap())
+    }
+
+    /// Spawns all commands and returns readers for the stdout and stderr of the last command.
+    ///
+    /// The two readers are independent of each other. They should be read concurrently (e.g. by
+    /// reading one of them in a separate thread), since the command blocks once the pipe of the
+    /// reader that is not read is full, which can lead to a deadlock.
+    ///
+    /// The commands are waited for in a background thread and their exit statuses are discarded.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    /// use std::io::Read;
+    /// use std::thread;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let mut pipe_cmd = cmd!("ls" => "grep", "example");
+    /// let (mut stdout, mut stderr) = pipe_cmd.split_output()?;
+    /// let stderr_thread = thread::spawn(move || {
+    ///     let mut buf = Vec::new();
+    ///     stderr.read_to_end(&mut buf).map(|_| buf)
+    /// });
+    /// let mut stdout_buf = Vec::new();
+    /// stdout.read_to_end(&mut stdout_buf)?;
+    /// let stderr_buf = stderr_thread.join().unwrap()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// [`commands`]: Self::commands
+    pub fn split_output(&mut self) -> io::Result<(ChildStdout, ChildStderr)> {
+        let (mut children, mut child) = self.spawn_with(|command| {
+            pipeline::with_piped_tail(command, Tail::StdoutStderr, Command::spawn)
+        })?;
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        children.push(child);
+        thread::spawn(move || pipeline::wait_all(children));
+        Ok((stdout, stderr))
+    }
+
+    /// Sets the time to wait between spawning two consecutive commands.
+    ///
+    /// This is a knob for testing code that supervises pipelines, e.g. to reproduce race
+    /// conditions that depend on the order in which the commands are started. It is only
+    /// available with the `testing` feature and should not be used in production.
+    #[cfg(feature = "testing")]
+    pub fn set_spawn_delay(&mut self, delay: Duration) -> &mut Self {
+        self.spawn_options.spawn_delay = delay;
+        self
+    }
+
+    /// Sets the function that [`auto_nice`] uses to query the system load.
+    ///
+    /// This makes it possible to test the behavior of [`auto_nice`] for a given load. It is only
+    /// available on Unix with the `testing` feature.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`auto_nice`] wasn't called before.
+    ///
+    /// [`auto_nice`]: Self::auto_nice
+    #[cfg(all(unix, feature = "testing"))]
+    pub fn set_load_source(&mut self, load: fn() -> io::Result<f64>) -> &mut Self {
+        self.spawn_options
+            .auto_nice
+            .as_mut()
+            .expect("`auto_nice` must be called before the load source is set")
+            .load = load;
+        self
+    }
+
+    /// Runs the pipeline and asserts that the stdout of the last command equals `expected`.
+    ///
+    /// A single trailing newline is removed from both the output and `expected` before they are
+    /// compared, so `"a\nb"` matches the output `a\nb\n`. The output is converted to a string
+    /// lossily. This method is only available with the `testing` feature.
+    ///
+    /// # Panics
+    ///
+    /// This method panics with a line-by-line comparison if the output doesn't match, and it
+    /// panics if spawning the pipeline fails or if the pipeline failed (see [`run`]). It also
+    /// panics if [`commands`] is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// cmd!("printf", "b\\na\\n" => "sort").assert_stdout_eq("a\nb");
+    /// ```
+    ///
+    /// [`run`]: Self::run
+    /// [`commands`]: Self::commands
+    #[cfg(feature = "testing")]
+    pub fn assert_stdout_eq(&mut self, expected: &str) {
+        use crate::testing;
+
+        let output = match self.run() {
+            Ok(report) => report.output,
+            Err(e) => panic!("failed to run `{}`: {}", self.to_shell_string(), e),
+        };
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let actual = testing::trim_newline(&stdout);
+        let expected = testing::trim_newline(expected);
+        if actual != expected {
+            panic!(
+                "unexpected stdout of `{}` (- expected, + actual):\n{}",
+                self.to_shell_string(),
+                testing::diff(expected, actual)
+            );
+        }
+    }
+
+    /// Writes the pipeline to stderr every time before it is spawned, like `set -x` in shells.
+    ///
+    /// This is the same as [`traced_to`] with [`io::stderr`].
+    ///
+    /// [`traced_to`]: Self::traced_to
+    pub fn traced(&mut self) -> &mut Self {
+        self.traced_to(io::stderr())
+    }
+
+    /// Writes the pipeline to `writer` every time before it is spawned, like `set -x` in shells.
+    ///
+    /// The pipeline is rendered with [`to_shell_string`] and written on its own line, prefixed
+    /// with `+ `. Errors while writing are ignored, so the pipeline is spawned even if the trace
+    /// can't be written.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let mut pipe_cmd = cmd!("ls" => "grep", "my file");
+    /// pipe_cmd.traced();
+    /// // Writes `+ ls | grep 'my file'` to stderr.
+    /// let exit_status = pipe_cmd.status()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`to_shell_string`]: Self::to_shell_string
+    pub fn traced_to<W>(&mut self, writer: W) -> &mut Self
+    where
+        W: Write + Send + 'static,
+    {
+        self.tracer = Some(Tracer(Box::new(writer)));
+        self
+    }
+
+    /// Sets the maximum number of commands that the pipeline may contain.
+    ///
+    /// If the pipeline contains more commands, the methods that spawn it return an error of kind
+    /// [`io::ErrorKind::InvalidInput`] before spawning any command. This is useful for tools that
+    /// generate pipelines, to fail early with a clear error instead of exhausting system
+    /// resources.
+    pub fn max_commands(&mut self, max: usize) -> &mut Self {
+        self.spawn_options.max_commands = Some(max);
+        self
+    }
+
+    /// Sets whether the data between two commands is copied by the current process.
+    ///
+    /// By default, the stdout of a command is connected directly to the stdin of the next command
+    /// with a pipe, so the data is passed by the kernel without involving the current process.
+    /// If `enabled` is `true`, every connection between two commands is routed through two pipes
+    /// instead, and a thread of the current process copies the data from one to the other. This
+    /// is the basis for observing or modifying the data between two commands.
+    ///
+    /// The data is passed through unchanged and a command that exits early still closes the
+    /// pipeline like with direct pipes, i.e. the previous command receives `SIGPIPE` (or an error)
+    /// when it writes more data. The copying costs a thread per connection, additional system
+    /// calls and memory bandwidth, so the throughput is lower and the latency is higher than with
+    /// direct pipes. Since the threads hold the pipes while the commands run, a pipeline of `N`
+    /// commands also keeps `2 * (N - 1)` file descriptors open in the current process.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let mut pipe_cmd = cmd!("echo", "test" => "tr", "a-z", "A-Z");
+    /// pipe_cmd.mediated_pipes(true);
+    /// assert_eq!(pipe_cmd.read()?, "TEST");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn mediated_pipes(&mut self, enabled: bool) -> &mut Self {
+        self.spawn_options.mediated = enabled;
+        self
+    }
+
+    /// Calls `f` on the data that flows from the command at `boundary` to the next command.
+    ///
+    /// The data is passed through to the next command unchanged, and `f` is called on every chunk
+    /// before it's passed on, so it sees exactly what the command at `boundary` writes to its
+    /// stdout (unless a [`transform`] was added to the connection before). This is useful for
+    /// debugging what a command emits in the middle of a pipeline. The chunks are at most 8 KiB
+    /// long, but their boundaries are arbitrary, e.g. a line can be split across two chunks.
+    ///
+    /// Observing the data requires the connection to be copied by the current process, so this
+    /// enables [mediated pipes] for this connection, with the same overhead. `f` is called from
+    /// the thread that copies the data, and the next command only receives a chunk once `f`
+    /// returned, so a slow `f` slows down the pipeline. Multiple callbacks can be added to the
+    /// same connection; they are called in the order in which they were added.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let mut pipe_cmd = cmd!("ls" => "grep", "example");
+    /// pipe_cmd.tap(0, |chunk| eprint!("{}", String::from_utf8_lossy(chunk)));
+    /// let output = pipe_cmd.output()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `boundary` is not less than `N - 1`, i.e. if there is no command at
+    /// `boundary + 1`.
+    ///
+    /// [`transform`]: Self::transform
+    /// [mediated pipes]: Self::mediated_pipes
+    pub fn tap<F>(&mut self, boundary: usize, f: F) -> &mut Self
+    where
+        F: FnMut(&[u8]) + Send + 'static,
+    {
+        self.add_hook(boundary, Hook::Tap(Box::new(f)))
+    }
+
+    /// Replaces the data that flows from the command at `boundary` to the next command with the
+    /// data returned by `f`.
+    ///
+    /// This inserts a filter written in Rust between two commands, e.g. to redact secrets,
+    /// without spawning another process. `f` is called on every chunk that the command at
+    /// `boundary` writes to its stdout, and the returned data is passed to the next command
+    /// instead (an empty [`Vec`] passes nothing on). Like with [`tap`], the chunks are at most
+    /// 8 KiB long and their boundaries are arbitrary, so a pattern that should be replaced can be
+    /// split across two chunks. Use [`HybridPipeline`] if the filter needs to see complete lines.
+    ///
+    /// This enables [mediated pipes] for this connection, with the same overhead. The callbacks
+    /// of a connection that were added with [`tap`] and [`transform`] are called in the order in
+    /// which they were added, and every callback receives the data returned by the previous
+    /// transformation.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let mut pipe_cmd = cmd!("echo", "a", "b" => "cat");
+    /// pipe_cmd.transform(0, |chunk| chunk.to_ascii_uppercase());
+    /// assert_eq!(pipe_cmd.read()?, "A B");
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `boundary` is not less than `N - 1`, i.e. if there is no command at
+    /// `boundary + 1`.
+    ///
+    /// [`tap`]: Self::tap
+    /// [`HybridPipeline`]: crate::HybridPipeline
+    /// [mediated pipes]: Self::mediated_pipes
+    /// [`transform`]: Self::transform
+    pub fn transform<F>(&mut self, boundary: usize, f: F) -> &mut Self
+    where
+        F: FnMut(&[u8]) -> Vec<u8> + Send + 'static,
+    {
+        self.add_hook(boundary, Hook::Transform(Box::new(f)))
+    }
+
+    /// Adds `hook` to the connection between the command at `boundary` and the next command.
+    fn add_hook(&mut self, boundary: usize, hook: Hook) -> &mut Self {
+        assert!(
+            boundary + 1 < N,
+            "the boundary index is {} but the pipeline contains only {} commands",
+            boundary,
+            N
+        );
+        self.spawn_options
+            .hooks
+            .entry(boundary)
+            .or_default()
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(hook);
+        self
+    }
+
+    /// Sets whether to check that enough file descriptors are available before spawning.
+    ///
+    /// If enabled, the methods that spawn the pipeline return an error before spawning any command
+    /// if the process is so close to its limit of open files that spawning might fail midway. The
+    /// limit is queried with `getrlimit(RLIMIT_NOFILE)` and the open files are counted by listing
+    /// `/dev/fd`. Spawning needs a small, fixed number of file descriptors at a time, regardless of
+    /// the number of commands, since every pipe is closed in this process once it's connected.
+    ///
+    /// This method is only available on Unix.
+    #[cfg(unix)]
+    pub fn check_fd_limit(&mut self, enabled: bool) -> &mut Self {
+        self.spawn_options.check_fd_limit = enabled;
+        self
+    }
+
+    /// Lowers the priority of the commands if the system load is above `threshold`.
+    ///
+    /// This is useful for batch jobs that should not slow down other programs when the system is
+    /// busy, but should run at full speed otherwise. Every time the pipeline is spawned, the load
+    /// average of the last minute is queried with `getloadavg`. If it is greater than
+    /// `threshold`, every command increases its nice value by 10 (like the `nice` program) right
+    /// before its program is executed, so programs that the commands start run with the lower
+    /// priority as well. The priority of the current process is not changed. If the load can't be
+    /// queried, the priority is not lowered.
+    ///
+    /// The load average is the number of processes that are running or waiting to run, so a
+    /// sensible threshold depends on the number of CPUs, e.g. the value returned by
+    /// [`std::thread::available_parallelism`]. Note that the load average reacts slowly, so a
+    /// short burst of activity may not be detected.
+    ///
+    /// Calling this method again only changes the threshold. This method is only available on
+    /// Unix.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    /// use std::thread;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let cpus = thread::available_parallelism()?.get();
+    /// let mut pipe_cmd = cmd!("ls" => "sort");
+    /// pipe_cmd.auto_nice(cpus as f64 * 0.8);
+    /// let output = pipe_cmd.output()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(unix)]
+    pub fn auto_nice(&mut self, threshold: f64) -> &mut Self {
+        match &mut self.spawn_options.auto_nice {
+            Some(auto_nice) => auto_nice.threshold = threshold,
+            None => {
+                let auto_nice = nice::AutoNice::new(threshold);
+                for command in &mut self.commands {
+                    auto_nice.install(command);
+                }
+                self.spawn_options.auto_nice = Some(auto_nice);
+            }
+        }
+        self
+    }
+
+    /// Sets how the stdin of the first command is set up.
+    ///
+    /// The policy is applied every time the pipeline is spawned. Without a policy, the default
+    /// depends on the method: [`spawn`], [`spawn_all`] and [`spawn_async`] return running
+    /// commands that may be interactive, so the first command inherits the stdin of the current
+    /// process. All other methods (like [`output`] and [`status`]) wait for the pipeline and
+    /// connect the stdin to the null device, so a command that reads from it receives an EOF
+    /// instead of blocking or consuming input that is meant for the current process.
+    ///
+    /// Note that this is a change from earlier versions, where the stdin was always inherited,
+    /// like for a [`Command`]. Use [`StdinPolicy::Inherit`] to restore the old behavior.
+    ///
+    /// A stdin that is set explicitly with [`head_stdin`] (or [`head_stdin_from`] or the `stdin`
+    /// clause of [`cmd!`]) takes precedence over the policy. It is only replaced by methods that
+    /// supply the stdin themselves (like [`head_stdin_from_reader`] or [`spawn_duplex`]), after
+    /// which the policy applies again.
+    ///
+    /// [`StdinPolicy::Piped`] is meant for [`spawn_all`], where the stdin can be written through
+    /// the [`Child`] of the first command. Methods that wait for the pipeline keep the pipe open
+    /// until they wait for the first command, so a first command that reads its stdin blocks.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::{cmd, StdinPolicy};
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let mut pipe_cmd = cmd!("cat" => "wc", "-c");
+    /// pipe_cmd.set_stdin_inherit_policy(StdinPolicy::Null);
+    /// assert_eq!(pipe_cmd.read()?.trim(), "0");
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`spawn`]: Self::spawn
+    /// [`spawn_all`]: Self::spawn_all
+    /// [`spawn_async`]: Self::spawn_async
+    /// [`output`]: Self::output
+    /// [`status`]: Self::status
+    /// [`head_stdin`]: Self::head_stdin
+    /// [`head_stdin_from`]: Self::head_stdin_from
+    /// [`cmd!`]: crate::cmd
+    /// [`head_stdin_from_reader`]: Self::head_stdin_from_reader
+    /// [`spawn_duplex`]: Self::spawn_duplex
+    pub fn set_stdin_inherit_policy(&mut self, policy: StdinPolicy) -> &mut Self {
+        self.stdin_policy = Some(policy);
+        self
+    }
+
+    /// Sets the stdin of the first command.
+    ///
+    /// Unlike setting the stdin of [`commands[0]`](Self::commands) directly, this takes precedence
+    /// over the [stdin policy](Self::set_stdin_inherit_policy) (see there for details).
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    /// use std::fs::File;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let mut pipe_cmd = cmd!("sort" => "head", "-n", "1");
+    /// pipe_cmd.head_stdin(File::open("Cargo.toml")?);
+    /// let first_line = pipe_cmd.read()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// [`commands`]: Self::commands
+    pub fn head_stdin<T: Into<Stdio>>(&mut self, stdin: T) -> &mut Self {
+        self.commands[0].stdin(stdin);
+        self.head_stdin_set = true;
+        self
+    }
+
+    /// Sets the stdin of the first command to the stdout of another child process.
+    ///
+    /// This can be used to embed the pipeline into a larger process graph that was built by hand.
+    /// The ownership of `stdout` is transferred to the first command, so the read end of the pipe
+    /// is closed once the [`PipeCommand`] is dropped or the stdin of the first command is set
+    /// again. Note that the pipe can only be read once, so running the pipeline again will not
+    /// receive the output of the child process again.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    /// use std::process::Stdio;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let mut child = cmd!("ls").stdout(Stdio::piped()).spawn()?;
+    /// let mut pipe_cmd = cmd!("grep", "example" => "wc", "-l");
+    /// pipe_cmd.head_stdin_from(child.stdout.take().unwrap());
+    /// let output = pipe_cmd.output()?;
+    /// child.wait()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// [`commands`]: Self::commands
+    pub fn head_stdin_from(&mut self, stdout: ChildStdout) -> &mut Self {
+        self.head_stdin(stdout)
+    }
+
+    /// Copies the data read from `reader` to the stdin of the first command.
+    ///
+    /// This can be used to feed the pipeline from a file, a socket or a decoder without reading
+    /// all of the data into memory first. When the pipeline is spawned, a thread is started that
+    /// copies the data from `reader` to a pipe that is connected to the stdin of the first
+    /// command, and the pipe is closed once `reader` returns an EOF or an error. If the first
+    /// command exits before it read all of its input, the copy is stopped.
+    ///
+    /// The reader is only used the next time the pipeline is spawned. Afterwards, the stdin of the
+    /// first command is set up according to the [stdin policy] again.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    /// use std::io::Cursor;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let mut pipe_cmd = cmd!("sort" => "head", "-n", "1");
+    /// pipe_cmd.head_stdin_from_reader(Cursor::new("b\na\n"));
+    /// assert_eq!(pipe_cmd.read()?, "a");
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [stdin policy]: Self::set_stdin_inherit_policy
+    pub fn head_stdin_from_reader<R>(&mut self, reader: R) -> &mut Self
+    where
+        R: Read + Send + 'static,
+    {
+        self.head_stdin = Some(HeadStdin(Box::new(reader)));
+        self
+    }
+
+    /// Feeds `data` to the stdin of the command at `index` the next time the pipeline is spawned.
+    ///
+    /// Only a command whose stdin isn't connected to the stdout of another command can receive
+    /// input. In a [`PipeCommand`], every command except the first one reads the stdout of the
+    /// previous command, so `index` must be `0`, in which case this is the same as calling
+    /// [`head_stdin_from_reader`] with a [`Cursor`] over `data`.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::InvalidInput`] is returned if `index` is out of bounds
+    /// or if the command at `index` already receives the stdout of the previous command.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let mut pipe_cmd = cmd!("sort" => "head", "-n", "1");
+    /// pipe_cmd.input_to(0, "b\na\n")?;
+    /// assert_eq!(pipe_cmd.read()?, "a");
+    /// assert!(pipe_cmd.input_to(1, "b\na\n").is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`head_stdin_from_reader`]: Self::head_stdin_from_reader
+    /// [`Cursor`]: io::Cursor
+    pub fn input_to<D>(&mut self, index: usize, data: D) -> io::Result<&mut Self>
+    where
+        D: Into<Vec<u8>>,
+    {
+        if index >= N {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "cannot feed input to the command at index {}, since the pipeline contains \
+                     only {} commands",
+                    index, N
+                ),
+            ));
+        }
+        if index > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "cannot feed input to the command at index {}, since its stdin is the stdout \
+                     of the command at index {}",
+                    index,
+                    index - 1
+                ),
+            ));
+        }
+        Ok(self.head_stdin_from_reader(io::Cursor::new(data.into())))
+    }
+
+    /// Feeds `text` encoded in `encoding` to the stdin of the first command.
+    ///
+    /// This is the same as calling [`head_stdin_from_reader`] with a [`Cursor`] over the encoded
+    /// text, and is the counterpart of [`output_string_with`] for programs that expect their
+    /// input in a legacy encoding.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::InvalidInput`] is returned if `text` contains characters
+    /// that can't be represented in `encoding`, or if `encoding` can't be used for encoding (like
+    /// UTF-16, which is only supported for decoding).
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::{cmd, PipeCommand};
+    /// use procmd::encoding_rs::WINDOWS_1252;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let mut pipe_cmd = PipeCommand::new([cmd!("wc", "-c")]);
+    /// pipe_cmd.input_encoded("café", WINDOWS_1252)?;
+    /// assert_eq!(pipe_cmd.read()?.trim(), "4");
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`head_stdin_from_reader`]: Self::head_stdin_from_reader
+    /// [`output_string_with`]: Self::output_string_with
+    /// [`Cursor`]: io::Cursor
+    #[cfg(feature = "encoding")]
+    pub fn input_encoded(
+        &mut self,
+        text: &str,
+        encoding: &'static encoding_rs::Encoding,
+    ) -> io::Result<&mut Self> {
+        if encoding.output_encoding() != encoding {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cannot encode text as {}", encoding.name()),
+            ));
+        }
+        let (data, _, had_errors) = encoding.encode(text);
+        if had_errors {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("text cannot be represented in {}", encoding.name()),
+            ));
+        }
+        Ok(self.head_stdin_from_reader(io::Cursor::new(data.into_owned())))
+    }
+
+    /// Sets the stdout of the last command to the FIFO (named pipe) at `path`.
+    ///
+    /// The FIFO is created with permissions `0600` if it doesn't exist. It is opened every time
+    /// the pipeline is spawned and closed again once the last command is spawned, so a reader
+    /// receives an EOF when the last command exits.
+    ///
+    /// Note that opening a FIFO for writing blocks until it is opened for reading, so the methods
+    /// that spawn the pipeline block until a reader connects. Methods that capture the stdout of
+    /// the last command (like [`output`]) still open the FIFO, but the output is not written to
+    /// it.
+    ///
+    /// This replaces a file set with [`tail_stdout_file`]. This method is only available on Unix.
+    ///
+    /// [`output`]: Self::output
+    /// [`tail_stdout_file`]: Self::tail_stdout_file
+    #[cfg(unix)]
+    pub fn tail_stdout_fifo<P: Into<PathBuf>>(&mut self, path: P) -> &mut Self {
+        self.tail_stdout = Some(TailStdout::Fifo(path.into()));
+        self
+    }
+
+    /// Sets the stdout of the last command to `file`.
+    ///
+    /// In contrast to setting the stdout of the last command directly, the file is kept open for
+    /// as long as the [`PipeCommand`] exists, so the pipeline can be run multiple times without
+    /// opening the file again. Every time the pipeline is spawned, the handle is duplicated once
+    /// (see [`File::try_clone`]) and the duplicate is closed again once the last command is
+    /// spawned. Open the file in append mode to keep the output of all runs.
+    ///
+    /// This replaces a FIFO set with [`tail_stdout_fifo`]. Methods that capture the stdout of the
+    /// last command (like [`output`]) don't write the output to the file.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use procmd::cmd;
+    /// use std::fs::OpenOptions;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let log = OpenOptions::new().create(true).append(true).open("/var/log/example.log")?;
+    /// let mut pipe_cmd = cmd!("date" => "tr", "a-z", "A-Z");
+    /// pipe_cmd.tail_stdout_file(log);
+    /// pipe_cmd.status()?;
+    /// pipe_cmd.status()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`tail_stdout_fifo`]: Self::tail_stdout_fifo
+    /// [`output`]: Self::output
+    pub fn tail_stdout_file(&mut self, file: File) -> &mut Self {
+        self.tail_stdout = Some(TailStdout::File(file));
+        self
+    }
+
+    /// Passes `fd` to the command at `index` as the file descriptor `target`.
+    ///
+    /// Some programs read additional input from or write additional output to file descriptors
+    /// other than stdin, stdout and stderr, e.g. `gpg --passphrase-fd 3`. This is useful for
+    /// passing secrets that shouldn't appear in the arguments or the environment. `fd` can be
+    /// anything that can be converted into an [`OwnedFd`], like a [`File`] or the reader or writer
+    /// returned by [`io::pipe`].
+    ///
+    /// In the spawned process, `fd` is duplicated to `target` (with `dup2`) right before the
+    /// program is executed, and `target` doesn't have the close-on-exec flag, so the program
+    /// inherits it. `fd` itself keeps the close-on-exec flag, so it isn't leaked to other
+    /// processes. The [`PipeCommand`] takes ownership of `fd` and keeps it open in the current
+    /// process until the [`PipeCommand`] is dropped, so it can be passed every time the pipeline
+    /// is spawned. Note that a reader of a pipe doesn't receive an EOF while the [`PipeCommand`]
+    /// still owns the write end.
+    ///
+    /// This method can be called multiple times to pass several file descriptors to the same
+    /// command. They are duplicated together, so `fd` may also be a file descriptor that is the
+    /// `target` of another one. Passing a file descriptor as a `target` that was already used for
+    /// the command replaces the previous one.
+    ///
+    /// This method is only available on Unix.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use procmd::cmd;
+    /// use std::io::{self, Write};
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let (reader, mut writer) = io::pipe()?;
+    /// writer.write_all(b"passphrase")?;
+    /// drop(writer);
+    /// let mut pipe_cmd = cmd!("cat", "secret.gpg" => "gpg", "--passphrase-fd", "3", "-d");
+    /// pipe_cmd.stage_extra_fd(1, 3, reader);
+    /// let output = pipe_cmd.output()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `index` is out of bounds or if `target` is less than 3, since the
+    /// standard streams should be configured with [`Command::stdin`], [`Command::stdout`] and
+    /// [`Command::stderr`].
+    ///
+    /// [`OwnedFd`]: std::os::unix::io::OwnedFd
+    #[cfg(unix)]
+    pub fn stage_extra_fd<F>(&mut self, index: usize, target: i32, fd: F) -> &mut Self
+    where
+        F: Into<std::os::unix::io::OwnedFd>,
+    {
+        assert!(target >= 3, "the target file descriptor must be at least 3");
+        self.extra_fds[index].pass(&mut self.commands[index], fd.into(), target);
+        self
+    }
+
+    /// Sets the file mode creation mask (umask) of all commands to `mask`.
+    ///
+    /// The umask is set with `umask` in the spawned processes right before the programs are
+    /// executed, so files created by the commands get the permissions `0o666 & !mask` (or `0o777
+    /// & !mask` for directories) unless a program requests fewer permissions. The umask of the
+    /// current process is not changed. If this method is called multiple times, the last mask is
+    /// used. Only the permission bits of `mask` are used.
+    ///
+    /// This method is only available on Unix.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let mut pipe_cmd = cmd!("echo", "test" => "sh", "-c", "cat >/dev/null; umask");
+    /// pipe_cmd.umask_all(0o027);
+    /// assert_eq!(pipe_cmd.read()?, "0027");
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(unix)]
+    pub fn umask_all(&mut self, mask: u32) -> &mut Self {
+        for command in &mut self.commands {
+            umask::set(command, mask);
+        }
+        self
+    }
+
+    /// Sets the stderr of all intermediate commands to the [`Stdio`] returned by `f`.
+    ///
+    /// `f` is called once for every command except the first and the last one, since [`Stdio`]
+    /// can't be cloned. The stderr of the first and the last command can be configured separately
+    /// with [`Command::stderr`]. Methods that redirect the stderr of every command themselves,
+    /// like [`run_quiet`] and [`output_full`], reset it afterwards, so this method has to be called
+    /// again after them.
+    ///
+    /// This method requires the pipeline to contain at least 2 commands, so calling it on a
+    /// `PipeCommand<1>` fails to compile.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    /// use std::process::Stdio;
+    ///
+    /// let mut pipe_cmd = cmd!("ls" => "grep", "example" => "wc", "-l");
+    /// pipe_cmd.intermediate_stderr(Stdio::null);
+    /// ```
+    ///
+    /// [`run_quiet`]: Self::run_quiet
+    /// [`output_full`]: Self::output_full
+    pub fn intermediate_stderr<F>(&mut self, mut f: F) -> &mut Self
+    where
+        F: FnMut() -> Stdio,
+    {
+        let () = StageCount::<N>::AT_LEAST_TWO;
+        if N > 2 {
+            for command in &mut self.commands[1..N - 1] {
+                command.stderr(f());
+            }
+        }
+        self
+    }
+
+    /// Appends the stderr of all commands to `sink`.
+    ///
+    /// Every time the pipeline is spawned, the stderr of every command is connected to a pipe and
+    /// a thread appends everything that is written to it to `sink`. This overrides the stderr that
+    /// is configured on the commands (e.g. with [`intermediate_stderr`]), except for methods that
+    /// capture the stderr of the last command themselves (like [`output_tee`]), in which case
+    /// only the stderr of the other commands is appended.
+    ///
+    /// # Locking and ordering
+    ///
+    /// The thread reads the stderr into its own buffer and only locks `sink` while appending a
+    /// chunk to it. So `sink` can be locked while the pipeline is running (e.g. from the callback
+    /// of [`for_each_line`]) to get the stderr that was appended so far, and pipelines that share
+    /// `sink` can run at the same time.
+    ///
+    /// The methods that wait for the pipeline to exit also wait until all of its stderr was
+    /// appended, which happens once all commands closed their stderr (usually when they exit).
+    /// Methods that return while the pipeline is running, like [`spawn`], don't, so the stderr
+    /// may still be appended after the commands exited. Spawning the pipeline again waits until
+    /// the stderr of the previous run was appended.
+    ///
+    /// The commands run concurrently, so their stderr is appended in the order in which it is
+    /// written, and the output of different commands can be interleaved. A single write of up to
+    /// `PIPE_BUF` bytes (at least 512, 4096 on Linux) is not split, so short lines of different
+    /// commands are not mixed if every line is written at once.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let sink = Arc::new(Mutex::new(Vec::new()));
+    /// let mut pipe_cmd = cmd!("ls", "/nonexistent" => "grep", "example");
+    /// pipe_cmd.stderr_all_to(Arc::clone(&sink));
+    /// pipe_cmd.status()?;
+    /// assert!(!sink.lock().unwrap().is_empty());
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`intermediate_stderr`]: Self::intermediate_stderr
+    /// [`output_tee`]: Self::output_tee
+    /// [`for_each_line`]: Self::for_each_line
+    /// [`spawn`]: Self::spawn
+    pub fn stderr_all_to(&mut self, sink: Arc<Mutex<Vec<u8>>>) -> &mut Self {
+        self.stderr_sink = Some(sink);
+        self
+    }
+
+    /// Sets the environment variables of every command, where the `i`-th element of `envs` is
+    /// applied to the `i`-th command (see [`Command::envs`]).
+    ///
+    /// Since `envs` is an array of length `N`, it's checked at compile time that there is exactly
+    /// one element per command. All elements must have the same type, e.g. a [`Vec`] or a
+    /// [`HashMap`] that is empty for commands without additional environment variables.
+    ///
+    /// Like with [`Command::env`], setting a variable that is already set overrides it. On
+    /// Windows, the names are case-insensitive, so setting `Path` overrides `PATH`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// let mut pipe_cmd = cmd!("ls" => "grep", "example");
+    /// pipe_cmd.env_per_stage([vec![("LC_ALL", "C")], vec![("GREP_COLORS", "mt=01;32")]]);
+    /// ```
+    ///
+    /// [`HashMap`]: std::collections::HashMap
+    pub fn env_per_stage<I, K, V>(&mut self, envs: [I; N]) -> &mut Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        for (command, envs) in self.commands.iter_mut().zip(envs) {
+            command.envs(envs);
+        }
+        self
+    }
+
+    /// Removes duplicate directories from the `PATH` of every command.
+    ///
+    /// This is the same as [`dedup_paths_all`] with only `PATH`.
+    ///
+    /// [`dedup_paths_all`]: Self::dedup_paths_all
+    pub fn dedup_path_all(&mut self) -> &mut Self {
+        self.dedup_paths_all(["PATH"])
+    }
+
+    /// Removes duplicate entries from the path lists `vars` (like `PATH` or `LD_LIBRARY_PATH`) of
+    /// every command.
+    ///
+    /// When directories are added to a path list that is inherited from the parent process,
+    /// duplicates accumulate easily. Every variable is split with [`env::split_paths`], only the
+    /// first occurrence of every entry is kept and the entries are joined again with
+    /// [`env::join_paths`], so the order of the entries (and therefore which programs are found)
+    /// doesn't change.
+    ///
+    /// The variable is set on every command that configures it and on every command that inherits
+    /// it from the current process. Since it can't be detected whether [`Command::env_clear`] was
+    /// called on a command, this method should be called before clearing the environment. The
+    /// variables are deduplicated once, so this method should be called after the entries have
+    /// been added.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// let mut pipe_cmd = cmd!("ls" => "grep", "example");
+    /// pipe_cmd.commands[0].env("PATH", "/usr/local/bin:/usr/bin:/usr/local/bin");
+    /// pipe_cmd.dedup_paths_all(["PATH", "MANPATH"]);
+    /// # #[cfg(unix)]
+    /// let path = pipe_cmd.commands[0].get_envs().find(|(key, _)| *key == "PATH");
+    /// # #[cfg(unix)]
+    /// assert_eq!(path.unwrap().1.unwrap(), "/usr/local/bin:/usr/bin");
+    /// ```
+    ///
+    /// [`env::split_paths`]: std::env::split_paths
+    /// [`env::join_paths`]: std::env::join_paths
+    pub fn dedup_paths_all<I, K>(&mut self, vars: I) -> &mut Self
+    where
+        I: IntoIterator<Item = K>,
+        K: AsRef<OsStr>,
+    {
+        for var in vars {
+            let var = var.as_ref();
+            for command in &mut self.commands {
+                if let Some(value) = env::effective(command, var) {
+                    command.env(var, env::dedup_paths(&value));
+                }
+            }
+        }
+        self
+    }
+
+    /// Sets the environment variables `envs` on every command (see [`Command::envs`]).
+    ///
+    /// The variables are set in the order in which `envs` yields them, so if a variable occurs
+    /// multiple times, the last value wins, like when calling [`Command::env`] repeatedly. Use an
+    /// ordered container like an array, a [`Vec`] or a [`BTreeMap`] if this matters.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// let mut pipe_cmd = cmd!("ls" => "sort");
+    /// pipe_cmd.env_all([("LC_ALL", "en_US.UTF-8"), ("LC_ALL", "C")]);
+    /// ```
+    ///
+    /// [`BTreeMap`]: std::collections::BTreeMap
+    pub fn env_all<I, K, V>(&mut self, envs: I) -> &mut Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        let envs = envs
+            .into_iter()
+            .map(|(key, value)| (key.as_ref().to_owned(), value.as_ref().to_owned()))
+            .collect::<Vec<_>>();
+        for command in &mut self.commands {
+            command.envs(envs.iter().map(|(key, value)| (key, value)));
+        }
+        self
+    }
+
+    /// Applies a prepared environment `map` to every command.
+    ///
+    /// If `clear_first` is `true`, the environment of every command is cleared first (see
+    /// [`Command::env_clear`]), which removes the inherited variables as well as the variables
+    /// that were already set on the commands, and then the variables of `map` are set. So the
+    /// commands see exactly the variables of `map`. Like with [`inherit_env`], this can't be
+    /// undone. If `clear_first` is `false`, the variables of `map` are set on top of the current
+    /// environment of the commands, like with [`env_all`].
+    ///
+    /// `map` can be a [`HashMap`] or a [`BTreeMap`]. The keys of a map are unique, so the order
+    /// only matters on platforms where environment variable names are case-insensitive (e.g.
+    /// `Path` and `PATH` on Windows), where the last one wins. A [`BTreeMap`] makes the order
+    /// deterministic in this case.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    /// use std::collections::HashMap;
+    /// use std::ffi::OsString;
+    ///
+    /// let mut env = HashMap::new();
+    /// env.insert(OsString::from("PATH"), OsString::from("/usr/bin:/bin"));
+    /// env.insert(OsString::from("LC_ALL"), OsString::from("C"));
+    /// let mut pipe_cmd = cmd!("env" => "sort");
+    /// pipe_cmd.set_env_all(&env, true);
+    /// ```
+    ///
+    /// [`inherit_env`]: Self::inherit_env
+    /// [`env_all`]: Self::env_all
+    /// [`HashMap`]: std::collections::HashMap
+    /// [`BTreeMap`]: std::collections::BTreeMap
+    pub fn set_env_all<'a, I>(&mut self, map: I, clear_first: bool) -> &mut Self
+    where
+        I: IntoIterator<Item = (&'a OsString, &'a OsString)>,
+    {
+        let map = map.into_iter().collect::<Vec<_>>();
+        if clear_first {
+            self.inherit_env = false;
+        }
+        for command in &mut self.commands {
+            if clear_first {
+                command.env_clear();
+            }
+            command.envs(map.iter().copied());
+        }
+        self
+    }
+
+    /// Sets whether the commands inherit the environment variables of the parent process.
+    ///
+    /// If `inherit` is `false`, [`Command::env_clear`] is called on every command. The variables
+    /// that were already set on the commands (e.g. with [`env_per_stage`]) are kept, and variables
+    /// can still be set afterwards, so the environment is always cleared first and the per-stage
+    /// settings are applied on top.
+    ///
+    /// Since clearing the environment of a [`Command`] can't be undone, passing `true` (the
+    /// default) has no effect.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// let mut pipe_cmd = cmd!("env" => "sort");
+    /// pipe_cmd.inherit_env(false).env_per_stage([vec![("LANG", "C")], vec![("LC_ALL", "C")]]);
+    /// ```
+    ///
+    /// [`env_per_stage`]: Self::env_per_stage
+    pub fn inherit_env(&mut self, inherit: bool) -> &mut Self {
+        if !inherit {
+            self.inherit_env = false;
+            for command in &mut self.commands {
+                let envs = command
+                    .get_envs()
+                    .filter_map(|(key, value)| Some((key.to_owned(), value?.to_owned())))
+                    .collect::<Vec<_>>();
+                command.env_clear().envs(envs);
+            }
+        }
+        self
+    }
+
+    /// Returns the differences between the environment variables configured for the commands at
+    /// index `i` and `j`, sorted by the name of the variable.
+    ///
+    /// Only the variables set with [`Command::env`] (and similar methods) and removed with
+    /// [`Command::env_remove`] are compared, since these are the only ones that are returned by
+    /// [`Command::get_envs`]. Whether [`Command::env_clear`] was called is not taken into account.
+    ///
+    /// On Windows, the names of environment variables are compared case-insensitively, so `PATH`
+    /// and `Path` are considered the same variable.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `i` or `j` is out of bounds.
+    pub fn env_diff_between(&self, i: usize, j: usize) -> Vec<(OsString, EnvDelta)> {
+        env::diff(&self.commands[i], &self.commands[j])
+    }
+
+    /// Returns the absolute path of the program that the command at `index` runs.
+    ///
+    /// If the program contains a path separator, it's resolved relative to the working directory
+    /// of the command. Otherwise it's searched in the directories of the `PATH` environment
+    /// variable, as configured for the command or inherited from the current process. On Windows,
+    /// `.exe` is appended to programs without an extension.
+    ///
+    /// This is a best-effort approximation of the lookup that the operating system performs when
+    /// the command is spawned. Whether [`Command::env_clear`] was called is not taken into account.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::NotFound`] is returned if no executable file is found.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `index` is out of bounds.
+    pub fn resolved_program(&self, index: usize) -> io::Result<PathBuf> {
+        resolve::resolve(&self.commands[index])
+    }
+
+    /// Checks that every command with a relative program path and a working directory can find
+    /// its program, without spawning any of them.
+    ///
+    /// A program that contains a path separator, like `./build.sh` or `bin/tool`, is resolved
+    /// relative to the working directory of the command (set with [`Command::current_dir`] or the
+    /// `cwd` clause of [`cmd!`]), not relative to the working directory of the current process.
+    /// This is easy to get wrong, so this method can be called before spawning to get a precise
+    /// error instead of a generic "not found" error from the operating system. Programs without
+    /// a path separator are searched in `PATH` and commands without a working directory are not
+    /// checked; use [`resolved_program`] or [`plan`] to check those as well.
+    ///
+    /// Note that on some platforms (e.g. Windows), a relative program may be resolved relative to
+    /// the working directory of the current process instead, so this check is stricter than
+    /// necessary there.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::NotFound`] is returned for the first command whose program
+    /// doesn't exist relative to its working directory. The message contains the index of the
+    /// command, the program and the working directory.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// let pipe_cmd = cmd!("./configure", cwd: "/nonexistent" => "tee", "configure.log");
+    /// assert!(pipe_cmd.verify_relative_programs().is_err());
+    /// ```
+    ///
+    /// [`cmd!`]: crate::cmd
+    /// [`resolved_program`]: Self::resolved_program
+    /// [`plan`]: Self::plan
+    pub fn verify_relative_programs(&self) -> io::Result<()> {
+        for (i, command) in self.commands.iter().enumerate() {
+            resolve::check_relative(command).map_err(|e| {
+                io::Error::new(e.kind(), format!("the command at index {}: {}", i, e))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Returns what would be passed to the operating system for every command, without spawning
+    /// any of them.
+    ///
+    /// For every command, the plan contains the absolute path of the program (resolved like in
+    /// [`resolved_program`]), the program followed by the arguments (see [`argv_of`]), all
+    /// environment variables the command would receive (the variables of the current process,
+    /// unless disabled with [`inherit_env`], combined with the variables configured for the
+    /// command) and the absolute path of the working directory. This can be used to review a
+    /// pipeline before it is run, e.g. for auditing.
+    ///
+    /// Note that [`Command`] doesn't expose whether [`Command::env_clear`] was called on it, so
+    /// the environment is only cleared in the plan if [`inherit_env`] was used. Like in
+    /// [`resolved_program`], the program is resolved on a best-effort basis.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the program of a command is not found or if the working directory
+    /// of the current process can't be determined.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let mut pipe_cmd = cmd!("ls", "-l" => "wc", "-l");
+    /// pipe_cmd.inherit_env(false).env_all([("LC_ALL", "C")]);
+    /// for plan in pipe_cmd.plan()? {
+    ///     println!("{:?} {:?} in {:?} with {:?}", plan.program, plan.argv, plan.cwd, plan.env);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`resolved_program`]: Self::resolved_program
+    /// [`argv_of`]: Self::argv_of
+    /// [`inherit_env`]: Self::inherit_env
+    pub fn plan(&self) -> io::Result<Vec<ExecPlan>> {
+        (0..N)
+            .map(|i| {
+                let command = &self.commands[i];
+                Ok(ExecPlan {
+                    program: resolve::resolve(command)?,
+                    argv: self.argv_of(i),
+                    env: env::materialize(command, self.inherit_env),
+                    cwd: resolve::cwd(command)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the program followed by the arguments of the command at `index`.
+    ///
+    /// This is the exact argument vector that is passed to the operating system when the command
+    /// is spawned, so in contrast to [`to_shell_string`] there is no quoting involved. This makes
+    /// it suitable for logging or for checking commands against an allowlist.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `index` is out of bounds.
+    ///
+    /// [`to_shell_string`]: Self::to_shell_string
+    pub fn argv_of(&self, index: usize) -> Vec<OsString> {
+        let command = &self.commands[index];
+        std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(ToOwned::to_owned)
+            .collect()
+    }
+
+    /// Renders the commands as a POSIX shell pipeline.
+    ///
+    /// Every program and argument is quoted if necessary, so the returned string can be passed to
+    /// `sh -c`. Only the programs and arguments are rendered; environment variables, working
+    /// directories and redirections configured on the commands are not included. Invalid UTF-8 is
+    /// replaced with `U+FFFD`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// let pipe_cmd = cmd!("ls", "-a" => "grep", "my file");
+    /// assert_eq!(pipe_cmd.to_shell_string(), "ls -a | grep 'my file'");
+    /// ```
+    pub fn to_shell_string(&self) -> String {
+        shell::render(&self.commands)
+    }
+
+    /// Renders the pipeline as a complete shell script for `flavor`.
+    ///
+    /// In contrast to [`to_shell_string`], the script also sets up the environment variables and
+    /// working directories of the commands, so it can be used to reproduce what the pipeline does
+    /// outside of the program. Variables that are set or removed identically on all commands are
+    /// exported (`export FOO=bar`) or unset, and a working directory that is shared by all
+    /// commands is entered with `cd`. Anything that differs between the commands is set up for
+    /// the individual commands with `env` and a subshell. If the environment is not inherited
+    /// (see [`inherit_env`]), the commands are run with `env -i`.
+    ///
+    /// The script exits on the first failing command (`set -e`). Redirections configured on the
+    /// commands are not included. Invalid UTF-8 is replaced with `U+FFFD`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::{cmd, ScriptFlavor};
+    ///
+    /// let pipe_cmd = cmd!("make", env: [("CC", "clang")], cwd: "/src" => "grep", "error");
+    /// assert_eq!(
+    ///     pipe_cmd.to_script(ScriptFlavor::Posix),
+    ///     "#!/bin/sh\nset -e\n(cd /src && exec env CC=clang make) | grep error\n",
+    /// );
+    /// ```
+    ///
+    /// [`to_shell_string`]: Self::to_shell_string
+    /// [`inherit_env`]: Self::inherit_env
+    pub fn to_script(&self, flavor: ScriptFlavor) -> String {
+        shell::render_script(
+            &self.commands,
+            flavor,
+            self.exit_policy.pipefail,
+            self.inherit_env,
+        )
+    }
+
+    /// Renders the pipeline as a Graphviz DOT graph.
+    ///
+    /// Every command is a node that is labeled with its [label] or, if it has none, its program.
+    /// The edges connect the stdout of a command to the stdin of the next command. The graph can
+    /// be rendered with e.g. `dot -Tsvg`. Invalid UTF-8 is replaced with `U+FFFD`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// let pipe_cmd = cmd!("ls" => "wc", "-l");
+    /// assert_eq!(
+    ///     pipe_cmd.to_dot(),
+    ///     "digraph pipeline {
+    ///     stage0 [label=\"ls\"];
+    ///     stage1 [label=\"wc\"];
+    ///     stage0 -> stage1;
+    /// }
+    /// ",
+    /// );
+    /// ```
+    ///
+    /// [label]: Self::label
+    pub fn to_dot(&self) -> String {
+        shell::render_dot(
+            self.commands
+                .iter()
+                .zip(&self.labels)
+                .map(|(command, label)| {
+                    label
+                        .clone()
+                        .unwrap_or_else(|| command.get_program().to_string_lossy().into_owned())
+                }),
+        )
+    }
+
+    /// Renders the commands as the value of an `ExecStart=` line of a systemd unit.
+    ///
+    /// systemd doesn't support pipes in `ExecStart=`, so the pipeline is rendered with
+    /// [`to_shell_string`] and run by `/bin/sh -c`. The shell command line is passed as a double
+    /// quoted word, in which `\`, `"` and newlines are escaped with a backslash. Additionally,
+    /// `%` is escaped as `%%` and `$` as `$$`, so systemd doesn't expand specifiers or environment
+    /// variables.
+    ///
+    /// The same caveats as for [`to_shell_string`] apply, i.e. environment variables, working
+    /// directories and redirections of the commands are not included. These can be configured
+    /// with `Environment=`, `WorkingDirectory=` and `StandardOutput=` in the unit instead.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// let pipe_cmd = cmd!("journalctl", "-f" => "grep", "100%");
+    /// assert_eq!(
+    ///     pipe_cmd.to_systemd_exec_start(),
+    ///     r#"/bin/sh -c "journalctl -f | grep 100%%""#
+    /// );
+    /// ```
+    ///
+    /// [`to_shell_string`]: Self::to_shell_string
+    pub fn to_systemd_exec_start(&self) -> String {
+        format!(
+            "/bin/sh -c {}",
+            shell::quote_systemd(&self.to_shell_string())
+        )
+    }
+
+    /// Runs the whole pipeline as root with `sudo sh -c` and returns the exit status of the shell.
+    ///
+    /// In contrast to prefixing a command with `sudo`, which only elevates that single command,
+    /// this runs all commands under a single `sudo` invocation, so the user is only asked for their
+    /// password once. The pipeline is rendered with [`to_shell_string`].
+    ///
+    /// # Security
+    ///
+    /// - All commands run with root privileges. Never use this with programs or arguments that come
+    ///   from an untrusted source, even though they are quoted.
+    /// - Since the commands are run by a shell, only their programs and arguments are used.
+    ///   Environment variables, working directories and redirections configured on the commands
+    ///   are ignored, and `sudo` may reset the environment and `PATH` according to its policy.
+    /// - `sudo` has to be able to authenticate the user, i.e. it needs an interactive terminal, an
+    ///   askpass helper (`SUDO_ASKPASS`) or cached credentials. Otherwise it fails.
+    ///
+    /// [`to_shell_string`]: Self::to_shell_string
+    pub fn run_elevated(&mut self) -> io::Result<ExitStatus> {
+        Command::new("sudo")
+            .arg("sh")
+            .arg("-c")
+            .arg(self.to_shell_string())
+            .status()
+    }
+
+    /// Returns the exit code of the pipeline like a shell would report it in `$?`.
+    ///
+    /// This is the exit code of the [`ExitStatus`] returned by [`status`], i.e. the exit code of
+    /// the last command, or of the last command that failed if [pipefail mode] is enabled. On Unix,
+    /// a command that was killed by a signal is reported as `128 + signal` (e.g. `137` for
+    /// `SIGKILL`), like shells do.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// [`status`]: Self::status
+    /// [pipefail mode]: Self::pipefail
+    /// [`commands`]: Self::commands
+    pub fn exit_code(&mut self) -> io::Result<i32> {
+        let statuses = self.statuses()?;
+        Ok(pipeline::exit_code(self.pipeline_status(&statuses)))
+    }
+
+    /// Runs the pipeline with its output shown live and returns an error with the stderr if it
+    /// failed.
+    ///
+    /// The stdout of the last command is inherited. The stderr of every command is forwarded to
+    /// the stderr of the current process while the pipeline runs, like with `tee`, and it is also
+    /// captured. If a command failed, the returned [`CommandError`] contains the captured stderr
+    /// in its [`stderr`] field, and its [`Display`] implementation includes it, so the reason of
+    /// the failure ends up in e.g. a log of a build step. On success, nothing else is printed.
+    ///
+    /// Every command is checked, as if [pipefail mode] was enabled, but the allowed exit codes are
+    /// respected. Since all commands write to the same pipe, the stderr of different commands can
+    /// be interleaved. If [`stderr_all_to`] is set, the stderr is appended to the
+    /// sink instead and is neither forwarded nor captured.
+    ///
+    /// Since the [`Stdio`] configured for a [`Command`] can't be read back, the stderr of every
+    /// command is reset to be inherited after spawning, which also discards a stderr that was
+    /// configured before (e.g. with [`intermediate_stderr`]). It has to be configured again for
+    /// the next run of the pipeline.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::{cmd, QuietError};
+    ///
+    /// match cmd!("ls", "/nonexistent" => "cat").run_quiet() {
+    ///     Err(QuietError::Command(err)) => {
+    ///         assert_eq!(err.index, 0);
+    ///         assert!(!err.stderr.is_empty());
+    ///     }
+    ///     result => panic!("unexpected result: {:?}", result),
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// [`stderr`]: CommandError::stderr
+    /// [`Display`]: fmt::Display
+    /// [pipefail mode]: Self::pipefail
+    /// [`stderr_all_to`]: Self::stderr_all_to
+    /// [`intermediate_stderr`]: Self::intermediate_stderr
+    /// [`commands`]: Self::commands
+    pub fn run_quiet(&mut self) -> Result<(), QuietError> {
+        let (mut pipe_reader, pipe_writer) = io::pipe()?;
+        for command in &mut self.commands {
+            command.stderr(pipe_writer.try_clone()?);
+        }
+        drop(pipe_writer);
+        let result = self.spawn_with(Command::spawn);
+        for command in &mut self.commands {
+            command.stderr(Stdio::inherit());
+        }
+        let stderr_thread = thread::spawn(move || {
+            let mut captured = Vec::new();
+            let mut buf = [0; 8 * 1024];
+            loop {
+                match pipe_reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(len) => {
+                        let _ = io::stderr().write_all(&buf[..len]);
+                        captured.extend_from_slice(&buf[..len]);
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(_) => break,
+                }
+            }
+            captured
+        });
+        let (mut children, child) = result?;
+        children.push(child);
+        let statuses = self.wait_all(children)?;
+        let stderr = stderr_thread.join().unwrap();
+        self.require_success_from(&statuses.try_into().unwrap(), 0)
+            .map_err(|e| QuietError::Command(CommandError { stderr, ..e }))
+    }
+
+    /// Returns the [`ExitStatus`] of the last command, or an error if the pipeline failed.
+    ///
+    /// This is the same as [`status`], except that the [`CommandError`] returned by
+    /// [`require_success`] is converted into an [`io::Error`] with [`io::Error::other`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// [`status`]: Self::status
+    /// [`require_success`]: Self::require_success
+    /// [`commands`]: Self::commands
+    pub fn status_checked(&mut self) -> io::Result<ExitStatus> {
+        let statuses = self.statuses()?;
+        self.require_success(&statuses).map_err(io::Error::other)?;
+        Ok(self.pipeline_status(&statuses))
+    }
+
+    /// Returns the [`ExitStatus`] of the last command, or `None` if the pipeline didn't finish
+    /// within `limit`.
+    ///
+    /// If the commands are still running after `limit`, they are killed and waited for. This is
+    /// the same as [`status_timeout_graceful`] with a grace period of zero.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// [`status_timeout_graceful`]: Self::status_timeout_graceful
+    /// [`commands`]: Self::commands
+    pub fn status_timeout(&mut self, limit: Duration) -> io::Result<Option<ExitStatus>> {
+        self.status_timeout_graceful(limit, Duration::from_secs(0))
+    }
+
+    /// Returns the [`ExitStatus`] of the last command, or `None` if the pipeline didn't finish
+    /// within `limit`.
+    ///
+    /// If the commands are still running after `limit`, they are asked to terminate. Commands that
+    /// are still running after the additional `grace` period are killed. All commands are waited
+    /// for before this method returns. The time limits of individual commands (see
+    /// [`stage_timeout`]) are respected as well.
+    ///
+    /// If [pipefail mode] is enabled, the exit status of the last command that failed is returned
+    /// (see [`status`]).
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// On Unix, the commands are asked to terminate by sending `SIGTERM`, which they can handle to
+    /// shut down cleanly (or ignore), and killed by sending `SIGKILL`. Other platforms have no
+    /// equivalent of `SIGTERM`, so the commands are killed right away after `limit` and `grace`
+    /// has no effect.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// [pipefail mode]: Self::pipefail
+    /// [`status`]: Self::status
+    /// [`stage_timeout`]: Self::stage_timeout
+    /// [`commands`]: Self::commands
+    pub fn status_timeout_graceful(
+        &mut self,
+        limit: Duration,
+        grace: Duration,
+    ) -> io::Result<Option<ExitStatus>> {
+        let start = Instant::now();
+        let deadlines = self.deadlines(start).map(|deadline| {
+            Some(deadline.map_or(start + limit, |deadline| deadline.min(start + limit)))
+        });
+        let (mut children, child) = self.spawn_with(|command| command.spawn())?;
+        children.push(child);
+        let result = (|| {
+            if let Ok(statuses) = pipeline::wait_deadlines(&mut children, &deadlines)? {
+                return Ok(Some(statuses));
+            }
+            for child in &mut children {
+                pipeline::terminate(child)?;
+            }
+            pipeline::wait_until(&mut children, Instant::now() + grace)?;
+            Ok(None)
+        })();
+        match result {
+            Ok(Some(statuses)) => {
+                self.join_stderr();
+                Ok(Some(self.pipeline_status(&statuses.try_into().unwrap())))
+            }
+            Ok(None) => {
+                pipeline::kill_all(children);
+                Ok(None)
+            }
+            Err(e) => {
+                pipeline::kill_all(children);
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the [`Output`] of the last command, or an error containing the output produced so
+    /// far if the pipeline didn't finish within `limit`.
+    ///
+    /// This is the same as [`output`], except that the commands are killed if they are still
+    /// running after `limit` or after their own time limit (see [`stage_timeout`]). The stdout and
+    /// stderr of the last command are captured while the pipeline runs, so the output that was
+    /// written before the commands were killed isn't lost.
+    ///
+    /// # Errors
+    ///
+    /// If a time limit is reached, all commands are killed and waited for, and an error of kind
+    /// [`io::ErrorKind::TimedOut`] is returned that contains a [`PartialOutput`], which can be
+    /// retrieved with [`io::Error::get_ref`] and [`downcast_ref`](std::error::Error::downcast_ref).
+    /// The partial output contains everything the last command wrote before it was killed. If a
+    /// process that was started by a command keeps the stdout or stderr open after the command was
+    /// killed, its output is only collected for a short moment, so output written afterwards is
+    /// not contained.
+    ///
+    /// Otherwise, the errors are the same as in [`output`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::{cmd, PartialOutput};
+    /// use std::time::Duration;
+    ///
+    /// let mut pipe_cmd = cmd!("sh", "-c", "echo connecting; exec sleep 5" => "cat");
+    /// let err = pipe_cmd.output_timeout(Duration::from_millis(200)).unwrap_err();
+    /// assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    /// let partial = err.get_ref().unwrap().downcast_ref::<PartialOutput>().unwrap();
+    /// assert_eq!(partial.stdout, b"connecting\n");
+    /// ```
+    ///
+    /// [`output`]: Self::output
+    /// [`stage_timeout`]: Self::stage_timeout
+    /// [`commands`]: Self::commands
+    pub fn output_timeout(&mut self, limit: Duration) -> io::Result<Output> {
+        let start = Instant::now();
+        let deadlines = self.deadlines(start).map(|deadline| {
+            Some(deadline.map_or(start + limit, |deadline| deadline.min(start + limit)))
+        });
+        let (mut children, mut child) = self.spawn_with(|command| {
+            pipeline::with_piped_tail(command, Tail::StdoutStderr, Command::spawn)
+        })?;
+        let capacity = self.reader_config.capacity;
+        let stdout = Capture::new(child.stdout.take().unwrap(), capacity);
+        let stderr = Capture::new(child.stderr.take().unwrap(), capacity);
+        children.push(child);
+        match pipeline::wait_deadlines(&mut children, &deadlines) {
+            Ok(Ok(statuses)) => {
+                self.join_stderr();
+                Ok(Output {
+                    status: self.checked_status(&statuses.try_into().unwrap())?,
+                    stdout: stdout.finish()?,
+                    stderr: stderr.finish()?,
+                })
+            }
+            Ok(Err(index)) => {
+                pipeline::kill_all(children);
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    PartialOutput {
+                        stdout: stdout.finish_within(DRAIN_TIMEOUT),
+                        stderr: stderr.finish_within(DRAIN_TIMEOUT),
+                        index,
+                    },
+                ))
+            }
+            Err(e) => {
+                pipeline::kill_all(children);
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the [`ExitStatus`] of the last command, or `None` if the last command didn't write
+    /// to its stdout for `idle`.
+    ///
+    /// Unlike [`status_timeout`], the time limit is reset whenever the last command writes to its
+    /// stdout, so long-running pipelines are only killed if they stop producing output, e.g. if a
+    /// command hangs. The stdout of the last command is forwarded to the stdout of the current
+    /// process while it is monitored. Once the last command closed its stdout, this method waits
+    /// for all commands to exit without a time limit.
+    ///
+    /// If no output arrives within `idle`, all commands are killed and waited for.
+    ///
+    /// If [pipefail mode] is enabled, the exit status of the last command that failed is returned
+    /// (see [`status`]).
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    /// use std::time::Duration;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let mut pipe_cmd = cmd!("sh", "-c", "echo a; sleep 5; echo b" => "cat");
+    /// let status = pipe_cmd.status_idle_timeout(Duration::from_millis(500))?;
+    /// assert!(status.is_none());
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`status_timeout`]: Self::status_timeout
+    /// [pipefail mode]: Self::pipefail
+    /// [`status`]: Self::status
+    /// [`commands`]: Self::commands
+    pub fn status_idle_timeout(&mut self, idle: Duration) -> io::Result<Option<ExitStatus>> {
+        let (children, mut stdout) = self.spawn_piped()?;
+        let (tx, rx) = mpsc::channel();
+        let capacity = self.reader_config.capacity;
+        thread::spawn(move || {
+            let mut buf = vec![0; capacity];
+            loop {
+                match stdout.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(len) => {
+                        let mut out = io::stdout();
+                        let _ = out.write_all(&buf[..len]).and_then(|()| out.flush());
+                        if tx.send(()).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(_) => break,
+                }
+            }
+        });
+        loop {
+            match rx.recv_timeout(idle) {
+                Ok(()) => {}
+                Err(RecvTimeoutError::Timeout) => {
+                    pipeline::kill_all(children);
+                    return Ok(None);
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        let statuses = self.wait_all(children)?;
+        Ok(Some(self.pipeline_status(&statuses.try_into().unwrap())))
+    }
+
+    /// Spawns all commands, waits for them to exit and returns a detailed report.
+    ///
+    /// The report contains the label, program, arguments, exit status and duration of every
+    /// command, as well as the captured [`Output`] of the last command (like [`output`]). The
+    /// labels are only set for commands that were labeled with [`label`]. The duration of a
+    /// command is measured from spawning the pipeline until the command exited. A pipeline that
+    /// failed is not an error, which can be checked with the [`success`] field of the report.
+    ///
+    /// # Errors
+    ///
+    /// If a command can't be spawned (e.g. because its program doesn't exist), the commands that
+    /// were already spawned are killed and an error of the same [`io::ErrorKind`] is returned
+    /// that contains a [`PartialReport`] of the commands that were run, which can be retrieved
+    /// with [`io::Error::get_ref`] and [`downcast_ref`](std::error::Error::downcast_ref).
+    ///
+    /// An error of kind [`io::ErrorKind::TimedOut`] is returned if a command exceeded its time
+    /// limit (see [`stage_timeout`]).
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::{cmd, PartialReport};
+    ///
+    /// let err = cmd!("ls" => "nonexistent-program").run_detailed().unwrap_err();
+    /// let report = err.get_ref().unwrap().downcast_ref::<PartialReport>().unwrap();
+    /// assert_eq!(report.failed, 1);
+    /// assert!(report.stages[0].is_some());
+    /// assert!(report.stages[1].is_none());
+    /// ```
+    ///
+    /// [`output`]: Self::output
+    /// [`label`]: Self::label
+    /// [`success`]: PipeReport::success
+    /// [`PartialReport`]: crate::PartialReport
+    /// [`stage_timeout`]: Self::stage_timeout
+    /// [`commands`]: Self::commands
+    pub fn run_detailed(&mut self) -> io::Result<PipeReport> {
+        let start = Instant::now();
+        let started_at = SystemTime::now();
+        let spawned = self.spawn_with_partial(HeadMode::Batch, |command| {
+            pipeline::with_piped_tail(command, Tail::StdoutStderr, Command::spawn)
+        });
+        let reporter = pipeline::Reporter {
+            commands: &self.commands,
+            labels: &self.labels,
+            timeouts: &self.timeouts,
+            exit_policy: &self.exit_policy,
+        };
+        let report = reporter.report(start, started_at, spawned);
+        self.join_stderr();
+        report
+    }
+
+    /// Runs the pipeline and returns the stdout of the last command as a string, or an error if
+    /// the pipeline failed.
+    ///
+    /// Trailing newlines are removed from the output. The stderr of all commands is inherited.
+    /// Whether the pipeline failed is determined like in [`require_success`] and the
+    /// [`CommandError`] is converted into an [`io::Error`] with [`io::Error::other`]. If the output
+    /// isn't valid UTF-8, an error of kind [`io::ErrorKind::InvalidData`] is returned.
+    ///
+    /// This is the equivalent of `read` in the `duct` crate.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let output = cmd!("echo", "hello world" => "tr", "a-z", "A-Z").read()?;
+    /// assert_eq!(output, "HELLO WORLD");
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`require_success`]: Self::require_success
+    /// [`commands`]: Self::commands
+    pub fn read(&mut self) -> io::Result<String> {
+        self.read_with(utf8)
+    }
+
+    /// Returns the stdout of the last command as a [`String`] without ANSI escape sequences.
+    ///
+    /// This is the same as [`read`], except that ANSI escape sequences are removed from the output
+    /// before it is decoded. This is useful for parsing the output of programs that emit colors
+    /// even if their stdout is not a terminal. The following sequences are removed:
+    ///
+    /// - CSI sequences like colors (`ESC [ 31 m`) or cursor movements (`ESC [ 2 A`).
+    /// - OSC sequences like hyperlinks or window titles (`ESC ]` up to `BEL` or `ESC \`).
+    /// - Other escape sequences like character set selections (`ESC ( B`).
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let mut pipe_cmd = cmd!("printf", "\\033[1;31merror\\033[0m" => "cat");
+    /// let output = pipe_cmd.output_string_stripped()?;
+    /// assert_eq!(output, "error");
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`read`]: Self::read
+    /// [`commands`]: Self::commands
+    pub fn output_string_stripped(&mut self) -> io::Result<String> {
+        self.read_with(|stdout| utf8(ansi::strip(&stdout)))
+    }
+
+    /// Returns the stdout of the last command decoded from `encoding` as a [`String`].
+    ///
+    /// This is the same as [`read`], except that the output is decoded from `encoding` instead of
+    /// UTF-8, which is useful for programs that emit text in a legacy encoding like Latin-1. A
+    /// byte order mark at the start of the output is not treated specially.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the errors returned by [`read`], an error of kind
+    /// [`io::ErrorKind::InvalidData`] is returned if the output is malformed in `encoding`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::{cmd, PipeCommand};
+    /// use procmd::encoding_rs::WINDOWS_1252;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let mut pipe_cmd = PipeCommand::new([cmd!("printf", r"caf\351")]);
+    /// assert_eq!(pipe_cmd.output_string_with(WINDOWS_1252)?, "café");
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`read`]: Self::read
+    /// [`commands`]: Self::commands
+    #[cfg(feature = "encoding")]
+    pub fn output_string_with(
+        &mut self,
+        encoding: &'static encoding_rs::Encoding,
+    ) -> io::Result<String> {
+        self.read_with(|stdout| {
+            encoding
+                .decode_without_bom_handling_and_without_replacement(&stdout)
+                .map(|stdout| stdout.into_owned())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("stdout is not valid {}", encoding.name()),
+                    )
+                })
+        })
+    }
+
+    /// Implements [`read`](Self::read), decoding the stdout with `f`.
+    fn read_with<F>(&mut self, f: F) -> io::Result<String>
+    where
+        F: FnOnce(Vec<u8>) -> io::Result<String>,
+    {
+        let (children, output) = self.spawn_with(|command| {
+            pipeline::with_piped_tail(command, Tail::Stdout, Command::output)
+        })?;
+        let mut statuses = self.wait_all(children)?;
+        statuses.push(output.status);
+        self.require_success(&statuses.try_into().unwrap())
+            .map_err(io::Error::other)?;
+        let stdout = f(output.stdout)?;
+        Ok(stdout.trim_end_matches(['\n', '\r']).to_owned())
+    }
+
+    /// Runs the pipeline and returns a detailed report, or an error if the pipeline failed.
+    ///
+    /// This is the same as [`run_detailed`], except that a failure is reported like in
+    /// [`read`]. It is the equivalent of `run` in the `duct` crate.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// [`run_detailed`]: Self::run_detailed
+    /// [`read`]: Self::read
+    /// [`commands`]: Self::commands
+    pub fn run(&mut self) -> io::Result<PipeReport> {
+        let report = self.run_detailed()?;
+        let statuses = report
+            .stages
+            .iter()
+            .map(|stage| stage.status)
+            .collect::<Vec<_>>()
+            .try_into()
+            .unwrap();
+        self.require_success(&statuses).map_err(io::Error::other)?;
+        Ok(report)
+    }
+
+    /// Runs the pipeline and returns the [`Output`] of the last command, or panics if the pipeline
+    /// failed.
+    ///
+    /// This method is intended for build scripts (`build.rs`), where a failed command should abort
+    /// the build with a readable error and returning a [`Result`] is less ergonomic. It should not
+    /// be used in libraries. The panic message contains the whole pipeline as a shell command
+    /// line, the command that failed and its exit code, and the captured stderr of the last
+    /// command. The stderr of the other commands is inherited, so Cargo shows it as well.
+    ///
+    /// Whether the pipeline failed is determined like in [`require_success`]. The stdout of the
+    /// last command is captured and returned, so a build script can e.g. read the output of
+    /// `git rev-parse HEAD` without it being interpreted as instructions for Cargo.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the pipeline fails, if spawning or waiting for any of the commands
+    /// fails, or if [`commands`] is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use procmd::cmd;
+    ///
+    /// let output = cmd!("git", "rev-parse", "HEAD" => "cut", "-c", "1-8").run_or_panic();
+    /// let hash = String::from_utf8_lossy(&output.stdout);
+    /// println!("cargo:rustc-env=GIT_HASH={}", hash.trim());
+    /// ```
+    ///
+    /// [`require_success`]: Self::require_success
+    /// [`commands`]: Self::commands
+    #[track_caller]
+    pub fn run_or_panic(&mut self) -> Output {
+        let result = self
+            .spawn_with(|command| pipeline::with_piped_tail(command, Tail::Stdout, Command::output))
+            .and_then(|(children, output)| Ok((self.wait_all(children)?, output)));
+        let (mut statuses, output) = match result {
+            Ok(result) => result,
+            Err(e) => panic!("failed to run `{}`: {}", shell::render(&self.commands), e),
+        };
+        statuses.push(output.status);
+        if let Err(e) = self.require_success(&statuses.try_into().unwrap()) {
+            panic!(
+                "command failed: `{}`\n{} (exit code {})\n--- stderr of `{}` ---\n{}",
+                shell::render(&self.commands),
+                e,
+                pipeline::exit_code(e.status),
+                self.commands[N - 1].get_program().to_string_lossy(),
+                String::from_utf8_lossy(&output.stderr).trim_end(),
+            );
+        }
+        output
+    }
+
+    /// Spawns all commands, waits for them to exit and returns `true` if every command succeeded.
+    ///
+    /// Exit codes allowed with [`allow_failure`] and [`allow_exit_codes`] are considered a
+    /// success. In contrast to [`require_success`], all commands are checked regardless of
+    /// whether pipefail mode is enabled.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// [`allow_failure`]: Self::allow_failure
+    /// [`allow_exit_codes`]: Self::allow_exit_codes
+    /// [`require_success`]: Self::require_success
+    /// [`commands`]: Self::commands
+    pub fn all_success(&mut self) -> io::Result<bool> {
+        let statuses = self.statuses()?;
+        Ok(statuses
+            .iter()
+            .enumerate()
+            .all(|(i, status)| self.is_success(i, *status)))
+    }
+
+    /// Runs the pipeline and calls `f` with the index and the [`ExitStatus`] of every command as
+    /// soon as it exits.
+    ///
+    /// This is useful for showing the progress of a long-running pipeline. Note that `f` is called
+    /// in the order in which the commands exit, not in the order of their indices, e.g. the first
+    /// command often exits before the last command has processed all of its input. The exit status
+    /// of a command is detected within a few milliseconds after it exited.
+    ///
+    /// Returns the [`ExitStatus`] of every command (in the order of the indices), like
+    /// [`statuses`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use procmd::cmd;
+    ///
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let mut pipe_cmd = cmd!("ls" => "wc", "-l");
+    /// pipe_cmd.on_exit(|i, status| eprintln!("command {} exited with {}", i, status))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`statuses`]: Self::statuses
+    /// [`commands`]: Self::commands
+    pub fn on_exit<F>(&mut self, f: F) -> io::Result<[ExitStatus; N]>
+    where
+        F: FnMut(usize, ExitStatus),
+    {
+        let (mut children, child) = self.spawn_with(|command| command.spawn())?;
+        children.push(child);
+        let statuses = pipeline::wait_each(children, f)?;
+        self.join_stderr();
+        Ok(statuses.try_into().unwrap())
+    }
+
+    /// Returns the [`ExitStatus`] of every command.
+    ///
+    /// This method spawns all commands and waits for all of them to exit.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::TimedOut`] is returned if a command exceeded its time
+    /// limit (see [`stage_timeout`]).
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`commands`] is empty.
+    ///
+    /// [`stage_timeout`]: Self::stage_timeout
+    /// [`commands`]: Self::commands
+    pub fn statuses(&mut self) -> io::Result<[ExitStatus; N]> {
+        if self.timeouts.iter().all(Option::is_none) {
+            let (children, status) = self.spawn_with(|command| command.status())?;
+            let mut statuses = self.wait_all(children)?;
+            statuses.push(status);
+            return Ok(statuses.try_into().unwrap());
+        }
+        let start = Instant::now();
+        let deadlines = self.deadlines(start);
+        let (mut children, child) = self.spawn_with(|command| command.spawn())?;
+        children.push(child);
+        let results = pipeline::wait_within(&self.commands, children, start, &deadlines)?;
+        self.join_stderr();
+        let statuses = results
+            .into_iter()
+            .map(|(status, _)| status)
+            .collect::<Vec<_>>();
+        Ok(statuses.try_into().unwrap())
+    }
+}
+
+impl<const N: usize> Pipeline for PipeCommand<N> {
+    fn commands(&self) -> &[Command] {
+        &self.commands
+    }
+
+    fn commands_mut(&mut self) -> &mut [Command] {
+        &mut self.commands
+    }
+
+    fn spawn(&mut self) -> io::Result<Child> {
+        PipeCommand::spawn(self)
+    }
+
+    fn output(&mut self) -> io::Result<Output> {
+        PipeCommand::output(self)
+    }
+
+    fn status(&mut self) -> io::Result<ExitStatus> {
+        PipeCommand::status(self)
+    }
+}
+
+/// Decodes the stdout of a command as UTF-8.
+fn utf8(stdout: Vec<u8>) -> io::Result<String> {
+    String::from_utf8(stdout).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// How long the output of a killed pipeline is still collected (see
+/// [`PipeCommand::output_timeout`]).
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Reads a stream in a separate thread, so the data that was read so far is available at any
+/// time.
+struct Capture {
+    buf: Arc<Mutex<Vec<u8>>>,
+    done: Receiver<io::Result<()>>,
+}
+
+impl Capture {
+    fn new<R: Read + Send + 'static>(mut reader: R, capacity: usize) -> Self {
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let (tx, done) = mpsc::channel();
+        let shared = Arc::clone(&buf);
+        thread::spawn(move || {
+            let mut chunk = vec![0; capacity];
+            let result = loop {
+                match reader.read(&mut chunk) {
+                    Ok(0) => break Ok(()),
+                    Ok(len) => shared
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .extend_from_slice(&chunk[..len]),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => break Err(e),
+                }
+            };
+            let _ = tx.send(result);
+        });
+        Self { buf, done }
+    }
+
+    /// Waits until the stream is closed and returns all data.
+    fn finish(self) -> io::Result<Vec<u8>> {
+        self.done.recv().unwrap_or(Ok(()))?;
+        Ok(self.take())
+    }
+
+    /// Waits at most `timeout` until the stream is closed and returns the data read so far.
+    fn finish_within(self, timeout: Duration) -> Vec<u8> {
+        let _ = self.done.recv_timeout(timeout);
+        self.take()
+    }
+
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.buf.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
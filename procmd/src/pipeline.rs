@@ -0,0 +1,720 @@
+use crate::boundary::{self, Hooks};
+#[cfg(unix)]
+use crate::nice::AutoNice;
+use crate::{PartialReport, PipeReport, StageReport};
+use std::cmp;
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::process::{Child, ChildStdout, Command, ExitStatus, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// The interval in which children are polled while waiting with a deadline.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The capacity of the buffer that the data between two commands is copied through if hooks are
+/// invoked on it or if it's passed through a filter of a [`HybridPipeline`].
+///
+/// [`HybridPipeline`]: crate::HybridPipeline
+pub(crate) const MEDIATED_CAPACITY: usize = 8 * 1024;
+
+/// A common interface for [`PipeCommand`] and [`DynPipeCommand`].
+///
+/// This trait can be used to write functions that accept any kind of pipeline, regardless of
+/// whether the number of commands is known at compile time.
+///
+/// Both kinds of pipelines determine the exit status of the pipeline the same way, respecting
+/// pipefail mode and the allowed exit codes. However, the methods of this trait only use the
+/// configuration that the pipeline supports: a [`PipeCommand`] applies all of its settings (e.g.
+/// its [stdin policy], which connects the stdin to the null device in [`output`](Self::output)
+/// and [`status`](Self::status) by default, and [`error_on_failure`]), while a [`DynPipeCommand`]
+/// leaves the stdin of the first command as it's configured, so it's inherited by default.
+///
+/// # Object safety
+///
+/// All methods of this trait take `&self` or `&mut self` and have no generic parameters, so the
+/// trait is object safe and `&mut dyn Pipeline` can be used as well.
+///
+/// # Examples
+///
+/// ```rust
+/// use procmd::{cmd, DynPipeCommand, Pipeline};
+///
+/// fn line_count(pipeline: &mut impl Pipeline) -> std::io::Result<usize> {
+///     let output = pipeline.output()?;
+///     Ok(output.stdout.split(|b| *b == b'\n').count() - 1)
+/// }
+///
+/// # fn main() -> Result<(), std::io::Error> {
+/// let mut pipe_cmd = cmd!("ls" => "grep", "example");
+/// let count = line_count(&mut pipe_cmd)?;
+///
+/// let mut dyn_pipe_cmd = DynPipeCommand::new(vec![cmd!("ls"), cmd!("grep", "example")]);
+/// let count = line_count(&mut dyn_pipe_cmd)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`PipeCommand`]: crate::PipeCommand
+/// [`DynPipeCommand`]: crate::DynPipeCommand
+/// [stdin policy]: crate::PipeCommand::set_stdin_inherit_policy
+/// [`error_on_failure`]: crate::PipeCommand::error_on_failure
+pub trait Pipeline {
+    /// Returns the commands of the pipeline.
+    fn commands(&self) -> &[Command];
+
+    /// Returns the commands of the pipeline mutably.
+    fn commands_mut(&mut self) -> &mut [Command];
+
+    /// Returns the number of commands in the pipeline.
+    fn len(&self) -> usize {
+        self.commands().len()
+    }
+
+    /// Returns `true` if the pipeline contains no commands.
+    fn is_empty(&self) -> bool {
+        self.commands().is_empty()
+    }
+
+    /// Returns the command at `index`, or `None` if `index` is out of bounds.
+    fn command(&self, index: usize) -> Option<&Command> {
+        self.commands().get(index)
+    }
+
+    /// Spawns all commands and returns the [`Child`] of the last command.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the pipeline is empty.
+    fn spawn(&mut self) -> io::Result<Child>;
+
+    /// Returns the [`Output`] of the last command, with the exit status of the whole pipeline.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the pipeline is empty.
+    fn output(&mut self) -> io::Result<Output>;
+
+    /// Returns the [`ExitStatus`] of the whole pipeline.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the pipeline is empty.
+    fn status(&mut self) -> io::Result<ExitStatus>;
+}
+
+/// The maximum number of file descriptors that are open at the same time while spawning a
+/// command.
+///
+/// These are the read end of the previous pipe, both ends of the next pipe, the pipe that the
+/// standard library uses to report errors of `exec` and up to three pipes for capturing the
+/// output of the last command.
+#[cfg(unix)]
+const FDS_PER_SPAWN: u64 = 8;
+
+/// Options for spawning the commands of a pipeline.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SpawnOptions {
+    /// The time to wait between spawning two consecutive commands.
+    pub(crate) spawn_delay: Duration,
+    /// The maximum number of commands.
+    pub(crate) max_commands: Option<usize>,
+    /// Whether to check that enough file descriptors are available before spawning.
+    #[cfg(unix)]
+    pub(crate) check_fd_limit: bool,
+    /// Whether the priority of the commands is lowered if the system load is high.
+    #[cfg(unix)]
+    pub(crate) auto_nice: Option<AutoNice>,
+    /// Whether the data between two commands is copied by a thread of the current process.
+    pub(crate) mediated: bool,
+    /// The hooks of the connections between two commands, where the connection at index `i` is
+    /// the one between the commands at index `i` and `i + 1`.
+    ///
+    /// Connections with hooks are always copied by a thread of the current process.
+    pub(crate) hooks: BTreeMap<usize, Hooks>,
+}
+
+impl SpawnOptions {
+    /// Returns an error if the limits configured by the options would be exceeded by spawning
+    /// `len` commands.
+    fn check(&self, len: usize) -> io::Result<()> {
+        if let Some(max) = self.max_commands {
+            check_max_commands(len, max)?;
+        }
+        #[cfg(unix)]
+        if self.check_fd_limit {
+            check_fd_limit()?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns an error of kind [`io::ErrorKind::InvalidInput`] if `len` commands are more than `max`.
+pub(crate) fn check_max_commands(len: usize, max: usize) -> io::Result<()> {
+    if len > max {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "the pipeline contains {} commands, but at most {} are allowed",
+                len, max
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Returns an error if fewer than [`FDS_PER_SPAWN`] file descriptors can be opened.
+///
+/// The limit is queried with `getrlimit(RLIMIT_NOFILE)` and the open file descriptors are counted
+/// by listing `/dev/fd`.
+#[cfg(unix)]
+fn check_fd_limit() -> io::Result<()> {
+    use nix::sys::resource::{self, Resource};
+
+    let (limit, _) = resource::getrlimit(Resource::RLIMIT_NOFILE)?;
+    // Reading the directory opens a file descriptor itself, which is included in the count.
+    let open = std::fs::read_dir("/dev/fd")?.count() as u64;
+    if limit.saturating_sub(open) < FDS_PER_SPAWN {
+        return Err(io::Error::other(format!(
+            "too many open files to spawn the pipeline: {} of {} file descriptors are in use, but \
+             spawning requires up to {} more",
+            open, limit, FDS_PER_SPAWN
+        )));
+    }
+    Ok(())
+}
+
+/// Spawns all commands except the last one and calls `f` on the last command.
+///
+/// The stdout of every command is connected to the stdin of the next command. The spawned
+/// children are returned together with the result of `f`. If spawning fails, the children that
+/// were already spawned are killed.
+///
+/// # Panics
+///
+/// This function panics if `commands` is empty.
+pub(crate) fn run<F, U>(
+    commands: &mut [Command],
+    options: &SpawnOptions,
+    f: F,
+) -> io::Result<(Vec<Child>, U)>
+where
+    F: FnOnce(&mut Command) -> io::Result<U>,
+{
+    run_partial(commands, options, f).map_err(|(e, children)| {
+        kill_all(children);
+        e
+    })
+}
+
+/// Like [`run`], but if spawning fails, the children that were already spawned are returned
+/// together with the error instead of being killed.
+pub(crate) fn run_partial<F, U>(
+    commands: &mut [Command],
+    options: &SpawnOptions,
+    f: F,
+) -> Result<(Vec<Child>, U), (io::Error, Vec<Child>)>
+where
+    F: FnOnce(&mut Command) -> io::Result<U>,
+{
+    options.check(commands.len()).map_err(|e| (e, Vec::new()))?;
+    #[cfg(unix)]
+    if let Some(auto_nice) = &options.auto_nice {
+        auto_nice.update();
+    }
+    let (last, rest) = commands
+        .split_last_mut()
+        .expect("pipeline must contain at least one command");
+    let mut children = Vec::with_capacity(rest.len());
+    let mut stdout: Option<Stdio> = None;
+    for (i, command) in rest.iter_mut().enumerate() {
+        if let Some(stdout) = stdout.take() {
+            command.stdin(stdout);
+        }
+        if i > 0 && !options.spawn_delay.is_zero() {
+            thread::sleep(options.spawn_delay);
+        }
+        command.stdout(Stdio::piped());
+        let result = command.spawn();
+        if i > 0 {
+            // Drop the read end of the previous pipe that is still owned by the command.
+            command.stdin(Stdio::null());
+        }
+        match result {
+            Ok(mut child) => {
+                let child_stdout = child.stdout.take().unwrap();
+                children.push(child);
+                stdout = Some(if options.mediated || options.hooks.contains_key(&i) {
+                    match mediate(child_stdout, options.hooks.get(&i).cloned()) {
+                        Ok(stdout) => stdout,
+                        Err(e) => return Err((e, children)),
+                    }
+                } else {
+                    child_stdout.into()
+                });
+            }
+            Err(e) => return Err((e, children)),
+        }
+    }
+    if let Some(stdout) = stdout {
+        last.stdin(stdout);
+    }
+    if !rest.is_empty() && !options.spawn_delay.is_zero() {
+        thread::sleep(options.spawn_delay);
+    }
+    let result = f(last);
+    if !rest.is_empty() {
+        last.stdin(Stdio::null());
+    }
+    match result {
+        Ok(value) => Ok((children, value)),
+        Err(e) => Err((e, children)),
+    }
+}
+
+/// Copies the data of `stdout` to a new pipe in a separate thread, invoking `hooks` if there are
+/// any, and returns the read end of the pipe.
+///
+/// The thread exits once `stdout` is closed or the read end of the pipe is closed, in which case
+/// `stdout` is closed as well, so the command that writes to it receives `SIGPIPE` (or an error).
+fn mediate(mut stdout: ChildStdout, hooks: Option<Hooks>) -> io::Result<Stdio> {
+    let (reader, mut writer) = io::pipe()?;
+    thread::spawn(move || match hooks {
+        Some(hooks) => boundary::copy(stdout, writer, &hooks, MEDIATED_CAPACITY),
+        None => {
+            let _ = io::copy(&mut stdout, &mut writer);
+        }
+    });
+    Ok(reader.into())
+}
+
+/// The streams of the last command that are piped by [`with_piped_tail`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Tail {
+    /// Only the stdout is piped.
+    Stdout,
+    /// Both the stdout and the stderr are piped.
+    StdoutStderr,
+}
+
+/// Calls `f` on `command` with the streams selected by `tail` piped, and resets them afterwards.
+///
+/// A [`Command`] keeps its stdio configuration, so without the reset, a later call to e.g.
+/// [`Command::status`] would write the output to a pipe that nobody reads. The configuration
+/// can't be read back from a [`Command`], so the streams are reset to [`Stdio::inherit`], which
+/// is the default of [`Command::spawn`] and [`Command::status`].
+pub(crate) fn with_piped_tail<F, U>(command: &mut Command, tail: Tail, f: F) -> io::Result<U>
+where
+    F: FnOnce(&mut Command) -> io::Result<U>,
+{
+    command.stdout(Stdio::piped());
+    if tail == Tail::StdoutStderr {
+        command.stderr(Stdio::piped());
+    }
+    let result = f(command);
+    command.stdout(Stdio::inherit());
+    if tail == Tail::StdoutStderr {
+        command.stderr(Stdio::inherit());
+    }
+    result
+}
+
+/// Waits for all `children` to exit.
+///
+/// Every child is waited for even if waiting for an earlier one failed, so no child is left as a
+/// zombie. The first error is returned.
+pub(crate) fn wait_all(children: Vec<Child>) -> io::Result<Vec<ExitStatus>> {
+    let results: Vec<_> = children.into_iter().map(|mut child| child.wait()).collect();
+    results.into_iter().collect()
+}
+
+/// Kills and reaps all `children`, ignoring any errors.
+pub(crate) fn kill_all(children: Vec<Child>) {
+    for mut child in children {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// Waits for all `children` to exit until `deadline` is reached.
+///
+/// Returns `None` if at least one child is still running at the deadline.
+pub(crate) fn wait_until(
+    children: &mut [Child],
+    deadline: Instant,
+) -> io::Result<Option<Vec<ExitStatus>>> {
+    loop {
+        let statuses = children
+            .iter_mut()
+            .map(Child::try_wait)
+            .collect::<io::Result<Option<Vec<_>>>>()?;
+        if statuses.is_some() {
+            return Ok(statuses);
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(cmp::min(POLL_INTERVAL, deadline - now));
+    }
+}
+
+/// Waits for all `children` to exit, where the child at index `i` has to exit before
+/// `deadlines[i]`.
+///
+/// Returns `Ok(Err(i))` if the child at index `i` is still running at its deadline. The children
+/// are not killed in this case.
+pub(crate) fn wait_deadlines(
+    children: &mut [Child],
+    deadlines: &[Option<Instant>],
+) -> io::Result<Result<Vec<ExitStatus>, usize>> {
+    Ok(wait_deadlines_timed(children, deadlines)?
+        .map(|results| results.into_iter().map(|(status, _)| status).collect()))
+}
+
+/// Like [`wait_deadlines`], but the exit status of every child is returned together with the
+/// time at which the child was first seen to have exited.
+pub(crate) fn wait_deadlines_timed(
+    children: &mut [Child],
+    deadlines: &[Option<Instant>],
+) -> io::Result<Result<Vec<(ExitStatus, Instant)>, usize>> {
+    let mut results = vec![None; children.len()];
+    loop {
+        let now = Instant::now();
+        let mut next: Option<Instant> = None;
+        for (i, child) in children.iter_mut().enumerate() {
+            if results[i].is_some() {
+                continue;
+            }
+            match child.try_wait()? {
+                Some(status) => results[i] = Some((status, now)),
+                None => match deadlines[i] {
+                    Some(deadline) if now >= deadline => return Ok(Err(i)),
+                    Some(deadline) => next = Some(next.map_or(deadline, |n| n.min(deadline))),
+                    None => {}
+                },
+            }
+        }
+        if results.iter().all(Option::is_some) {
+            return Ok(Ok(results.into_iter().map(Option::unwrap).collect()));
+        }
+        thread::sleep(next.map_or(POLL_INTERVAL, |next| cmp::min(POLL_INTERVAL, next - now)));
+    }
+}
+
+/// Like [`wait_deadlines_timed`], but if a child is still running after its deadline, all
+/// children are killed and an error of kind [`io::ErrorKind::TimedOut`] is returned that names the
+/// command of `commands` at the same index. The deadlines are relative to `start`.
+///
+/// If waiting fails, all children are killed.
+pub(crate) fn wait_within(
+    commands: &[Command],
+    mut children: Vec<Child>,
+    start: Instant,
+    deadlines: &[Option<Instant>],
+) -> io::Result<Vec<(ExitStatus, Instant)>> {
+    match wait_deadlines_timed(&mut children, deadlines) {
+        Ok(Ok(results)) => Ok(results),
+        Ok(Err(index)) => {
+            kill_all(children);
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "command `{}` at index {} didn't exit within {:?}",
+                    commands[index].get_program().to_string_lossy(),
+                    index,
+                    deadlines[index].unwrap() - start
+                ),
+            ))
+        }
+        Err(e) => {
+            kill_all(children);
+            Err(e)
+        }
+    }
+}
+
+/// Returns the deadline of every command with one of `timeouts` if the pipeline was spawned at
+/// `start`.
+pub(crate) fn deadlines(timeouts: &[Option<Duration>], start: Instant) -> Vec<Option<Instant>> {
+    timeouts
+        .iter()
+        .map(|timeout| timeout.map(|timeout| start + timeout))
+        .collect()
+}
+
+/// Waits for all `children` to exit and calls `f` with the index and the exit status of every
+/// child as soon as it exits.
+///
+/// If waiting fails, all children are killed.
+pub(crate) fn wait_each<F>(mut children: Vec<Child>, mut f: F) -> io::Result<Vec<ExitStatus>>
+where
+    F: FnMut(usize, ExitStatus),
+{
+    let mut statuses = vec![None; children.len()];
+    while statuses.iter().any(Option::is_none) {
+        for (i, child) in children.iter_mut().enumerate() {
+            if statuses[i].is_some() {
+                continue;
+            }
+            match child.try_wait() {
+                Ok(Some(status)) => {
+                    statuses[i] = Some(status);
+                    f(i, status);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    kill_all(children);
+                    return Err(e);
+                }
+            }
+        }
+        if statuses.iter().any(Option::is_none) {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+    Ok(statuses.into_iter().map(Option::unwrap).collect())
+}
+
+/// The exit statuses of a command that are not considered a failure.
+#[derive(Debug, Clone)]
+pub(crate) enum AllowedExit {
+    Any,
+    Codes(Vec<i32>),
+}
+
+impl AllowedExit {
+    fn permits(&self, status: ExitStatus) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Codes(codes) => status.code().is_some_and(|code| codes.contains(&code)),
+        }
+    }
+}
+
+/// Decides whether the commands of a pipeline failed and what the exit status of the pipeline is.
+///
+/// This is shared by all kinds of pipelines, so they agree on [pipefail mode], the allowed exit
+/// codes and the handling of `SIGPIPE`.
+///
+/// [pipefail mode]: crate::PipeCommand::pipefail
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ExitPolicy {
+    /// Whether every command is checked instead of only the last one.
+    pub(crate) pipefail: bool,
+    /// The exit statuses that are not considered a failure, by the index of the command.
+    pub(crate) allowed: BTreeMap<usize, AllowedExit>,
+}
+
+impl ExitPolicy {
+    /// Returns `true` if `status` is considered a success for the command at `index` of a
+    /// pipeline with `len` commands.
+    ///
+    /// A command other than the last one that was killed by `SIGPIPE` never failed, since this
+    /// only means that a later command stopped reading its input early.
+    pub(crate) fn is_success(&self, index: usize, len: usize, status: ExitStatus) -> bool {
+        if index < len - 1 && is_broken_pipe(status) {
+            return true;
+        }
+        match self.allowed.get(&index) {
+            Some(allowed) => allowed.permits(status),
+            None => status.success(),
+        }
+    }
+
+    /// Returns the index of the last command from index `first` on that failed.
+    pub(crate) fn failure_from(&self, statuses: &[ExitStatus], first: usize) -> Option<usize> {
+        (first..statuses.len())
+            .rev()
+            .find(|i| !self.is_success(*i, statuses.len(), statuses[*i]))
+    }
+
+    /// Returns the index of the command that made the pipeline fail, i.e. the last command that
+    /// failed in pipefail mode and otherwise the last command if it failed.
+    pub(crate) fn failure(&self, statuses: &[ExitStatus]) -> Option<usize> {
+        let first = if self.pipefail { 0 } else { statuses.len() - 1 };
+        self.failure_from(statuses, first)
+    }
+
+    /// Returns the exit status of the whole pipeline, which is the exit status of the command
+    /// returned by [`failure`](Self::failure), or of the last command if the pipeline succeeded.
+    pub(crate) fn status(&self, statuses: &[ExitStatus]) -> ExitStatus {
+        let index = self.failure(statuses).unwrap_or(statuses.len() - 1);
+        statuses[index]
+    }
+}
+
+/// The configuration of a pipeline that is needed to wait for it and report on its commands.
+///
+/// This is shared by all kinds of pipelines, so their detailed reports are built the same way.
+pub(crate) struct Reporter<'a> {
+    /// The commands of the pipeline.
+    pub(crate) commands: &'a [Command],
+    /// The label of every command.
+    pub(crate) labels: &'a [Option<String>],
+    /// The time limit of every command.
+    pub(crate) timeouts: &'a [Option<Duration>],
+    /// The policy that decides whether the commands failed.
+    pub(crate) exit_policy: &'a ExitPolicy,
+}
+
+impl Reporter<'_> {
+    /// Waits for the pipeline that was spawned at `start` (`started_at` in system time) and
+    /// returns a detailed report.
+    ///
+    /// `spawned` is the result of [`run_partial`], where the stdout and stderr of the last child
+    /// are piped. If spawning failed, the children that were already spawned are killed and an
+    /// error containing a [`PartialReport`] is returned. If a command exceeds its time limit, all
+    /// commands are killed (see [`wait_within`]).
+    pub(crate) fn report(
+        &self,
+        start: Instant,
+        started_at: SystemTime,
+        spawned: Result<(Vec<Child>, Child), (io::Error, Vec<Child>)>,
+    ) -> io::Result<PipeReport> {
+        let (mut children, mut child) = match spawned {
+            Ok(spawned) => spawned,
+            Err((error, children)) => return Err(self.partial_report(start, children, error)),
+        };
+        let readers = [
+            read_to_end(child.stdout.take().unwrap()),
+            read_to_end(child.stderr.take().unwrap()),
+        ];
+        children.push(child);
+        let deadlines = deadlines(self.timeouts, start);
+        let results = wait_within(self.commands, children, start, &deadlines)?;
+        let finished_at = SystemTime::now();
+        let [stdout, stderr] = readers.map(|reader| reader.join().unwrap());
+
+        let statuses = results
+            .iter()
+            .map(|(status, _)| *status)
+            .collect::<Vec<_>>();
+        let stages = results
+            .iter()
+            .enumerate()
+            .map(|(i, (status, end))| self.stage_report(i, *status, end.duration_since(start)))
+            .collect();
+        let duration = results
+            .iter()
+            .map(|(_, end)| end.duration_since(start))
+            .max()
+            .unwrap();
+        Ok(PipeReport {
+            stages,
+            output: Output {
+                status: self.exit_policy.status(&statuses),
+                stdout: stdout?,
+                stderr: stderr?,
+            },
+            success: self.exit_policy.failure(&statuses).is_none(),
+            duration,
+            started_at,
+            finished_at,
+        })
+    }
+
+    /// Kills and reaps `children`, which were spawned before spawning the next command failed
+    /// with `error`, and returns an error containing a [`PartialReport`].
+    fn partial_report(&self, start: Instant, children: Vec<Child>, error: io::Error) -> io::Error {
+        let failed = children.len();
+        let mut stages = children
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut child)| {
+                let _ = child.kill();
+                let status = child.wait().ok()?;
+                Some(self.stage_report(i, status, start.elapsed()))
+            })
+            .collect::<Vec<_>>();
+        stages.resize_with(self.commands.len(), || None);
+        io::Error::new(
+            error.kind(),
+            PartialReport {
+                stages,
+                failed,
+                error,
+            },
+        )
+    }
+
+    /// Returns the report of the command at `index` that exited with `status` after `duration`.
+    fn stage_report(&self, index: usize, status: ExitStatus, duration: Duration) -> StageReport {
+        let command = &self.commands[index];
+        StageReport {
+            label: self.labels[index].clone(),
+            program: command.get_program().to_owned(),
+            argv: std::iter::once(command.get_program())
+                .chain(command.get_args())
+                .map(ToOwned::to_owned)
+                .collect(),
+            status,
+            success: self
+                .exit_policy
+                .is_success(index, self.commands.len(), status),
+            duration,
+        }
+    }
+}
+
+/// Reads `reader` to the end in a new thread.
+fn read_to_end<R: Read + Send + 'static>(mut reader: R) -> thread::JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        Ok(buf)
+    })
+}
+
+/// Returns `true` if `status` indicates that the command was killed by `SIGPIPE`.
+pub(crate) fn is_broken_pipe(status: ExitStatus) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+
+        status.signal() == Some(nix::sys::signal::Signal::SIGPIPE as i32)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = status;
+        false
+    }
+}
+
+/// Returns the exit code of `status` like a shell would report it in `$?`.
+///
+/// On Unix, a process that was killed by a signal is reported as `128 + signal`.
+pub(crate) fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
+
+/// Asks `child` to terminate.
+///
+/// On Unix, `SIGTERM` is sent to the child. On other platforms, the child is killed.
+pub(crate) fn terminate(child: &mut Child) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use nix::sys::signal::{self, Signal};
+        use nix::unistd::Pid;
+
+        if child.try_wait()?.is_some() {
+            return Ok(());
+        }
+        match signal::kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM) {
+            Ok(()) | Err(nix::errno::Errno::ESRCH) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        child.kill()
+    }
+}
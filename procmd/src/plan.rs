@@ -0,0 +1,25 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// What is passed to the operating system to spawn a command.
+///
+/// This is returned by [`PipeCommand::plan`].
+///
+/// [`PipeCommand::plan`]: crate::PipeCommand::plan
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecPlan {
+    /// The absolute path of the program (see [`PipeCommand::resolved_program`]).
+    ///
+    /// [`PipeCommand::resolved_program`]: crate::PipeCommand::resolved_program
+    pub program: PathBuf,
+    /// The program followed by the arguments (see [`PipeCommand::argv_of`]).
+    ///
+    /// [`PipeCommand::argv_of`]: crate::PipeCommand::argv_of
+    pub argv: Vec<OsString>,
+    /// All environment variables, sorted by their names.
+    ///
+    /// This includes the inherited variables of the current process.
+    pub env: Vec<(OsString, OsString)>,
+    /// The absolute path of the working directory.
+    pub cwd: PathBuf,
+}
@@ -0,0 +1,23 @@
+/// Configuration for the methods that read the stdout of a pipeline while it is running.
+///
+/// This is used by methods like [`PipeCommand::for_each_line`] and can be set with
+/// [`PipeCommand::reader_config`].
+///
+/// [`PipeCommand::for_each_line`]: crate::PipeCommand::for_each_line
+/// [`PipeCommand::reader_config`]: crate::PipeCommand::reader_config
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReaderConfig {
+    /// The capacity of the buffer that the stdout is read into.
+    ///
+    /// The default is 8 KiB, which matches the default of [`std::io::BufReader`]. A larger buffer
+    /// means fewer read system calls and can increase the throughput for commands with a lot of
+    /// output, at the cost of memory and of a higher latency until data is delivered when the
+    /// output is consumed in chunks.
+    pub capacity: usize,
+}
+
+impl Default for ReaderConfig {
+    fn default() -> Self {
+        Self { capacity: 8 * 1024 }
+    }
+}
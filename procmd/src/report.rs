@@ -0,0 +1,165 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt;
+use std::io;
+use std::process::{ExitStatus, Output};
+use std::time::{Duration, SystemTime};
+
+/// A detailed report of a pipeline run.
+///
+/// This is returned by [`PipeCommand::run_detailed`] and [`DynPipeCommand::run_detailed`].
+///
+/// [`PipeCommand::run_detailed`]: crate::PipeCommand::run_detailed
+/// [`DynPipeCommand::run_detailed`]: crate::DynPipeCommand::run_detailed
+#[derive(Debug, Clone)]
+pub struct PipeReport {
+    /// The reports of the individual commands.
+    pub stages: Vec<StageReport>,
+    /// The output of the last command.
+    ///
+    /// The [`status`] is the exit status of the whole pipeline (see [`PipeCommand::status`]).
+    ///
+    /// [`status`]: Output::status
+    /// [`PipeCommand::status`]: crate::PipeCommand::status
+    pub output: Output,
+    /// Whether the pipeline succeeded (see [`PipeCommand::require_success`]).
+    ///
+    /// [`PipeCommand::require_success`]: crate::PipeCommand::require_success
+    pub success: bool,
+    /// The time it took until all commands exited.
+    ///
+    /// This is measured with the monotonic clock ([`Instant`]), so it's suitable for measuring
+    /// how long the pipeline ran.
+    ///
+    /// [`Instant`]: std::time::Instant
+    pub duration: Duration,
+    /// The system time at which the pipeline was spawned.
+    ///
+    /// In contrast to [`duration`], this is an absolute wall-clock time, which can be used to
+    /// correlate the run with external logs. Note that the system clock can be adjusted at any
+    /// time, so it should not be used to measure durations.
+    ///
+    /// [`duration`]: Self::duration
+    pub started_at: SystemTime,
+    /// The system time at which all commands had exited.
+    ///
+    /// This is read from the system clock when the pipeline finished, like [`started_at`] when it
+    /// was spawned. If the system clock was adjusted during the run, the difference between the
+    /// two isn't the [`duration`] and this can even be earlier than [`started_at`].
+    ///
+    /// [`started_at`]: Self::started_at
+    /// [`duration`]: Self::duration
+    pub finished_at: SystemTime,
+}
+
+/// A report of a single command of a pipeline run.
+#[derive(Debug, Clone)]
+pub struct StageReport {
+    /// The label of the command (see [`PipeCommand::label`]).
+    ///
+    /// [`PipeCommand::label`]: crate::PipeCommand::label
+    pub label: Option<String>,
+    /// The program of the command.
+    pub program: OsString,
+    /// The program followed by the arguments of the command (see [`PipeCommand::argv_of`]).
+    ///
+    /// [`PipeCommand::argv_of`]: crate::PipeCommand::argv_of
+    pub argv: Vec<OsString>,
+    /// The exit status of the command.
+    pub status: ExitStatus,
+    /// Whether the exit status is considered a success, taking the allowed exit codes into
+    /// account (see [`PipeCommand::allow_exit_codes`]).
+    ///
+    /// [`PipeCommand::allow_exit_codes`]: crate::PipeCommand::allow_exit_codes
+    pub success: bool,
+    /// The time from spawning the pipeline until the command exited.
+    pub duration: Duration,
+}
+
+/// A report of a pipeline run that failed because a command couldn't be spawned.
+///
+/// This is contained in the error returned by [`PipeCommand::run_detailed`] and
+/// [`DynPipeCommand::run_detailed`]. The commands that were spawned before the failure are
+/// killed, so their reports show how long they ran until then.
+///
+/// [`PipeCommand::run_detailed`]: crate::PipeCommand::run_detailed
+/// [`DynPipeCommand::run_detailed`]: crate::DynPipeCommand::run_detailed
+#[derive(Debug)]
+pub struct PartialReport {
+    /// The reports of the individual commands.
+    ///
+    /// This contains one element per command of the pipeline, which is `None` for the commands
+    /// that were not run.
+    pub stages: Vec<Option<StageReport>>,
+    /// The index of the command that couldn't be spawned.
+    ///
+    /// If spawning failed before any command was spawned (e.g. because of a limit set with
+    /// [`PipeCommand::max_commands`]), this is `0`.
+    ///
+    /// [`PipeCommand::max_commands`]: crate::PipeCommand::max_commands
+    pub failed: usize,
+    /// The error that occurred while spawning.
+    pub error: io::Error,
+}
+
+impl fmt::Display for PartialReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to spawn the command at index {}: {}",
+            self.failed, self.error
+        )
+    }
+}
+
+impl Error for PartialReport {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// The output that a pipeline produced before it was killed because of a time limit.
+///
+/// This is contained in the error returned by [`PipeCommand::output_timeout`]. It can be used to
+/// find out why a pipeline hung, e.g. by looking at the last lines it printed.
+///
+/// [`PipeCommand::output_timeout`]: crate::PipeCommand::output_timeout
+#[derive(Debug, Clone)]
+pub struct PartialOutput {
+    /// The stdout that the last command wrote until it was killed.
+    pub stdout: Vec<u8>,
+    /// The stderr that the last command wrote until it was killed.
+    pub stderr: Vec<u8>,
+    /// The index of the first command that was still running when its time limit was reached.
+    pub index: usize,
+}
+
+impl fmt::Display for PartialOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the command at index {} didn't exit within its time limit",
+            self.index
+        )
+    }
+}
+
+impl Error for PartialOutput {}
+
+/// The output of a whole pipeline.
+///
+/// This is returned by [`PipeCommand::output_full`].
+///
+/// [`PipeCommand::output_full`]: crate::PipeCommand::output_full
+#[derive(Debug, Clone)]
+pub struct FullOutput<const N: usize> {
+    /// The stdout of the last command.
+    ///
+    /// The stdout of the other commands is connected to the stdin of the next command, so it is
+    /// not contained in this field.
+    pub stdout: Vec<u8>,
+    /// The stderr of all commands, interleaved in the order in which it was written.
+    pub stderr: Vec<u8>,
+    /// The exit status of every command.
+    pub statuses: [ExitStatus; N],
+}
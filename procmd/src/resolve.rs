@@ -0,0 +1,113 @@
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+/// Returns the absolute path of the program that `command` runs.
+///
+/// A program that contains a path separator is resolved relative to the working directory of the
+/// command. Otherwise it's searched in the `PATH` configured for the command, or the `PATH` of the
+/// current process if the command doesn't override it.
+pub(crate) fn resolve(command: &Command) -> io::Result<PathBuf> {
+    let cwd = cwd(command)?;
+    let program = Path::new(command.get_program());
+    if program.components().count() > 1 {
+        let program = program
+            .components()
+            .filter(|component| *component != Component::CurDir)
+            .collect::<PathBuf>();
+        let path = cwd.join(&program);
+        return candidates(&path)
+            .into_iter()
+            .find(|path| is_executable(path))
+            .ok_or_else(|| not_found(program.as_os_str(), "does not exist"));
+    }
+    let path = match command.get_envs().find(|(key, _)| is_path_key(key)) {
+        Some((_, value)) => value.map(OsStr::to_owned),
+        None => env::var_os("PATH"),
+    };
+    env::split_paths(&path.unwrap_or_default())
+        .flat_map(|dir| candidates(&cwd.join(dir).join(program)))
+        .find(|path| is_executable(path))
+        .ok_or_else(|| not_found(program.as_os_str(), "was not found in PATH"))
+}
+
+/// Returns the absolute path of the working directory of `command`.
+pub(crate) fn cwd(command: &Command) -> io::Result<PathBuf> {
+    Ok(match command.get_current_dir() {
+        Some(dir) if dir.is_absolute() => dir.to_owned(),
+        Some(dir) => env::current_dir()?.join(dir),
+        None => env::current_dir()?,
+    })
+}
+
+/// Returns an error if the program of `command` is a relative path and `command` has a working
+/// directory in which the program doesn't exist.
+pub(crate) fn check_relative(command: &Command) -> io::Result<()> {
+    let program = Path::new(command.get_program());
+    if command.get_current_dir().is_none()
+        || program.is_absolute()
+        || program.components().count() <= 1
+    {
+        return Ok(());
+    }
+    let cwd = cwd(command)?;
+    if candidates(&cwd.join(program))
+        .iter()
+        .any(|path| is_executable(path))
+    {
+        return Ok(());
+    }
+    Err(not_found(
+        program.as_os_str(),
+        &format!(
+            "does not exist relative to the working directory `{}`",
+            cwd.display()
+        ),
+    ))
+}
+
+fn not_found(program: &OsStr, reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("program `{}` {}", program.to_string_lossy(), reason),
+    )
+}
+
+/// Returns `true` if `key` is the name of the `PATH` environment variable.
+fn is_path_key(key: &OsStr) -> bool {
+    if cfg!(windows) {
+        key.eq_ignore_ascii_case("PATH")
+    } else {
+        key == "PATH"
+    }
+}
+
+/// Returns the paths that are tried for `path`.
+///
+/// On Windows, `.exe` is appended if `path` has no extension.
+fn candidates(path: &Path) -> Vec<PathBuf> {
+    let mut candidates = vec![path.to_owned()];
+    if cfg!(windows) && path.extension().is_none() {
+        let mut exe = OsString::from(path);
+        exe.push(".exe");
+        candidates.push(exe.into());
+    }
+    candidates
+}
+
+/// Returns `true` if `path` is a file that can be executed.
+fn is_executable(path: &Path) -> bool {
+    match path.metadata() {
+        #[cfg(unix)]
+        Ok(metadata) => {
+            use std::os::unix::fs::PermissionsExt;
+
+            metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+        }
+        #[cfg(not(unix))]
+        Ok(metadata) => metadata.is_file(),
+        Err(_) => false,
+    }
+}
@@ -0,0 +1,62 @@
+use crate::pipeline::{self, ExitPolicy, SpawnOptions};
+use crate::{CommandError, DynPipeCommand};
+use std::io;
+use std::process::Stdio;
+
+/// Runs a pipeline given as a command line and returns the stdout of the last command.
+///
+/// The command line is parsed like a [`DynPipeCommand`] (see its [`FromStr`] implementation), so
+/// no shell is invoked: the words are split safely and there are no globs, variables or other
+/// expansions. The command line can contain at most [`DynPipeCommand::DEFAULT_MAX_COMMANDS`]
+/// commands. Trailing newlines are removed from the output and the stderr of all commands is
+/// inherited.
+///
+/// # Errors
+///
+/// An error is returned if the command line can't be parsed or contains too many commands (of
+/// kind [`io::ErrorKind::InvalidInput`]), if spawning or waiting for any of the commands fails, or if
+/// the output isn't valid UTF-8 (of kind [`io::ErrorKind::InvalidData`]). If any command fails,
+/// the rightmost command that failed is returned as a [`CommandError`], converted into an
+/// [`io::Error`] with [`io::Error::other`]. Like in [pipefail mode], a command other than the last
+/// one that was killed by `SIGPIPE` is not considered to have failed.
+///
+/// # Examples
+///
+/// ```rust
+/// # fn main() -> Result<(), std::io::Error> {
+/// let output = procmd::sh("echo 'hello world' | tr a-z A-Z")?;
+/// assert_eq!(output, "HELLO WORLD");
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`FromStr`]: std::str::FromStr
+/// [pipefail mode]: crate::PipeCommand::pipefail
+pub fn sh(command_line: &str) -> io::Result<String> {
+    let mut pipe_cmd = command_line
+        .parse::<DynPipeCommand>()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let (children, output) = pipeline::run(
+        &mut pipe_cmd.commands,
+        &SpawnOptions::default(),
+        |command| command.stdout(Stdio::piped()).output(),
+    )?;
+    let mut statuses = pipeline::wait_all(children)?;
+    statuses.push(output.status);
+    let exit_policy = ExitPolicy {
+        pipefail: true,
+        ..ExitPolicy::default()
+    };
+    if let Some(index) = exit_policy.failure(&statuses) {
+        return Err(io::Error::other(CommandError {
+            index,
+            program: pipe_cmd.commands[index].get_program().to_owned(),
+            status: statuses[index],
+            stderr: Vec::new(),
+            formatter: None,
+        }));
+    }
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(stdout.trim_end_matches(['\n', '\r']).to_owned())
+}
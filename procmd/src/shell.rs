@@ -0,0 +1,200 @@
+use std::ffi::OsStr;
+use std::path::Path;
+use std::process::Command;
+
+/// The shell that a script rendered by [`PipeCommand::to_script`] is written for.
+///
+/// [`PipeCommand::to_script`]: crate::PipeCommand::to_script
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptFlavor {
+    /// A POSIX shell, run as `/bin/sh`.
+    ///
+    /// Pipefail mode is not part of every POSIX shell (e.g. `dash` doesn't support it), so it is
+    /// not enabled in the script.
+    Posix,
+    /// Bash, run with `/usr/bin/env bash`.
+    ///
+    /// If pipefail mode is enabled on the pipeline, the script enables it with
+    /// `set -o pipefail`.
+    Bash,
+}
+
+/// Quotes `s` so that it is interpreted as a single word by a POSIX shell.
+///
+/// Strings that only contain characters without a special meaning are not quoted. Invalid UTF-8 is
+/// replaced with `U+FFFD`.
+pub(crate) fn quote(s: &OsStr) -> String {
+    let s = s.to_string_lossy();
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c);
+    if !s.is_empty() && s.chars().all(is_safe) {
+        s.into_owned()
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+}
+
+/// Renders the program and arguments of `command` as a POSIX shell command line.
+pub(crate) fn render_command(command: &Command) -> String {
+    let mut s = quote(command.get_program());
+    for arg in command.get_args() {
+        s.push(' ');
+        s.push_str(&quote(arg));
+    }
+    s
+}
+
+/// Renders `command` as a stage of a script, with the environment variables and working directory
+/// that are not set up by the script itself.
+fn render_stage(
+    command: &Command,
+    exported: &[(&OsStr, Option<&OsStr>)],
+    cwd: Option<&Path>,
+    inherit_env: bool,
+) -> String {
+    let mut unset = Vec::new();
+    let mut assignments = Vec::new();
+    for (key, value) in command.get_envs() {
+        if inherit_env && exported.contains(&(key, value)) {
+            continue;
+        }
+        match value {
+            Some(value) => assignments.push(format!("{}={}", key.to_string_lossy(), quote(value))),
+            None => unset.push(format!("-u {}", key.to_string_lossy())),
+        }
+    }
+    let mut prefix = Vec::new();
+    if !inherit_env {
+        prefix.push("env -i".to_owned());
+    } else if !unset.is_empty() || !assignments.is_empty() {
+        prefix.push("env".to_owned());
+        prefix.append(&mut unset);
+    }
+    prefix.append(&mut assignments);
+    prefix.push(render_command(command));
+    let s = prefix.join(" ");
+    match command.get_current_dir() {
+        Some(dir) if Some(dir) != cwd => format!("(cd {} && exec {})", quote(dir.as_os_str()), s),
+        _ => s,
+    }
+}
+
+/// Renders `commands` as a complete shell script for `flavor`.
+///
+/// Environment variables that are set or removed identically on all commands are exported or
+/// unset by the script and a working directory that is shared by all commands is entered with
+/// `cd`. Everything else is set up for the individual commands.
+pub(crate) fn render_script(
+    commands: &[Command],
+    flavor: ScriptFlavor,
+    pipefail: bool,
+    inherit_env: bool,
+) -> String {
+    let mut script = String::from(match flavor {
+        ScriptFlavor::Posix => "#!/bin/sh\nset -e\n",
+        ScriptFlavor::Bash => "#!/usr/bin/env bash\nset -e\n",
+    });
+    if pipefail && flavor == ScriptFlavor::Bash {
+        script.push_str("set -o pipefail\n");
+    }
+    let mut exported = Vec::new();
+    if inherit_env {
+        if let Some((first, rest)) = commands.split_first() {
+            exported = first
+                .get_envs()
+                .filter(|env| {
+                    rest.iter()
+                        .all(|command| command.get_envs().any(|e| e == *env))
+                })
+                .collect();
+        }
+        for (key, value) in &exported {
+            match value {
+                Some(value) => script.push_str(&format!(
+                    "export {}={}\n",
+                    key.to_string_lossy(),
+                    quote(value)
+                )),
+                None => script.push_str(&format!("unset {}\n", key.to_string_lossy())),
+            }
+        }
+    }
+    let mut dirs = commands.iter().map(Command::get_current_dir);
+    let cwd = match dirs.next() {
+        Some(Some(dir)) if dirs.all(|d| d == Some(dir)) => Some(dir),
+        _ => None,
+    };
+    if let Some(cwd) = cwd {
+        script.push_str(&format!("cd {}\n", quote(cwd.as_os_str())));
+    }
+    let stages = commands
+        .iter()
+        .map(|command| render_stage(command, &exported, cwd, inherit_env))
+        .collect::<Vec<_>>();
+    script.push_str(&stages.join(" | "));
+    script.push('\n');
+    script
+}
+
+/// Renders `commands` as a POSIX shell pipeline.
+pub(crate) fn render(commands: &[Command]) -> String {
+    commands
+        .iter()
+        .map(render_command)
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+/// Escapes `s` as a double quoted word of a systemd `ExecStart=` line.
+pub(crate) fn quote_systemd(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '%' => quoted.push_str("%%"),
+            '$' => quoted.push_str("$$"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Escapes `s` as a double quoted string of the Graphviz DOT language.
+pub(crate) fn quote_dot(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Renders a linear pipeline whose commands are labeled with `labels` as a Graphviz DOT graph.
+pub(crate) fn render_dot<I, S>(labels: I) -> String
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut nodes = String::new();
+    let mut edges = String::new();
+    for (i, label) in labels.into_iter().enumerate() {
+        nodes.push_str(&format!(
+            "    stage{} [label={}];\n",
+            i,
+            quote_dot(label.as_ref())
+        ));
+        if i > 0 {
+            edges.push_str(&format!("    stage{} -> stage{};\n", i - 1, i));
+        }
+    }
+    format!("digraph pipeline {{\n{}{}}}\n", nodes, edges)
+}
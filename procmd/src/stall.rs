@@ -0,0 +1,81 @@
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+/// A callback that is called when the output of a pipeline stalls.
+pub(crate) struct StallHandler {
+    pub(crate) interval: Duration,
+    pub(crate) f: Box<dyn FnMut() + Send>,
+}
+
+impl fmt::Debug for StallHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StallHandler")
+            .field("interval", &self.interval)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A reader that calls the callback of a [`StallHandler`] whenever no data arrived within its
+/// interval.
+///
+/// The inner reader is read in a separate thread, which exits once the inner reader returns an
+/// EOF or an error, or once the [`StallReader`] is dropped and the next read of the inner reader
+/// returns.
+pub(crate) struct StallReader<'a> {
+    rx: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+    handler: &'a mut StallHandler,
+}
+
+impl<'a> StallReader<'a> {
+    pub(crate) fn new<R>(mut inner: R, capacity: usize, handler: &'a mut StallHandler) -> Self
+    where
+        R: Read + Send + 'static,
+    {
+        let (tx, rx) = mpsc::sync_channel(1);
+        thread::spawn(move || {
+            let mut buf = vec![0; capacity];
+            loop {
+                let result = match inner.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(len) => Ok(buf[..len].to_vec()),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => Err(e),
+                };
+                let is_err = result.is_err();
+                if tx.send(result).is_err() || is_err {
+                    break;
+                }
+            }
+        });
+        Self {
+            rx,
+            chunk: Vec::new(),
+            pos: 0,
+            handler,
+        }
+    }
+}
+
+impl Read for StallReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.rx.recv_timeout(self.handler.interval) {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.pos = 0;
+                }
+                Err(RecvTimeoutError::Timeout) => (self.handler.f)(),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.chunk.len() - self.pos);
+        buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
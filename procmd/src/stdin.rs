@@ -0,0 +1,28 @@
+use std::process::Stdio;
+
+/// How the stdin of the first command of a pipeline is set up.
+///
+/// This is used by [`PipeCommand::set_stdin_inherit_policy`]. Without a policy, the default
+/// depends on the method that spawns the pipeline.
+///
+/// [`PipeCommand::set_stdin_inherit_policy`]: crate::PipeCommand::set_stdin_inherit_policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StdinPolicy {
+    /// The stdin of the current process is inherited (see [`Stdio::inherit`]).
+    Inherit,
+    /// The stdin is connected to the null device, so reading from it returns an EOF (see
+    /// [`Stdio::null`]).
+    Null,
+    /// A new pipe is created for the stdin (see [`Stdio::piped`]).
+    Piped,
+}
+
+impl StdinPolicy {
+    pub(crate) fn stdio(self) -> Stdio {
+        match self {
+            Self::Inherit => Stdio::inherit(),
+            Self::Null => Stdio::null(),
+            Self::Piped => Stdio::piped(),
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use std::fmt::Write;
+
+/// Removes a single trailing newline (`\n` or `\r\n`) from `s`.
+pub(crate) fn trim_newline(s: &str) -> &str {
+    s.strip_suffix('\n')
+        .map_or(s, |s| s.strip_suffix('\r').unwrap_or(s))
+}
+
+/// Returns a line-by-line comparison of `expected` and `actual`.
+///
+/// Lines that only appear in `expected` are prefixed with `-`, lines that only appear in `actual`
+/// are prefixed with `+`, and equal lines are indented.
+pub(crate) fn diff(expected: &str, actual: &str) -> String {
+    let expected = expected.split('\n').collect::<Vec<_>>();
+    let actual = actual.split('\n').collect::<Vec<_>>();
+    let mut diff = String::new();
+    for i in 0..expected.len().max(actual.len()) {
+        match (expected.get(i), actual.get(i)) {
+            (Some(a), Some(b)) if a == b => writeln!(diff, "  {}", a).unwrap(),
+            (a, b) => {
+                if let Some(a) = a {
+                    writeln!(diff, "- {}", a).unwrap();
+                }
+                if let Some(b) = b {
+                    writeln!(diff, "+ {}", b).unwrap();
+                }
+            }
+        }
+    }
+    diff
+}
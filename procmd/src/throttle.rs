@@ -0,0 +1,47 @@
+use std::cmp;
+use std::io::{self, Read};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The number of reads per second that a [`ThrottledReader`] aims for.
+///
+/// This limits the size of every read, so the data is delivered in small steps instead of in one
+/// burst followed by a long sleep.
+const READS_PER_SEC: u64 = 20;
+
+/// A reader that limits the average rate at which data is read from the inner reader.
+///
+/// After every read, the reader sleeps until the total number of bytes read so far doesn't exceed
+/// the rate since the first read.
+pub(crate) struct ThrottledReader<R> {
+    inner: R,
+    bytes_per_sec: u64,
+    start: Option<Instant>,
+    total: u64,
+}
+
+impl<R> ThrottledReader<R> {
+    pub(crate) fn new(inner: R, bytes_per_sec: u64) -> Self {
+        Self {
+            inner,
+            bytes_per_sec,
+            start: None,
+            total: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = *self.start.get_or_insert_with(Instant::now);
+        let max = cmp::max(self.bytes_per_sec / READS_PER_SEC, 1);
+        let len = cmp::min(buf.len() as u64, max) as usize;
+        let len = self.inner.read(&mut buf[..len])?;
+        self.total += len as u64;
+        let due = Duration::from_secs_f64(self.total as f64 / self.bytes_per_sec as f64);
+        if let Some(wait) = due.checked_sub(start.elapsed()) {
+            thread::sleep(wait);
+        }
+        Ok(len)
+    }
+}
@@ -0,0 +1,18 @@
+use nix::sys::stat::{self, Mode};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+/// Sets the file mode creation mask of the process spawned by `command` to `mask`.
+#[allow(unsafe_code)]
+pub(crate) fn set(command: &mut Command, mask: u32) {
+    let mask = Mode::from_bits_truncate(mask as _);
+    let hook = move || {
+        stat::umask(mask);
+        Ok(())
+    };
+    // SAFETY: The hook only calls `umask`, which is async-signal-safe, and it doesn't allocate,
+    // so it can run between `fork` and `exec`.
+    unsafe {
+        command.pre_exec(hook);
+    }
+}
@@ -0,0 +1,12 @@
+//! A failed `exec` resets the disposition of `SIGPIPE` of the whole process to the default (see
+//! [`std::os::unix::process::CommandExt::exec`]), so this test runs in a separate test binary.
+
+#![cfg(unix)]
+
+use procmd::{cmd, CommandExt};
+
+#[test]
+fn exec_replace_not_found() {
+    let err = cmd!("procmd-nonexistent-program").exec_replace();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+}
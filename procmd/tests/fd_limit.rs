@@ -0,0 +1,24 @@
+//! This test lowers the limit of open files of the whole process, so it runs in a separate test
+//! binary.
+
+#![cfg(unix)]
+
+use nix::sys::resource::{self, Resource};
+use procmd::cmd;
+use std::fs;
+
+#[test]
+fn check_fd_limit() {
+    let (soft, hard) = resource::getrlimit(Resource::RLIMIT_NOFILE).unwrap();
+    let open = fs::read_dir("/dev/fd").unwrap().count() as u64;
+
+    let mut a = cmd!("echo", "test" => "cat");
+    a.check_fd_limit(true);
+    assert!(a.status().unwrap().success());
+
+    resource::setrlimit(Resource::RLIMIT_NOFILE, open + 2, hard).unwrap();
+    let result = a.status();
+    resource::setrlimit(Resource::RLIMIT_NOFILE, soft, hard).unwrap();
+    let err = result.unwrap_err();
+    assert!(err.to_string().starts_with("too many open files"));
+}
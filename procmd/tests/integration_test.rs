@@ -1,7 +1,24 @@
-#![feature(command_access)]
+use procmd::{
+    cmd, flag, Cmd, CommandError, CommandExt, DynPipeCommand, EnvDelta, Flag, HybridPipeline,
+    LazyPipeline, LazyStage, ParseError, PartialOutput, PipeCommand, Pipeline, PipelineOptions,
+    QuietError, ReaderConfig,
+};
+use std::cell::Cell;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use procmd::{cmd, PipeCommand};
-use std::process::Command;
+fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("procmd-test-{}-{}", std::process::id(), name));
+    let _ = fs::remove_file(&path);
+    path
+}
 
 fn assert_eq_commands(a: &Command, b: &Command) {
     assert_eq!(a.get_program(), b.get_program());
@@ -13,7 +30,7 @@ fn assert_eq_commands(a: &Command, b: &Command) {
 fn simple() {
     let a = cmd!("ls", "-a", "-l");
     let mut b = Command::new("ls");
-    b.args(&["-a", "-l"]);
+    b.args(["-a", "-l"]);
     assert_eq_commands(&a, &b);
 }
 
@@ -25,3 +42,2125 @@ fn piped() {
     assert_eq_commands(&a.commands[1], &b.commands[1]);
     assert_eq_commands(&a.commands[2], &b.commands[2]);
 }
+
+fn tail_stdout(pipeline: &mut impl Pipeline) -> Vec<u8> {
+    pipeline.output().unwrap().stdout
+}
+
+fn stage_count(pipeline: &dyn Pipeline) -> usize {
+    pipeline.len()
+}
+
+#[test]
+fn pipeline_trait() {
+    let mut a = cmd!("echo", "hello" => "tr", "a-z", "A-Z");
+    let mut b = DynPipeCommand::new(vec![cmd!("echo", "hello"), cmd!("tr", "a-z", "A-Z")]);
+    assert_eq!(tail_stdout(&mut a), b"HELLO\n");
+    assert_eq!(tail_stdout(&mut b), b"HELLO\n");
+    assert_eq!(stage_count(&a), 2);
+    assert_eq!(stage_count(&b), 2);
+    assert_eq!(b.command(1).unwrap().get_program(), "tr");
+
+    let mut pipelines: Vec<Box<dyn Pipeline>> = vec![Box::new(a), Box::new(b)];
+    for pipeline in &mut pipelines {
+        assert!(pipeline.status().unwrap().success());
+    }
+}
+
+#[test]
+fn pipefail() {
+    let mut a = cmd!("echo", "test" => "grep", "nomatch" => "cat");
+    assert!(a.status().unwrap().success());
+    a.pipefail(true);
+    assert_eq!(a.status().unwrap().code(), Some(1));
+    assert_eq!(a.output().unwrap().status.code(), Some(1));
+    let statuses = a.statuses().unwrap();
+    assert!(statuses[0].success());
+    assert_eq!(statuses[1].code(), Some(1));
+    assert!(statuses[2].success());
+}
+
+#[test]
+fn pipefail_allowed_failure() {
+    let mut a = cmd!("echo", "test" => "grep", "nomatch" => "cat");
+    a.pipefail(true).allow_failure(1);
+    assert!(a.status().unwrap().success());
+
+    let mut b = cmd!("echo", "test" => "grep", "nomatch" => "cat");
+    b.pipefail(true).allow_exit_codes(1, [0, 1]);
+    assert!(b.status().unwrap().success());
+    b.allow_exit_codes(1, [0]);
+    assert_eq!(b.status().unwrap().code(), Some(1));
+}
+
+#[test]
+fn dyn_pipe_command_pipefail() {
+    let mut a: DynPipeCommand = "echo test | grep nomatch | cat".parse().unwrap();
+    assert!(a.status().unwrap().success());
+    a.pipefail(true);
+    assert_eq!(a.status().unwrap().code(), Some(1));
+    assert_eq!(a.output().unwrap().status.code(), Some(1));
+    a.allow_exit_codes(1, [0, 1]);
+    assert!(a.status().unwrap().success());
+
+    let mut b: DynPipeCommand = "yes | head -n 1".parse().unwrap();
+    b.pipefail(true);
+    assert!(b.status().unwrap().success());
+}
+
+#[test]
+fn ok_codes_clause() {
+    let mut a = cmd!("echo", "test" => "grep", "nomatch", ok_codes: [0, 1] => "cat");
+    a.pipefail(true);
+    assert!(a.status_checked().unwrap().success());
+
+    let mut b = cmd!("echo", "test" => "grep", "nomatch", ok_codes: [0] => "cat");
+    b.pipefail(true);
+    let err = b.status_checked().unwrap_err();
+    let err = err
+        .get_ref()
+        .unwrap()
+        .downcast_ref::<CommandError>()
+        .unwrap();
+    assert_eq!(err.index, 1);
+    assert_eq!(err.program, "grep");
+    assert_eq!(err.status.code(), Some(1));
+
+    let mut c = cmd!("grep", "nomatch", ok_codes: [0, 1]);
+    c.commands[0].stdin(Stdio::null());
+    assert_eq!(c.status_checked().unwrap().code(), Some(1));
+    let statuses = c.statuses().unwrap();
+    assert!(c.require_success(&statuses).is_ok());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn pipeline_job() {
+    use procmd::PipelineJob;
+
+    let dir = temp_path("pipeline_job");
+    fs::create_dir(&dir).unwrap();
+    fs::write(dir.join("input.txt"), "b\na\nc\n").unwrap();
+    let json = format!(
+        r#"{{
+            "spec": {{
+                "stages": [
+                    {{ "program": "cat", "args": ["input.txt"], "cwd": {:?}, "label": "read" }},
+                    {{ "program": "sh", "args": ["-c", "sort; echo $SUFFIX $SHARED; exit 3"],
+                       "env": {{ "SUFFIX": "done" }} }},
+                    {{ "program": "cat" }}
+                ]
+            }},
+            "options": {{
+                "pipefail": true,
+                "allowed_exit_codes": [[1, [0, 3]]],
+                "stage_timeouts": [[2, {{ "secs": 10, "nanos": 0 }}]],
+                "env": {{ "SHARED": "ok" }}
+            }}
+        }}"#,
+        dir
+    );
+    let mut job = serde_json::from_str::<PipelineJob>(&json).unwrap();
+    let report = job.run().unwrap();
+    assert!(report.success);
+    assert_eq!(report.stages.len(), 3);
+    assert_eq!(report.stages[0].label.as_deref(), Some("read"));
+    assert_eq!(report.stages[1].status.code(), Some(3));
+    assert_eq!(report.output.stdout, b"a\nb\nc\ndone ok\n");
+
+    job.options.allowed_exit_codes.clear();
+    let report = job.run().unwrap();
+    assert!(!report.success);
+    assert_eq!(report.output.status.code(), Some(3));
+    fs::remove_dir_all(dir).unwrap();
+
+    let json = r#"{
+        "spec": { "stages": [{ "program": "sleep", "args": ["10"] }, { "program": "cat" }] },
+        "options": { "stage_timeouts": [[0, { "secs": 0, "nanos": 100000000 }]] }
+    }"#;
+    let start = Instant::now();
+    let err = serde_json::from_str::<PipelineJob>(json)
+        .unwrap()
+        .run()
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    let mut stages = vec![serde_json::json!({ "program": "echo", "args": ["many"] })];
+    stages.resize(20, serde_json::json!({ "program": "cat" }));
+    let json = serde_json::json!({ "spec": { "stages": stages } });
+    let report = serde_json::from_value::<PipelineJob>(json)
+        .unwrap()
+        .run()
+        .unwrap();
+    assert_eq!(report.stages.len(), 20);
+    assert_eq!(report.output.stdout, b"many\n");
+
+    stages.resize(
+        DynPipeCommand::DEFAULT_MAX_COMMANDS + 1,
+        serde_json::json!({ "program": "cat" }),
+    );
+    let json = serde_json::json!({ "spec": { "stages": stages } });
+    let deep = serde_json::from_value::<PipelineJob>(json).unwrap();
+    let err = deep.run().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    assert!(err.to_string().contains("at most 256"));
+    let err = deep.run_with_max_commands(10).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+    let json = r#"{ "spec": { "stages": [{ "program": "cat" }] } }"#;
+    let report = serde_json::from_str::<PipelineJob>(json)
+        .unwrap()
+        .run()
+        .unwrap();
+    assert!(report.success);
+    assert!(report.output.stdout.is_empty());
+
+    job.spec.stages.clear();
+    let err = job.run().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn output_ndjson() {
+    let input = "[1, \"a\"]\n\n[2, \"b\"]\nnot json\n[3, \"c\"]";
+    let mut a = cmd!("printf", "%s", input => "cat");
+    let values = a
+        .output_ndjson::<(u32, String)>()
+        .unwrap()
+        .collect::<Vec<_>>();
+    assert_eq!(values.len(), 4);
+    assert_eq!(values[0].as_ref().unwrap(), &(1, "a".to_owned()));
+    assert_eq!(values[1].as_ref().unwrap(), &(2, "b".to_owned()));
+    assert_eq!(
+        values[2].as_ref().unwrap_err().kind(),
+        std::io::ErrorKind::InvalidData
+    );
+    assert_eq!(values[3].as_ref().unwrap(), &(3, "c".to_owned()));
+}
+
+#[test]
+fn pipeline_options() {
+    let options = PipelineOptions {
+        pipefail: true,
+        allowed_exit_codes: vec![(2, vec![0, 1])],
+        ..PipelineOptions::default()
+    };
+    let mut a = cmd!("echo", "test" => "grep", "nomatch");
+    let mut b = cmd!("echo", "test" => "cat" => "grep", "nomatch" => "cat");
+    options.apply(&mut a);
+    options.apply(&mut b);
+    assert_eq!(a.status().unwrap().code(), Some(1));
+    assert!(a.status_checked().is_err());
+    assert!(b.status().unwrap().success());
+    assert!(b.status_checked().is_ok());
+
+    let options = PipelineOptions {
+        error_formatter: Some(Arc::new(|e: &CommandError| {
+            format!("step {} failed ({})", e.index, e)
+        })),
+        ..options
+    };
+    let mut c = cmd!("echo", "test" => "false" => "cat");
+    options.apply(&mut c);
+    let err = c.status_checked().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "step 1 failed (command `false` at index 1 failed with exit status: 1)"
+    );
+    let err = err
+        .get_ref()
+        .unwrap()
+        .downcast_ref::<CommandError>()
+        .unwrap();
+    assert_eq!(err.index, 1);
+    assert!(format!("{:?}", options).contains("error_formatter: Some(..)"));
+
+    let options = PipelineOptions {
+        stage_timeouts: vec![(0, Duration::from_millis(100)), (5, Duration::from_secs(1))],
+        env: [("GREETING".to_owned(), "hello".to_owned())].into(),
+        ..PipelineOptions::default()
+    };
+    let mut d = cmd!("sh", "-c", "echo $GREETING" => "cat");
+    options.apply(&mut d);
+    assert_eq!(d.read().unwrap(), "hello");
+    let mut e = cmd!("sleep", "10" => "cat");
+    options.apply(&mut e);
+    assert_eq!(e.status().unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+
+    let mut f: DynPipeCommand = "sh -c 'echo $GREETING; exit 1' | cat".parse().unwrap();
+    let options = PipelineOptions {
+        allowed_failures: vec![0],
+        ..options
+    };
+    options.apply_dyn(&mut f);
+    let report = f.run_detailed().unwrap();
+    assert!(report.success);
+    assert_eq!(report.output.stdout, b"hello\n");
+    let mut g: DynPipeCommand = "sleep 10 | cat".parse().unwrap();
+    options.apply_dyn(&mut g);
+    assert_eq!(g.status().unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+}
+
+#[test]
+fn for_each_line_reader_config() {
+    let mut a = cmd!("printf", "a\\nbb\\nccc" => "cat");
+    a.reader_config(ReaderConfig { capacity: 1 });
+    let mut lines = Vec::new();
+    let statuses = a.for_each_line(|line| lines.push(line.to_owned())).unwrap();
+    assert_eq!(lines, ["a", "bb", "ccc"]);
+    assert!(statuses.iter().all(|status| status.success()));
+}
+
+#[test]
+fn split_output() {
+    let mut a = cmd!("echo", "test" => "sh", "-c", "cat; echo error >&2");
+    let (mut stdout, mut stderr) = a.split_output().unwrap();
+    let stderr_thread = thread::spawn(move || {
+        let mut buf = String::new();
+        stderr.read_to_string(&mut buf).unwrap();
+        buf
+    });
+    let mut stdout_buf = String::new();
+    stdout.read_to_string(&mut stdout_buf).unwrap();
+    assert_eq!(stdout_buf, "test\n");
+    assert_eq!(stderr_thread.join().unwrap(), "error\n");
+}
+
+#[test]
+fn to_shell_string() {
+    let a = cmd!("echo", "it's" => "grep", "-e", "a b" => "wc", "-l", "");
+    assert_eq!(
+        a.to_shell_string(),
+        "echo 'it'\\''s' | grep -e 'a b' | wc -l ''"
+    );
+}
+
+#[test]
+#[ignore = "requires sudo"]
+fn run_elevated() {
+    let mut a = cmd!("id", "-u" => "grep", "-x", "0");
+    assert!(a.run_elevated().unwrap().success());
+}
+
+#[test]
+fn dyn_pipe_command_from_str() {
+    let mut a: DynPipeCommand = r#"echo -n "a \"b\"" 'c|d' e\ f | tr a-z A-Z"#.parse().unwrap();
+    assert_eq!(a.len(), 2);
+    assert_eq_commands(&a.commands[0], &cmd!("echo", "-n", "a \"b\"", "c|d", "e f"));
+    assert_eq_commands(&a.commands[1], &cmd!("tr", "a-z", "A-Z"));
+    assert_eq!(a.output().unwrap().stdout, b"A \"B\" C|D E F");
+
+    assert_eq!(
+        "ls |".parse::<DynPipeCommand>().unwrap_err(),
+        ParseError::EmptyCommand
+    );
+    assert_eq!(
+        "".parse::<DynPipeCommand>().unwrap_err(),
+        ParseError::EmptyCommand
+    );
+    assert_eq!(
+        "echo 'a".parse::<DynPipeCommand>().unwrap_err(),
+        ParseError::UnterminatedQuote
+    );
+    assert_eq!(
+        "echo a\\".parse::<DynPipeCommand>().unwrap_err(),
+        ParseError::TrailingBackslash
+    );
+
+    let max = DynPipeCommand::DEFAULT_MAX_COMMANDS;
+    let command_line = vec!["cat"; max].join(" | ");
+    assert_eq!(command_line.parse::<DynPipeCommand>().unwrap().len(), max);
+    let command_line = format!("{} | cat", command_line);
+    assert_eq!(
+        command_line.parse::<DynPipeCommand>().unwrap_err(),
+        ParseError::TooManyCommands(max)
+    );
+    assert_eq!(
+        DynPipeCommand::parse_with_max_commands("ls | sort | uniq", 2).unwrap_err(),
+        ParseError::TooManyCommands(2)
+    );
+    assert_eq!(
+        DynPipeCommand::parse_with_max_commands("ls | sort", 2)
+            .unwrap()
+            .len(),
+        2
+    );
+}
+
+#[test]
+fn env_diff_between() {
+    let mut a = cmd!("ls" => "grep", "test");
+    a.commands[0].env("A", "1").env("B", "1").env("C", "1");
+    a.commands[1].env("A", "1").env("B", "2").env_remove("D");
+    assert_eq!(
+        a.env_diff_between(0, 1),
+        vec![
+            (
+                OsString::from("B"),
+                EnvDelta::Changed(Some("1".into()), Some("2".into()))
+            ),
+            (OsString::from("C"), EnvDelta::OnlyFirst(Some("1".into()))),
+            (OsString::from("D"), EnvDelta::OnlySecond(None)),
+        ]
+    );
+    assert!(a.env_diff_between(1, 1).is_empty());
+}
+
+#[test]
+fn intermediate_stderr() {
+    let path = temp_path("intermediate_stderr");
+    File::create(&path).unwrap();
+    let stage = |name: &str| cmd!("sh", "-c", format!("cat; echo {} >&2", name));
+    let mut a = PipeCommand::new([stage("a"), stage("b"), stage("c"), stage("d")]);
+    a.commands[0].stdin(Stdio::null()).stderr(Stdio::null());
+    a.commands[3].stderr(Stdio::null());
+    a.intermediate_stderr(|| OpenOptions::new().append(true).open(&path).unwrap().into());
+    assert!(a.status().unwrap().success());
+    let mut stderr = fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect::<Vec<_>>();
+    stderr.sort();
+    assert_eq!(stderr, ["b", "c"]);
+    fs::remove_file(&path).unwrap();
+
+    let mut b = cmd!("echo", "test" => "sh", "-c", "cat; echo error >&2" => "cat");
+    b.intermediate_stderr(Stdio::null);
+    assert_eq!(b.output().unwrap().stdout, b"test\n");
+}
+
+#[test]
+fn head_stdin_from() {
+    let mut child = cmd!("echo", "hello")
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut a = cmd!("cat" => "tr", "a-z", "A-Z");
+    a.head_stdin_from(child.stdout.take().unwrap());
+    assert_eq!(a.output().unwrap().stdout, b"HELLO\n");
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn status_timeout() {
+    let mut a = cmd!("echo", "test" => "cat");
+    let status = a.status_timeout(Duration::from_secs(5)).unwrap();
+    assert!(status.unwrap().success());
+
+    let mut b = cmd!("echo", "test" => "sleep", "5");
+    let start = Instant::now();
+    assert!(b
+        .status_timeout(Duration::from_millis(100))
+        .unwrap()
+        .is_none());
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[cfg(unix)]
+#[test]
+fn status_timeout_graceful() {
+    let limit = Duration::from_millis(100);
+    let grace = Duration::from_millis(500);
+
+    let mut a = cmd!("echo", "test" => "sleep", "5");
+    let start = Instant::now();
+    assert!(a.status_timeout_graceful(limit, grace).unwrap().is_none());
+    assert!(start.elapsed() < limit + grace);
+
+    let mut b = cmd!("echo", "test" => "sh", "-c", "trap '' TERM; exec sleep 5");
+    let start = Instant::now();
+    assert!(b.status_timeout_graceful(limit, grace).unwrap().is_none());
+    let elapsed = start.elapsed();
+    assert!(elapsed >= limit + grace);
+    assert!(elapsed < Duration::from_secs(3));
+}
+
+#[test]
+fn output_timeout() {
+    let mut a = cmd!("echo", "test" => "cat");
+    let output = a.output_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(output.stdout, b"test\n");
+
+    let mut b = cmd!("echo", "test" => "sh", "-c", "cat; echo waiting >&2; sleep 5");
+    let start = Instant::now();
+    let err = b.output_timeout(Duration::from_millis(300)).unwrap_err();
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    let partial = err
+        .get_ref()
+        .unwrap()
+        .downcast_ref::<PartialOutput>()
+        .unwrap();
+    assert_eq!(partial.stdout, b"test\n");
+    assert_eq!(partial.stderr, b"waiting\n");
+    assert_eq!(partial.index, 1);
+
+    let mut c = cmd!("sleep", "5" => "echo", "test");
+    c.stage_timeout(0, Duration::from_millis(100));
+    let err = c.output_timeout(Duration::from_secs(5)).unwrap_err();
+    let partial = err
+        .get_ref()
+        .unwrap()
+        .downcast_ref::<PartialOutput>()
+        .unwrap();
+    assert_eq!(partial.stdout, b"test\n");
+    assert_eq!(partial.index, 0);
+}
+
+#[test]
+fn argv_of() {
+    let a = cmd!("ls", "-a" => "grep", "a b", "");
+    assert_eq!(a.argv_of(0), ["ls", "-a"]);
+    assert_eq!(a.argv_of(1), ["grep", "a b", ""]);
+}
+
+#[cfg(unix)]
+#[test]
+fn tail_stdout_fifo() {
+    let path = temp_path("tail_stdout_fifo");
+    let mut a = cmd!("echo", "test" => "tr", "a-z", "A-Z");
+    a.tail_stdout_fifo(&path);
+    let reader = {
+        let path = path.clone();
+        thread::spawn(move || {
+            while !path.exists() {
+                thread::sleep(Duration::from_millis(10));
+            }
+            fs::read_to_string(&path).unwrap()
+        })
+    };
+    assert!(a.status().unwrap().success());
+    assert_eq!(reader.join().unwrap(), "TEST\n");
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn run_detailed() {
+    let mut a = cmd!("echo", "test" => "sh", "-c", "cat; exit 3" => "cat");
+    a.pipefail(true).label(1, "middle");
+    let report = a.run_detailed().unwrap();
+    assert_eq!(report.stages.len(), 3);
+    assert_eq!(
+        report
+            .stages
+            .iter()
+            .map(|stage| stage.success)
+            .collect::<Vec<_>>(),
+        [true, false, true]
+    );
+    assert_eq!(report.stages[1].label.as_deref(), Some("middle"));
+    assert_eq!(report.stages[1].status.code(), Some(3));
+    assert_eq!(report.stages[0].argv, ["echo", "test"]);
+    assert_eq!(report.output.stdout, b"test\n");
+    assert_eq!(report.output.status.code(), Some(3));
+    assert!(!report.success);
+    assert!(report
+        .stages
+        .iter()
+        .all(|stage| stage.duration <= report.duration));
+
+    a.allow_failure(1);
+    assert!(a.run_detailed().unwrap().success);
+
+    let mut b = cmd!("sleep", "10" => "cat");
+    b.stage_timeout(0, Duration::from_millis(100));
+    let start = Instant::now();
+    assert_eq!(
+        b.run_detailed().unwrap_err().kind(),
+        std::io::ErrorKind::TimedOut
+    );
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn dyn_pipe_command_run_detailed() {
+    let mut a: DynPipeCommand = "echo test | sh -c 'cat; exit 3' | cat".parse().unwrap();
+    a.pipefail(true).label(1, "middle");
+    let report = a.run_detailed().unwrap();
+    assert_eq!(report.stages.len(), 3);
+    assert!(!report.stages[1].success);
+    assert_eq!(report.stages[1].label.as_deref(), Some("middle"));
+    assert_eq!(report.stages[0].argv, ["echo", "test"]);
+    assert_eq!(report.output.stdout, b"test\n");
+    assert_eq!(report.output.status.code(), Some(3));
+    assert!(!report.success);
+
+    a.allow_exit_codes(1, [3]);
+    assert!(a.run_detailed().unwrap().success);
+
+    let mut b: DynPipeCommand = "sleep 10 | cat".parse().unwrap();
+    b.stage_timeout(0, Duration::from_millis(100));
+    let start = Instant::now();
+    assert_eq!(b.status().unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+    assert_eq!(
+        b.run_detailed().unwrap_err().kind(),
+        std::io::ErrorKind::TimedOut
+    );
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn lazy_stage() {
+    let called = Cell::new(false);
+    let a = vec![
+        LazyStage::from(cmd!("echo", "test")),
+        LazyStage::new(|| {
+            called.set(true);
+            cmd!("grep", "nomatch")
+        })
+        .when(false),
+        LazyStage::new(|| cmd!("tr", "a-z", "A-Z")),
+    ]
+    .into_iter()
+    .collect::<LazyPipeline>();
+    assert!(!called.get());
+    assert_eq!(a.len(), 2);
+    assert_eq!(a.output().unwrap().stdout, b"TEST\n");
+    assert!(!called.get());
+
+    let evaluated = Cell::new(false);
+    let mut b = LazyPipeline::new();
+    b.push(LazyStage::from(cmd!("echo", "test")))
+        .extend(vec![LazyStage::new(|| {
+            evaluated.set(true);
+            cmd!("cat")
+        })]);
+    assert!(!evaluated.get());
+    let b = b.into_pipe_command();
+    assert!(evaluated.get());
+    assert_eq!(b.len(), 2);
+}
+
+#[test]
+fn all_success() {
+    let mut a = cmd!("echo", "test" => "cat" => "cat");
+    assert!(a.all_success().unwrap());
+
+    let mut b = cmd!("echo", "test" => "grep", "nomatch" => "cat");
+    assert!(!b.all_success().unwrap());
+    b.allow_exit_codes(1, [0, 1]);
+    assert!(b.all_success().unwrap());
+}
+
+#[test]
+fn args_from_str() {
+    let mut a = cmd!("grep");
+    a.args_from_str(r#"-e "a \"b\"" 'c d' e\ f | g"#)
+        .unwrap()
+        .arg("h");
+    assert_eq_commands(
+        &a,
+        &cmd!("grep", "-e", "a \"b\"", "c d", "e f", "|", "g", "h"),
+    );
+    assert_eq!(
+        cmd!("grep").args_from_str("'a").unwrap_err(),
+        ParseError::UnterminatedQuote
+    );
+    assert!(cmd!("grep")
+        .args_from_str("")
+        .unwrap()
+        .get_args()
+        .next()
+        .is_none());
+}
+
+#[test]
+fn env_per_stage() {
+    let mut a = cmd!("sh", "-c", "echo $A$B" => "sh", "-c", "cat; echo $A$B");
+    a.env_per_stage([vec![("A", "1")], vec![("A", "2"), ("B", "3")]]);
+    assert!(a.commands[0]
+        .get_envs()
+        .eq([("A".as_ref(), Some("1".as_ref()))]));
+    assert_eq!(a.output().unwrap().stdout, b"1\n23\n");
+}
+
+#[test]
+fn to_systemd_exec_start() {
+    let a = cmd!("echo", "a\\b \"c\"" => "grep", "$HOME" => "wc", "-l");
+    assert_eq!(
+        a.to_systemd_exec_start(),
+        r#"/bin/sh -c "echo 'a\\b \"c\"' | grep '$$HOME' | wc -l""#
+    );
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn spawn_delay() {
+    let mut a = cmd!("echo", "test" => "cat" => "cat");
+    a.set_spawn_delay(Duration::from_millis(200));
+    let start = Instant::now();
+    assert!(a.status().unwrap().success());
+    assert!(start.elapsed() >= Duration::from_millis(400));
+}
+
+#[test]
+fn tail_stdin_disposition() {
+    let mut a = cmd!("echo", "test" => "sh", "-c", "test -p /dev/stdin && cat");
+    let output = a.output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"test\n");
+
+    let mut b = cmd!("true" => "true");
+    let mut child = b.spawn().unwrap();
+    assert!(child.stdin.is_none());
+    assert!(child.stdout.is_none());
+    assert!(child.stderr.is_none());
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn duct_compat() {
+    assert_eq!(
+        cmd!("echo", "hi" => "tr", "a-z", "A-Z").read().unwrap(),
+        "HI"
+    );
+    assert_eq!(
+        PipeCommand::new([cmd!("printf", "a\\nb\\n\\n")])
+            .read()
+            .unwrap(),
+        "a\nb"
+    );
+    assert!(cmd!("echo", "hi" => "false").read().is_err());
+
+    let report = cmd!("echo", "hi" => "cat").run().unwrap();
+    assert!(report.success);
+    assert_eq!(report.output.stdout, b"hi\n");
+    let err = cmd!("false" => "cat").pipefail(true).run().unwrap_err();
+    assert!(err.get_ref().unwrap().is::<CommandError>());
+
+    let mut pipe_cmd = DynPipeCommand::default()
+        .pipe(cmd!("echo", "hi"))
+        .pipe(cmd!("tr", "a-z", "A-Z"));
+    assert_eq!(pipe_cmd.output().unwrap().stdout, b"HI\n");
+}
+
+#[test]
+fn stream_to_channel() {
+    let (tx, rx) = mpsc::sync_channel(1);
+    let consumer = thread::spawn(move || rx.into_iter().collect::<Vec<_>>());
+    let statuses = cmd!("printf", "a\\nb\\nc\\n" => "cat")
+        .stream_to_channel(tx)
+        .unwrap();
+    assert!(statuses.iter().all(ExitStatus::success));
+    assert_eq!(consumer.join().unwrap(), ["a", "b", "c"]);
+
+    let (tx, rx) = mpsc::sync_channel(1);
+    let consumer = thread::spawn(move || rx.recv().unwrap());
+    let statuses = cmd!("yes" => "cat").stream_to_channel(tx).unwrap();
+    assert_eq!(consumer.join().unwrap(), "y");
+    assert!(!statuses[1].success());
+}
+
+#[test]
+fn inherit_env() {
+    std::env::set_var("PROCMD_TEST_INHERIT_ENV", "1");
+    let mut a = cmd!("env" => "cat");
+    a.commands[0].env("PROCMD_TEST_STAGE", "a");
+    a.inherit_env(false)
+        .env_per_stage([vec![("PROCMD_TEST_STAGE_2", "b")], vec![]]);
+    let stdout = String::from_utf8(a.output().unwrap().stdout).unwrap();
+    assert!(!stdout.contains("PROCMD_TEST_INHERIT_ENV"));
+    assert!(stdout.contains("PROCMD_TEST_STAGE=a\n"));
+    assert!(stdout.contains("PROCMD_TEST_STAGE_2=b\n"));
+
+    let mut b = cmd!("env" => "cat");
+    b.inherit_env(true);
+    let stdout = String::from_utf8(b.output().unwrap().stdout).unwrap();
+    assert!(stdout.contains("PROCMD_TEST_INHERIT_ENV=1\n"));
+}
+
+#[test]
+fn broken_pipe() {
+    let mut a = cmd!("yes" => "head", "-n", "5");
+    a.pipefail(true);
+    let statuses = a.statuses().unwrap();
+    assert!(!statuses[0].success());
+    assert!(a.require_success(&statuses).is_ok());
+    assert!(a.status().unwrap().success());
+    assert!(a.all_success().unwrap());
+
+    let mut b = cmd!("sh", "-c", "kill -PIPE $$" => "cat" => "sh", "-c", "cat; kill -PIPE $$");
+    b.pipefail(true);
+    let statuses = b.statuses().unwrap();
+    assert!(b.require_success(&statuses).is_err());
+}
+
+#[test]
+fn tail_stdout_file() {
+    let path = temp_path("tail_stdout_file");
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .unwrap();
+    let mut a = cmd!("echo", "test" => "tr", "a-z", "A-Z");
+    a.tail_stdout_file(file);
+    assert!(a.status().unwrap().success());
+    assert!(a.status().unwrap().success());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "TEST\nTEST\n");
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn args_clause() {
+    let flag = "-b";
+    let a = cmd!("tool", "-x", args: ["-a", flag], "file", args: vec!["-c"]);
+    let mut b = Command::new("tool");
+    b.args(["-x", "-a", "-b", "file", "-c"]);
+    assert_eq_commands(&a, &b);
+
+    let mut c = cmd!("echo", args: ["a", "b"] => "tr", args: &["a-z", "A-Z"]);
+    assert_eq!(c.output().unwrap().stdout, b"A B\n");
+}
+
+#[test]
+fn sh() {
+    assert_eq!(procmd::sh("echo 'a b' | tr a-z A-Z").unwrap(), "A B");
+    assert_eq!(procmd::sh("echo *").unwrap(), "*");
+    assert_eq!(procmd::sh("yes | head -n 2").unwrap(), "y\ny");
+    let err = procmd::sh("false | cat").unwrap_err();
+    let err = err
+        .get_ref()
+        .unwrap()
+        .downcast_ref::<CommandError>()
+        .unwrap();
+    assert_eq!(err.index, 0);
+    assert_eq!(
+        procmd::sh("echo 'test").unwrap_err().kind(),
+        std::io::ErrorKind::InvalidInput
+    );
+    let command_line = vec!["cat"; DynPipeCommand::DEFAULT_MAX_COMMANDS + 1].join(" | ");
+    assert_eq!(
+        procmd::sh(&command_line).unwrap_err().kind(),
+        std::io::ErrorKind::InvalidInput
+    );
+}
+
+#[test]
+fn for_each_chunk() {
+    let mut a = cmd!("head", "-c", "100000", "/dev/urandom" => "cat");
+    a.reader_config(ReaderConfig { capacity: 4096 });
+    let mut total = 0;
+    let mut max = 0;
+    let statuses = a
+        .for_each_chunk(|chunk| {
+            total += chunk.len();
+            max = max.max(chunk.len());
+        })
+        .unwrap();
+    assert!(statuses.iter().all(ExitStatus::success));
+    assert_eq!(total, 100000);
+    assert!(max <= 4096);
+}
+
+#[test]
+fn prepend_append_stage() {
+    let mut a = DynPipeCommand::new(vec![cmd!("tr", "a-z", "A-Z")]);
+    a.prepend_stage(cmd!("cat"))
+        .prepend_program("echo", ["test"])
+        .append_stage(cmd!("rev"))
+        .append_program("tr", ["T", "x"]);
+    let programs = a
+        .commands
+        .iter()
+        .map(|command| command.get_program().to_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(programs, ["echo", "cat", "tr", "rev", "tr"]);
+    assert_eq!(a.output().unwrap().stdout, b"xSEx\n");
+}
+
+#[test]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    // The stage count assertions are only evaluated when building, which trybuild only does if
+    // there is a test case that should compile.
+    t.pass("tests/ui/pass/*.rs");
+    t.compile_fail("tests/ui/compile_fail/*.rs");
+}
+
+#[test]
+fn on_stall() {
+    let stalls = Arc::new(AtomicUsize::new(0));
+    let mut a = cmd!("sh", "-c", "echo a; sleep 0.5; echo b" => "cat");
+    let counter = Arc::clone(&stalls);
+    a.on_stall(Duration::from_millis(100), move || {
+        counter.fetch_add(1, Ordering::SeqCst);
+    });
+    let mut lines = Vec::new();
+    let statuses = a.for_each_line(|line| lines.push(line.to_owned())).unwrap();
+    assert!(statuses.iter().all(ExitStatus::success));
+    assert_eq!(lines, ["a", "b"]);
+    assert!(stalls.load(Ordering::SeqCst) >= 2);
+}
+
+#[test]
+fn output_tee() {
+    let path = temp_path("output_tee");
+    let file = File::create(&path).unwrap();
+    let mut a = cmd!("sh", "-c", "echo out; echo err >&2" => "tr", "a-z", "A-Z");
+    let output = a.output_tee(file).unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"OUT\n");
+    assert_eq!(fs::read(&path).unwrap(), output.stdout);
+    fs::remove_file(path).unwrap();
+
+    struct Full;
+    impl Write for Full {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::new(std::io::ErrorKind::StorageFull, "full"))
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let err = cmd!("yes" => "cat").output_tee(Full).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::StorageFull);
+    assert!(err.to_string().starts_with("failed to write the output"));
+}
+
+#[test]
+fn output_and_inherit() {
+    let mut a = cmd!("echo", "out" => "tr", "a-z", "A-Z");
+    let output = a.output_and_inherit().unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"OUT\n");
+
+    let mut b = cmd!("echo", "out" => "false" => "cat");
+    b.pipefail(true).error_on_failure(true);
+    let err = b.output_and_inherit().unwrap_err();
+    let err = err
+        .get_ref()
+        .unwrap()
+        .downcast_ref::<CommandError>()
+        .unwrap();
+    assert_eq!(err.index, 1);
+    let mut sink = Vec::new();
+    assert!(b.output_tee(&mut sink).is_err());
+    b.error_on_failure(false);
+    assert_eq!(b.output_tee(&mut sink).unwrap().status.code(), Some(1));
+}
+
+#[cfg(unix)]
+#[test]
+fn auto_nice() {
+    let niceness = |a: &mut PipeCommand<2>| a.read().unwrap().parse::<i32>().unwrap();
+    let mut a = cmd!("true" => "nice");
+    let base = niceness(&mut a);
+
+    a.auto_nice(-1.0);
+    assert_eq!(niceness(&mut a), (base + 10).min(19));
+    a.auto_nice(f64::INFINITY);
+    assert_eq!(niceness(&mut a), base);
+}
+
+#[cfg(all(unix, feature = "testing"))]
+#[test]
+fn auto_nice_load_source() {
+    let niceness = |a: &mut PipeCommand<2>| a.read().unwrap().parse::<i32>().unwrap();
+    let mut a = cmd!("true" => "nice");
+    let base = niceness(&mut a);
+
+    a.auto_nice(4.0).set_load_source(|| Ok(6.5));
+    assert_eq!(niceness(&mut a), (base + 10).min(19));
+    a.set_load_source(|| Ok(4.0));
+    assert_eq!(niceness(&mut a), base);
+    a.set_load_source(|| Err(std::io::Error::other("unavailable")));
+    assert_eq!(niceness(&mut a), base);
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn assert_stdout_eq() {
+    cmd!("printf", "b\\na\\n" => "sort").assert_stdout_eq("a\nb\n");
+    cmd!("printf", "a" => "cat").assert_stdout_eq("a");
+}
+
+#[cfg(feature = "testing")]
+#[test]
+#[should_panic(expected = "- b\n+ c\n")]
+fn assert_stdout_eq_mismatch() {
+    cmd!("printf", "a\\nc\\n" => "cat").assert_stdout_eq("a\nb");
+}
+
+#[cfg(feature = "testing")]
+#[test]
+#[should_panic(expected = "failed to run")]
+fn assert_stdout_eq_failure() {
+    cmd!("echo", "a" => "false").assert_stdout_eq("");
+}
+
+#[cfg(windows)]
+#[test]
+fn env_case_insensitive() {
+    let mut a = PipeCommand::new([Command::new("cmd"), Command::new("cmd")]);
+    a.commands[0].env("PATH", "a");
+    a.env_per_stage([vec![("Path", "b")], vec![("path", "c")]]);
+    a.inherit_env(false);
+    assert_eq!(a.commands[0].get_envs().count(), 1);
+    assert_eq!(
+        a.env_diff_between(0, 1),
+        [(
+            OsString::from("PATH"),
+            EnvDelta::Changed(Some("b".into()), Some("c".into()))
+        )]
+    );
+}
+
+#[test]
+fn resolved_program() {
+    let mut a = cmd!("sh" => "procmd-does-not-exist" => "./sh");
+    let sh = a.resolved_program(0).unwrap();
+    assert!(sh.is_absolute());
+    assert!(sh.ends_with("sh"));
+    assert_eq!(
+        a.resolved_program(1).unwrap_err().kind(),
+        std::io::ErrorKind::NotFound
+    );
+
+    a.commands[0].env("PATH", "");
+    assert!(a.resolved_program(0).is_err());
+    a.commands[2].current_dir(sh.parent().unwrap());
+    assert_eq!(
+        a.resolved_program(2).unwrap(),
+        sh.parent().unwrap().join("./sh")
+    );
+}
+
+#[test]
+fn try_wait_all() {
+    let mut a = cmd!("sleep", "0.2" => "true");
+    let mut pipe_child = a.spawn_all().unwrap();
+    assert!(pipe_child.try_wait_all().unwrap().is_none());
+    let statuses = loop {
+        if let Some(statuses) = pipe_child.try_wait_all().unwrap() {
+            break statuses;
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    assert!(statuses.iter().all(ExitStatus::success));
+    assert_eq!(pipe_child.wait_all().unwrap(), statuses);
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn spawn_async() {
+    use tokio::io::AsyncReadExt;
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let mut reader = cmd!("echo", "test" => "tr", "a-z", "A-Z")
+            .spawn_async()
+            .unwrap();
+        let mut output = String::new();
+        reader.read_to_string(&mut output).await.unwrap();
+        assert_eq!(output, "TEST\n");
+        let statuses = reader.wait().await.unwrap();
+        assert!(statuses.iter().all(ExitStatus::success));
+    });
+}
+
+#[cfg(unix)]
+#[test]
+fn terminate_kill() {
+    use std::os::unix::process::ExitStatusExt;
+    use std::sync::Mutex;
+
+    let pipe_child = Arc::new(Mutex::new(
+        cmd!("sleep", "10" => "sleep", "10").spawn_all().unwrap(),
+    ));
+    let handler = {
+        let pipe_child = Arc::clone(&pipe_child);
+        thread::spawn(move || pipe_child.lock().unwrap().terminate().unwrap())
+    };
+    handler.join().unwrap();
+    let statuses = loop {
+        if let Some(statuses) = pipe_child.lock().unwrap().try_wait_all().unwrap() {
+            break statuses;
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    assert!(statuses.iter().all(|status| status.signal() == Some(15)));
+    pipe_child.lock().unwrap().terminate().unwrap();
+
+    let mut pipe_child = cmd!("sleep", "10" => "sleep", "10").spawn_all().unwrap();
+    pipe_child.kill().unwrap();
+    let statuses = pipe_child.wait_all().unwrap();
+    assert!(statuses.iter().all(|status| status.signal() == Some(9)));
+    pipe_child.kill().unwrap();
+}
+
+#[test]
+fn flag() {
+    let name = "message";
+    let mut a = cmd!("printf", "%s\\n", flag!(name, "a b"), flag!("dry-run") => "cat");
+    assert_eq!(a.output().unwrap().stdout, b"--message=a b\n--dry-run\n");
+}
+
+#[test]
+fn exit_code() {
+    assert_eq!(cmd!("echo", "test" => "cat").exit_code().unwrap(), 0);
+    assert_eq!(cmd!("true" => "sh", "-c", "exit 3").exit_code().unwrap(), 3);
+
+    let mut a = cmd!("sh", "-c", "exit 2" => "true");
+    assert_eq!(a.exit_code().unwrap(), 0);
+    a.pipefail(true);
+    assert_eq!(a.exit_code().unwrap(), 2);
+}
+
+#[cfg(unix)]
+#[test]
+fn exit_code_signal() {
+    let mut a = cmd!("sh", "-c", "kill -KILL $$" => "true");
+    a.pipefail(true);
+    assert_eq!(a.exit_code().unwrap(), 137);
+    assert_eq!(
+        cmd!("true" => "sh", "-c", "kill -TERM $$")
+            .exit_code()
+            .unwrap(),
+        143
+    );
+}
+
+#[test]
+fn cwd_clause() {
+    let dir = std::env::temp_dir();
+    let a = cmd!("pwd", cwd: &dir);
+    assert_eq!(a.get_current_dir(), Some(dir.as_path()));
+
+    let mut b = cmd!("pwd", cwd: "/" => "cat" => "sh", "-c", "cat; pwd", cwd: &dir);
+    assert_eq!(b.commands[0].get_current_dir(), Some("/".as_ref()));
+    assert_eq!(b.commands[1].get_current_dir(), None);
+    assert_eq!(b.commands[2].get_current_dir(), Some(dir.as_path()));
+    let stdout = String::from_utf8(b.output().unwrap().stdout).unwrap();
+    let dir = fs::canonicalize(&dir).unwrap();
+    assert_eq!(stdout, format!("/\n{}\n", dir.display()));
+}
+
+#[test]
+fn to_dot() {
+    let mut a = cmd!("ls" => "grep", "x" => "my \"tool\"");
+    a.label(1, "filter");
+    let dot = a.to_dot();
+    assert!(dot.starts_with("digraph pipeline {\n"));
+    assert!(dot.contains("stage0 [label=\"ls\"];"));
+    assert!(dot.contains("stage1 [label=\"filter\"];"));
+    assert!(dot.contains("stage2 [label=\"my \\\"tool\\\"\"];"));
+    assert!(dot.contains("stage0 -> stage1;"));
+    assert!(dot.contains("stage1 -> stage2;"));
+    assert!(!dot.contains("stage0 -> stage2;"));
+}
+
+#[test]
+fn max_commands() {
+    let mut a = cmd!("echo", "test" => "cat" => "cat");
+    a.max_commands(2);
+    let err = a.status().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    a.max_commands(3);
+    assert!(a.status().unwrap().success());
+}
+
+#[test]
+fn traced() {
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<std::sync::Mutex<Vec<u8>>>);
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let trace = SharedBuf::default();
+    let mut a = cmd!("echo", "a b" => "cat");
+    a.traced_to(trace.clone());
+    assert!(trace.0.lock().unwrap().is_empty());
+    a.output().unwrap();
+    a.status().unwrap();
+    assert_eq!(
+        *trace.0.lock().unwrap(),
+        b"+ echo 'a b' | cat\n+ echo 'a b' | cat\n"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn stage_extra_fd() {
+    let (reader, mut writer) = std::io::pipe().unwrap();
+    writer.write_all(b"secret").unwrap();
+    drop(writer);
+    let mut a = cmd!("echo", "test" => "sh", "-c", "cat; cat <&3");
+    a.stage_extra_fd(1, 3, reader);
+    assert_eq!(a.output().unwrap().stdout, b"test\nsecret");
+
+    let path = temp_path("stage_extra_fd");
+    fs::write(&path, "file").unwrap();
+    let mut b = cmd!("sh", "-c", "cat <&7" => "sh", "-c", "cat; test ! -e /dev/fd/7");
+    b.stage_extra_fd(0, 7, File::open(&path).unwrap());
+    let output = b.output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"file");
+    fs::remove_file(path).unwrap();
+
+    // The targets are swapped, so every file descriptor is the target of the other one.
+    use std::os::unix::io::AsRawFd;
+    let pipes = [std::io::pipe().unwrap(), std::io::pipe().unwrap()];
+    let [(reader_a, mut writer_a), (reader_b, mut writer_b)] = pipes;
+    writer_a.write_all(b"a").unwrap();
+    writer_b.write_all(b"b").unwrap();
+    drop((writer_a, writer_b));
+    let (fd_a, fd_b) = (reader_a.as_raw_fd(), reader_b.as_raw_fd());
+    // The file descriptors are read through `/dev/fd`, since `sh` may not support redirecting
+    // file descriptors above 9.
+    let script = format!("cat /dev/fd/{}; cat /dev/fd/{}", fd_a, fd_b);
+    let mut c = PipeCommand::new([cmd!("sh", "-c", script)]);
+    c.stage_extra_fd(0, fd_b, reader_a);
+    c.stage_extra_fd(0, fd_a, reader_b);
+    assert_eq!(c.output().unwrap().stdout, b"ba");
+    let (reader_c, mut writer_c) = std::io::pipe().unwrap();
+    writer_c.write_all(b"c").unwrap();
+    drop(writer_c);
+    c.stage_extra_fd(0, fd_a, reader_c);
+    assert_eq!(c.output().unwrap().stdout, b"c");
+}
+
+#[cfg(unix)]
+#[test]
+fn umask_all() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = temp_path("umask_all");
+    let mut a = cmd!("echo", "secret" => "tee", &path => "sh", "-c", "cat >/dev/null; umask");
+    a.umask_all(0o077);
+    assert_eq!(a.read().unwrap(), "0077");
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    fs::remove_file(&path).unwrap();
+
+    a.umask_all(0o002);
+    assert_eq!(a.read().unwrap(), "0002");
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o664);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn args_after_separator() {
+    let dir = temp_path("args_after_separator");
+    fs::create_dir(&dir).unwrap();
+    fs::write(dir.join("-n"), "test\n").unwrap();
+    let mut a = cmd!("cat", cwd: &dir);
+    a.stdin(Stdio::null()).args_after_separator(["-n"]);
+    assert!(a.get_args().eq(["--", "-n"]));
+    assert_eq!(a.output().unwrap().stdout, b"test\n");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn args_from_nul_separated() {
+    let dir = temp_path("args_from_nul_separated");
+    fs::create_dir(&dir).unwrap();
+    fs::write(dir.join("a b"), "1\n").unwrap();
+    fs::write(dir.join("c\nd"), "2\n").unwrap();
+    let names = cmd!("find", ".", "-type", "f", "-print0", cwd: &dir => "sort", "-z")
+        .output()
+        .unwrap()
+        .stdout;
+    let mut a = cmd!("cat", cwd: &dir);
+    a.stdin(Stdio::null()).args_from_nul_separated(&names);
+    assert!(a.get_args().eq(["./a b", "./c\nd"]));
+    assert_eq!(a.output().unwrap().stdout, b"1\n2\n");
+    fs::remove_dir_all(dir).unwrap();
+
+    assert!(cmd!("cat")
+        .args_from_nul_separated(b"")
+        .get_args()
+        .next()
+        .is_none());
+    assert!(cmd!("cat")
+        .args_from_nul_separated(b"a\0\0b")
+        .get_args()
+        .eq(["a", "", "b"]));
+}
+
+#[test]
+fn hybrid_pipeline() {
+    let mut a = HybridPipeline::new();
+    a.command(cmd!("echo", "test"))
+        .filter(|chunk| chunk.to_ascii_uppercase())
+        .command(cmd!("cat"));
+    assert_eq!(a.len(), 3);
+    let output = a.output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"TEST\n");
+
+    let mut b = HybridPipeline::new();
+    b.command(cmd!("yes"))
+        .filter(|chunk| chunk.to_vec())
+        .command(cmd!("head", "-n", "2"))
+        .filter(|chunk| chunk.iter().filter(|b| **b != b'\n').copied().collect())
+        .command(cmd!("cat"));
+    assert_eq!(b.output().unwrap().stdout, b"yy");
+
+    let path = temp_path("hybrid-stdin");
+    fs::write(&path, "").unwrap();
+    let mut c = HybridPipeline::new();
+    let mut head = cmd!("readlink", "/proc/self/fd/0");
+    head.stdin(fs::File::open(&path).unwrap());
+    c.command(head)
+        .filter(|chunk| chunk.to_vec())
+        .command(cmd!("cat"));
+    let expected = format!("{}\n", path.display()).into_bytes();
+    assert_eq!(c.output().unwrap().stdout, expected);
+    // The stdin of the first command isn't bridged, so it's kept.
+    assert_eq!(c.output().unwrap().stdout, expected);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn with_trailing() {
+    let trailing: Vec<OsString> = vec!["--no-newline".into(), "a b".into()];
+    let mut a = cmd!("echo", "-n");
+    a.with_trailing(trailing);
+    assert!(a.get_args().eq(["-n", "--no-newline", "a b"]));
+    assert_eq!(a.output().unwrap().stdout, b"--no-newline a b");
+}
+
+#[test]
+fn args_map() {
+    let mut map = std::collections::BTreeMap::new();
+    map.insert("zeta", "1");
+    map.insert("alpha", "two words");
+    map.insert("mid", "3");
+    let mut a = cmd!("echo");
+    a.args_map(&map);
+    assert!(a
+        .get_args()
+        .eq(["--alpha=two words", "--mid=3", "--zeta=1"]));
+    let output = a.output().unwrap();
+    assert_eq!(output.stdout, b"--alpha=two words --mid=3 --zeta=1\n");
+}
+
+#[test]
+fn output_string_stripped() {
+    let colored =
+        "\x1b[1;31merror\x1b[0m: \x1b]8;;https://example.com\x07link\x1b]8;;\x1b\\ \x1b(Bdone\n";
+    let mut a = PipeCommand::new([cmd!("printf", "%s", colored)]);
+    assert_eq!(a.output_string_stripped().unwrap(), "error: link done");
+    assert_eq!(a.read().unwrap(), colored.trim_end());
+}
+
+#[test]
+fn on_exit() {
+    let mut a = cmd!("echo", "test" => "sleep", "0.2" => "true");
+    let mut order = Vec::new();
+    let statuses = a
+        .on_exit(|i, status| order.push((i, status.success())))
+        .unwrap();
+    assert!(statuses.iter().all(ExitStatus::success));
+    assert_eq!(order.len(), 3);
+    assert_eq!(order.last(), Some(&(1, true)));
+}
+
+#[test]
+fn merge_outputs() {
+    let mut a = cmd!("printf", "a1\\na2\\n" => "cat");
+    let mut b = DynPipeCommand::new(vec![cmd!("printf", "b1\\nb2\\n"), cmd!("cat")]);
+    let mut merged = procmd::merge_outputs(&mut a, &mut b).unwrap();
+    let mut output = String::new();
+    merged.read_to_string(&mut output).unwrap();
+    let mut lines: Vec<_> = output.lines().collect();
+    lines.sort_unstable();
+    assert_eq!(lines, ["a1", "a2", "b1", "b2"]);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn merge_outputs_reaps_all_commands() {
+    let path = temp_path("merge_outputs_reaps_all_commands");
+    let mut a = cmd!("sh", "-c", "echo $$ >\"$0\"; echo a", &path => "cat");
+    let mut b = PipeCommand::new([cmd!("echo", "b")]);
+    let mut merged = procmd::merge_outputs(&mut a, &mut b).unwrap();
+    merged.read_to_end(&mut Vec::new()).unwrap();
+    let pid = fs::read_to_string(&path).unwrap();
+    assert!(!fs::exists(format!("/proc/{}", pid.trim())).unwrap());
+    fs::remove_file(path).unwrap();
+
+    let mut empty = DynPipeCommand::default();
+    let err = procmd::merge_outputs(&mut empty, &mut b).err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn status_idle_timeout() {
+    let mut a = cmd!("sh", "-c", "for i in 1 2 3; do echo $i; sleep 0.2; done" => "cat");
+    let status = a.status_idle_timeout(Duration::from_secs(2)).unwrap();
+    assert!(status.unwrap().success());
+
+    let mut b = cmd!("sh", "-c", "echo a; sleep 5; echo b" => "cat");
+    let start = Instant::now();
+    assert!(b
+        .status_idle_timeout(Duration::from_millis(300))
+        .unwrap()
+        .is_none());
+    assert!(start.elapsed() < Duration::from_secs(4));
+}
+
+#[test]
+fn head_stdin_from_reader() {
+    let mut a = PipeCommand::new([cmd!("cat")]);
+    a.head_stdin_from_reader(std::io::Cursor::new(b"test\n".to_vec()));
+    assert_eq!(a.output().unwrap().stdout, b"test\n");
+
+    let mut b = cmd!("head", "-c", "4" => "cat");
+    b.head_stdin_from_reader(std::io::repeat(b'y'));
+    assert_eq!(b.read().unwrap(), "yyyy");
+}
+
+#[test]
+fn typed_flags() {
+    enum Head {
+        Lines(usize),
+        Quiet,
+    }
+    impl Flag for Head {
+        fn to_args(&self) -> Vec<OsString> {
+            match self {
+                Self::Lines(n) => vec!["-n".into(), n.to_string().into()],
+                Self::Quiet => vec!["-q".into()],
+            }
+        }
+    }
+    let mut a = cmd!("head");
+    a.flag(Head::Quiet)
+        .flags(&[Head::Lines(2)])
+        .stdin(Stdio::piped());
+    assert!(a.get_args().eq(["-q", "-n", "2"]));
+    let mut child = a.stdout(Stdio::piped()).spawn().unwrap();
+    child.stdin.take().unwrap().write_all(b"a\nb\nc\n").unwrap();
+    assert_eq!(child.wait_with_output().unwrap().stdout, b"a\nb\n");
+}
+
+#[test]
+fn run_or_panic() {
+    let output = cmd!("echo", "test" => "cat").run_or_panic();
+    assert_eq!(output.stdout, b"test\n");
+}
+
+#[test]
+#[should_panic(
+    expected = "command failed: `echo test | sh -c 'echo oops >&2; exit 3'`\ncommand `sh` at index 1 failed with exit status: 3 (exit code 3)\n--- stderr of `sh` ---\noops"
+)]
+fn run_or_panic_failure() {
+    cmd!("echo", "test" => "sh", "-c", "echo oops >&2; exit 3").run_or_panic();
+}
+
+#[cfg(unix)]
+#[test]
+fn dedup_path_all() {
+    let mut a = cmd!("sh", "-c", "echo $PATH" => "sh", "-c", "cat; echo $DIRS");
+    a.commands[0].env("PATH", "/usr/bin:/bin:/usr/bin::/bin:");
+    a.commands[1].env("DIRS", "/a:/b:/a");
+    a.dedup_path_all();
+    assert_eq!(a.read().unwrap(), "/usr/bin:/bin:\n/a:/b:/a");
+    a.dedup_paths_all(["DIRS"]);
+    assert_eq!(a.read().unwrap(), "/usr/bin:/bin:\n/a:/b");
+}
+
+#[test]
+fn output_full() {
+    let mut a = cmd!("sh", "-c", "echo out; echo err0 >&2" => "sh", "-c", "sleep 0.1; cat; echo err1 >&2; exit 2");
+    let output = a.output_full().unwrap();
+    assert_eq!(output.stdout, b"out\n");
+    assert_eq!(output.stderr, b"err0\nerr1\n");
+    assert!(output.statuses[0].success());
+    assert_eq!(output.statuses[1].code(), Some(2));
+
+    let path = temp_path("output_full");
+    let log = || {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .into()
+    };
+    let mut b = cmd!("echo", "test" => "sh", "-c", "cat; echo error >&2" => "cat");
+    b.intermediate_stderr(log);
+    assert_eq!(b.output_full().unwrap().stderr, b"error\n");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "");
+    // The stderr was reset by `output_full`, so it's only written to the file once configured
+    // again.
+    assert_eq!(b.output().unwrap().stdout, b"test\n");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "");
+    b.intermediate_stderr(log);
+    assert_eq!(b.output().unwrap().stdout, b"test\n");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "error\n");
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn dyn_pipe_command_from_command() {
+    let mut a = DynPipeCommand::from(cmd!("echo", "test"));
+    assert_eq!(a.len(), 1);
+    a.append_stage(cmd!("tr", "a-z", "A-Z"));
+    assert_eq!(a.output().unwrap().stdout, b"TEST\n");
+}
+
+#[test]
+fn stdin_clause() {
+    let path = temp_path("stdin_clause");
+    fs::write(&path, "test\n").unwrap();
+    let mut a = cmd!("cat", stdin: File::open(&path).unwrap() => "tr", "a-z", "A-Z");
+    assert_eq!(a.output().unwrap().stdout, b"TEST\n");
+    let b = cmd!("cat", stdin: Stdio::null()).output().unwrap();
+    assert!(b.stdout.is_empty());
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn spawn_duplex() {
+    use std::io::{BufRead, BufReader};
+
+    let mut a = cmd!("cat" => "sed", "-u", "s/^/> /");
+    let mut pipe_child = a.spawn_duplex().unwrap();
+    let mut stdin = pipe_child.take_stdin().unwrap();
+    let mut stdout = BufReader::new(pipe_child.take_stdout().unwrap());
+    let mut line = String::new();
+    for request in ["first", "second"] {
+        stdin.write_all(request.as_bytes()).unwrap();
+        stdin.write_all(b"\n").unwrap();
+        stdin.flush().unwrap();
+        line.clear();
+        stdout.read_line(&mut line).unwrap();
+        assert_eq!(line, format!("> {}\n", request));
+    }
+    drop(stdin);
+    assert!(pipe_child
+        .wait_all()
+        .unwrap()
+        .iter()
+        .all(ExitStatus::success));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn scope() {
+    let is_reaped = |pid: u32| !std::path::Path::new(&format!("/proc/{}", pid)).exists();
+
+    let mut a = cmd!("echo", "test" => "cat");
+    let (pids, statuses) = a
+        .scope(|pipe_child| {
+            let pids = pipe_child
+                .children
+                .iter()
+                .map(|child| child.id())
+                .collect::<Vec<_>>();
+            (pids, pipe_child.wait_all().unwrap())
+        })
+        .unwrap();
+    assert!(statuses.iter().all(ExitStatus::success));
+    assert!(pids.into_iter().all(is_reaped));
+
+    let mut b = cmd!("sleep", "10" => "cat");
+    let start = Instant::now();
+    let pids = b
+        .scope(|pipe_child| {
+            pipe_child
+                .children
+                .iter()
+                .map(|child| child.id())
+                .collect::<Vec<_>>()
+        })
+        .unwrap();
+    assert!(pids.into_iter().all(is_reaped));
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    let mut pid = 0;
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        b.scope(|pipe_child| {
+            pid = pipe_child.children[0].id();
+            panic!("early exit");
+        })
+    }));
+    assert!(result.is_err());
+    assert!(is_reaped(pid));
+}
+
+#[test]
+fn cmd_builder() {
+    let message = "a b";
+    let a = Cmd::program("git")
+        .arg("--no-pager")
+        .sub("commit")
+        .flag("-m", message)
+        .args(["--quiet", "--allow-empty"])
+        .build();
+    let b = cmd!("git", "--no-pager", "commit", "-m", message, args: ["--quiet", "--allow-empty"]);
+    assert_eq_commands(&a, &b);
+    assert_eq_commands(&Command::from(Cmd::program("ls")), &cmd!("ls"));
+}
+
+#[test]
+fn run_detailed_spawn_failure() {
+    let mut a = cmd!("sleep", "0.2" => "procmd-nonexistent-program" => "cat");
+    a.label(0, "sleeper");
+    let err = a.run_detailed().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    let report = err
+        .get_ref()
+        .unwrap()
+        .downcast_ref::<procmd::PartialReport>()
+        .unwrap();
+    assert_eq!(report.failed, 1);
+    assert_eq!(report.stages.len(), 3);
+    let first = report.stages[0].as_ref().unwrap();
+    assert_eq!(first.label.as_deref(), Some("sleeper"));
+    assert!(!first.success);
+    assert!(first.duration < Duration::from_millis(200));
+    assert!(report.stages[1..].iter().all(Option::is_none));
+    assert!(err
+        .to_string()
+        .starts_with("failed to spawn the command at index 1"));
+}
+
+#[test]
+fn error_on_failure() {
+    let mut a = cmd!("false" => "cat");
+    assert!(a.status().unwrap().success());
+    a.error_on_failure(true);
+    assert!(a.status().unwrap().success());
+    a.pipefail(true);
+    let err = a.status().unwrap_err();
+    let err = err
+        .get_ref()
+        .unwrap()
+        .downcast_ref::<CommandError>()
+        .unwrap();
+    assert_eq!(err.index, 0);
+    assert_eq!(a.exit_code().unwrap(), 1);
+
+    let mut b = cmd!("echo", "test" => "sh", "-c", "cat; exit 3");
+    assert_eq!(b.output().unwrap().status.code(), Some(3));
+    b.error_on_failure(true);
+    assert!(b.output().is_err());
+    b.allow_exit_codes(1, [3]);
+    assert_eq!(b.output().unwrap().stdout, b"test\n");
+}
+
+#[test]
+fn env_last_wins() {
+    let mut a = cmd!("sh", "-c", "echo $A", env: [("A", "1"), ("A", "2")], env: vec![("B", "3")]);
+    assert_eq!(a.output().unwrap().stdout, b"2\n");
+
+    let mut b = cmd!("sh", "-c", "echo $A$B" => "sh", "-c", "cat; echo $A$B");
+    b.env_all(vec![("A", "1"), ("B", "2"), ("A", "3")]);
+    assert_eq!(b.read().unwrap(), "32\n32");
+}
+
+#[cfg(unix)]
+#[test]
+fn plan() {
+    let dir = std::env::temp_dir();
+    let mut a = cmd!("sh", "-c", "true", cwd: &dir => "cat");
+    a.inherit_env(false);
+    a.commands[0].env("A", "1").env("B", "2").env_remove("B");
+    a.commands[1].env("C", "3");
+    let plan = a.plan().unwrap();
+    assert_eq!(plan.len(), 2);
+    assert_eq!(plan[0].program, a.resolved_program(0).unwrap());
+    assert!(plan[0].program.is_absolute());
+    assert_eq!(plan[0].argv, ["sh", "-c", "true"]);
+    assert_eq!(plan[0].env, [("A".into(), "1".into())]);
+    assert_eq!(plan[0].cwd, dir);
+    assert_eq!(plan[1].argv, ["cat"]);
+    assert_eq!(plan[1].env, [("C".into(), "3".into())]);
+    assert_eq!(plan[1].cwd, std::env::current_dir().unwrap());
+
+    let mut b = cmd!("cat");
+    b.env("PROCMD_PLAN", "x");
+    let plan = PipeCommand::new([b]).plan().unwrap();
+    assert!(plan[0].env.len() > 1);
+    assert!(plan[0].env.contains(&("PROCMD_PLAN".into(), "x".into())));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn stdin_policy() {
+    use procmd::StdinPolicy;
+
+    let mut a = cmd!("readlink", "/proc/self/fd/0" => "cat");
+    let inherited = fs::read_link("/proc/self/fd/0").unwrap();
+    assert_eq!(a.read().unwrap(), "/dev/null");
+    a.commands[1].stdout(Stdio::piped());
+    let mut child = a.spawn().unwrap();
+    let mut output = String::new();
+    let mut stdout = child.stdout.take().unwrap();
+    stdout.read_to_string(&mut output).unwrap();
+    child.wait().unwrap();
+    assert_eq!(PathBuf::from(output.trim()), inherited);
+    a.commands[1].stdout(Stdio::inherit());
+
+    let path = temp_path("stdin-policy");
+    fs::write(&path, "").unwrap();
+    let mut b = cmd!("readlink", "/proc/self/fd/0", stdin: fs::File::open(&path).unwrap() => "cat");
+    b.set_stdin_inherit_policy(StdinPolicy::Null);
+    assert_eq!(PathBuf::from(b.read().unwrap()), path);
+    assert_eq!(PathBuf::from(b.read().unwrap()), path);
+    b.head_stdin_from_reader(std::io::empty());
+    assert!(b.read().unwrap().starts_with("pipe:"));
+    assert_eq!(b.read().unwrap(), "/dev/null");
+    fs::remove_file(&path).unwrap();
+
+    a.set_stdin_inherit_policy(StdinPolicy::Null);
+    assert_eq!(a.read().unwrap(), "/dev/null");
+    a.head_stdin_from_reader(std::io::empty());
+    assert!(a.read().unwrap().starts_with("pipe:"));
+    assert_eq!(a.read().unwrap(), "/dev/null");
+
+    a.set_stdin_inherit_policy(StdinPolicy::Piped);
+    let mut pipe_child = a.spawn_all().unwrap();
+    assert!(pipe_child.take_stdin().is_some());
+    pipe_child.wait_all().unwrap();
+
+    a.set_stdin_inherit_policy(StdinPolicy::Inherit);
+    assert_eq!(PathBuf::from(a.read().unwrap()), inherited);
+}
+
+#[test]
+fn to_script() {
+    use procmd::ScriptFlavor;
+
+    let mut a = cmd!("ls", "-a", env: [("FOO", "bar baz")], cwd: "/tmp" => "grep", "x", env: [("FOO", "bar baz"), ("LC_ALL", "C")], cwd: "/tmp");
+    a.pipefail(true);
+    assert_eq!(
+        a.to_script(ScriptFlavor::Bash),
+        "#!/usr/bin/env bash\nset -e\nset -o pipefail\nexport FOO='bar baz'\ncd /tmp\nls -a | env LC_ALL=C grep x\n"
+    );
+    assert_eq!(
+        a.to_script(ScriptFlavor::Posix),
+        "#!/bin/sh\nset -e\nexport FOO='bar baz'\ncd /tmp\nls -a | env LC_ALL=C grep x\n"
+    );
+
+    a.inherit_env(false);
+    assert_eq!(
+        a.to_script(ScriptFlavor::Posix),
+        "#!/bin/sh\nset -e\ncd /tmp\nenv -i FOO='bar baz' ls -a | env -i FOO='bar baz' LC_ALL=C grep x\n"
+    );
+
+    let a = cmd!("echo", "hi", cwd: "/" => "cat");
+    let script = a.to_script(ScriptFlavor::Posix);
+    assert_eq!(script, "#!/bin/sh\nset -e\n(cd / && exec echo hi) | cat\n");
+    let output = Command::new("sh").arg("-c").arg(&script).output().unwrap();
+    assert_eq!(output.stdout, b"hi\n");
+}
+
+#[test]
+fn throttle() {
+    let mut a = cmd!("head", "-c", "50000", "/dev/zero" => "cat");
+    a.throttle(100_000);
+    let mut len = 0;
+    let start = Instant::now();
+    a.for_each_chunk(|chunk| len += chunk.len()).unwrap();
+    let elapsed = start.elapsed();
+    assert_eq!(len, 50_000);
+    assert!(elapsed >= Duration::from_millis(400), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+}
+
+#[test]
+fn set_env_all() {
+    use std::collections::HashMap;
+
+    let mut env = HashMap::new();
+    env.insert(OsString::from("FOO"), OsString::from("bar"));
+    env.insert(OsString::from("LC_ALL"), OsString::from("C"));
+    let mut a = cmd!("/usr/bin/env", env: [("BAZ", "qux")] => "/usr/bin/sort");
+    a.set_env_all(&env, true);
+    assert_eq!(a.read().unwrap(), "FOO=bar\nLC_ALL=C");
+
+    let mut a = cmd!("/usr/bin/env" => "/usr/bin/grep", "^FOO=");
+    a.set_env_all(&env, false);
+    assert_eq!(a.read().unwrap(), "FOO=bar");
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn run_async() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let output = cmd!("echo", "test" => "tr", "a-z", "A-Z")
+            .run_async()
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"TEST\n");
+        assert!(output.status.success());
+
+        let mut a = cmd!("false" => "true");
+        a.pipefail(true);
+        assert!(!a.run_async().await.unwrap().status.success());
+    });
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn run_async_cancel() {
+    use std::future::Future;
+    use std::task::{Context, Waker};
+
+    let marker = temp_path("run-async-cancel");
+    let script = format!("sleep 0.5; touch {}", marker.display());
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let mut a = cmd!("sh", "-c", &script => "cat");
+        let mut future = Box::pin(a.run_async());
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(future.as_mut().poll(&mut cx).is_pending());
+    });
+    thread::sleep(Duration::from_secs(1));
+    assert!(!marker.exists());
+}
+
+#[test]
+fn stderr_all_to() {
+    use std::sync::Mutex;
+
+    let sink = Arc::new(Mutex::new(Vec::new()));
+    let mut a = cmd!("sh", "-c", "echo one >&2; echo out" => "sh", "-c", "cat; echo two >&2" => "sh", "-c", "cat; echo three >&2");
+    a.stderr_all_to(Arc::clone(&sink));
+    let output = a.output().unwrap();
+    assert_eq!(output.stdout, b"out\n");
+    assert!(output.stderr.is_empty());
+    let mut lines = String::from_utf8(sink.lock().unwrap().clone())
+        .unwrap()
+        .lines()
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+    lines.sort();
+    assert_eq!(lines, ["one", "three", "two"]);
+
+    a.status().unwrap();
+    assert_eq!(sink.lock().unwrap().len(), 28);
+
+    sink.lock().unwrap().clear();
+    let mut b = cmd!("sh", "-c", "echo first >&2; echo out; sleep 0.2; echo last >&2" => "cat");
+    b.stderr_all_to(Arc::clone(&sink));
+    let mut seen = Vec::new();
+    b.for_each_line(|_| {
+        thread::sleep(Duration::from_millis(100));
+        seen = sink.lock().unwrap().clone();
+    })
+    .unwrap();
+    assert_eq!(seen, b"first\n");
+    assert_eq!(*sink.lock().unwrap(), b"first\nlast\n");
+}
+
+#[test]
+fn input_to() {
+    let mut a = cmd!("tr", "a-z", "A-Z" => "cat");
+    a.input_to(0, "test").unwrap();
+    assert_eq!(a.read().unwrap(), "TEST");
+
+    let e = a.input_to(1, "test").unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    assert!(e.to_string().contains("stdout of the command at index 0"));
+    let e = a.input_to(2, "test").unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn run_quiet() {
+    cmd!("sh", "-c", "echo warning >&2" => "true")
+        .run_quiet()
+        .unwrap();
+
+    let e = cmd!("sh", "-c", "echo first >&2; exit 3" => "sh", "-c", "cat; echo second >&2")
+        .run_quiet()
+        .unwrap_err();
+    let e = match e {
+        QuietError::Command(e) => e,
+        QuietError::Io(e) => panic!("unexpected error: {}", e),
+    };
+    assert_eq!(e.index, 0);
+    assert_eq!(e.status.code(), Some(3));
+    let stderr = String::from_utf8(e.stderr.clone()).unwrap();
+    assert!(stderr.contains("first\n") && stderr.contains("second\n"));
+    assert!(e
+        .to_string()
+        .ends_with(&format!(":\n{}", stderr.trim_end())));
+
+    let e = std::io::Error::from(QuietError::Command(e));
+    assert!(e
+        .get_ref()
+        .unwrap()
+        .downcast_ref::<CommandError>()
+        .is_some());
+
+    let e = cmd!("nonexistent-program" => "cat")
+        .run_quiet()
+        .unwrap_err();
+    assert!(matches!(&e, QuietError::Io(e) if e.kind() == std::io::ErrorKind::NotFound));
+    assert_eq!(std::io::Error::from(e).kind(), std::io::ErrorKind::NotFound);
+
+    let path = temp_path("run_quiet");
+    let log = || {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .into()
+    };
+    let mut a = cmd!("echo", "test" => "sh", "-c", "cat; echo error >&2" => "cat");
+    a.intermediate_stderr(log);
+    assert_eq!(a.output().unwrap().stdout, b"test\n");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "error\n");
+    a.run_quiet().unwrap();
+    // The stderr was reset by `run_quiet`, so it's only written to the file once configured again.
+    assert_eq!(a.output().unwrap().stdout, b"test\n");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "error\n");
+    a.intermediate_stderr(log);
+    assert_eq!(a.output().unwrap().stdout, b"test\n");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "error\nerror\n");
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn dyn_pipe_command_from_specs() {
+    let word = String::from("b");
+    let pattern = OsString::from("b");
+    let mut a =
+        DynPipeCommand::from_specs(&[&[&"printf", &"a\\n%s\\n", &word], &[&"grep", &pattern]]);
+    assert_eq_commands(&a.commands[0], &cmd!("printf", "a\\n%s\\n", "b"));
+    assert_eq_commands(&a.commands[1], &cmd!("grep", "b"));
+    assert_eq!(a.output().unwrap().stdout, b"b\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn verify_relative_programs() {
+    cmd!("./ls", cwd: "/bin" => "cat")
+        .verify_relative_programs()
+        .unwrap();
+    cmd!("ls" => "cat", cwd: "/nonexistent")
+        .verify_relative_programs()
+        .unwrap();
+
+    let dir = std::env::temp_dir();
+    let e = cmd!("cat" => "./missing-tool", cwd: &dir)
+        .verify_relative_programs()
+        .unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+    assert_eq!(
+        e.to_string(),
+        format!(
+            "the command at index 1: program `./missing-tool` does not exist relative to the \
+             working directory `{}`",
+            dir.display()
+        )
+    );
+}
+
+#[test]
+fn timeout_clause() {
+    let mut a = cmd!("echo", "test" => "sleep", "10", timeout: Duration::from_millis(200) => "cat");
+    let start = Instant::now();
+    let e = a.status().unwrap_err();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+    assert!(e
+        .to_string()
+        .starts_with("command `sleep` at index 1 didn't exit within"));
+
+    let start = Instant::now();
+    assert_eq!(a.status_timeout(Duration::from_secs(10)).unwrap(), None);
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    let mut b = cmd!("true", timeout: Duration::from_secs(10));
+    assert!(b.status().unwrap().success());
+}
+
+#[test]
+fn run_detailed_timestamps() {
+    use std::time::SystemTime;
+
+    let before = SystemTime::now();
+    let report = cmd!("sleep", "0.1" => "cat").run_detailed().unwrap();
+    let after = SystemTime::now();
+    assert!(report.started_at >= before);
+    assert!(report.finished_at <= after);
+    let elapsed = report
+        .finished_at
+        .duration_since(report.started_at)
+        .unwrap();
+    assert!(elapsed >= Duration::from_millis(100));
+}
+
+#[test]
+fn mediated_pipes() {
+    let path = temp_path("mediated");
+    let mut a =
+        cmd!("head", "-c", "1000000", "/dev/urandom" => "tee", &path => "cat" => "wc", "-c");
+    a.mediated_pipes(true);
+    assert_eq!(a.read().unwrap().trim(), "1000000");
+
+    let mut b = cmd!("cat", &path => "cat" => "cat");
+    b.mediated_pipes(true);
+    assert_eq!(b.output().unwrap().stdout, fs::read(&path).unwrap());
+
+    let mut c = cmd!("yes" => "head", "-n", "3");
+    c.mediated_pipes(true).pipefail(true);
+    assert_eq!(c.read().unwrap(), "y\ny\ny");
+    assert!(c.status_checked().is_ok());
+}
+
+#[test]
+fn tap() {
+    use std::sync::Mutex;
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let mut a = cmd!("printf", "a\\nb\\n" => "tr", "a-z", "A-Z");
+    a.tap(0, {
+        let seen = Arc::clone(&seen);
+        move |chunk| seen.lock().unwrap().extend_from_slice(chunk)
+    });
+    assert_eq!(a.read().unwrap(), "A\nB");
+    assert_eq!(*seen.lock().unwrap(), b"a\nb\n");
+
+    assert_eq!(a.read().unwrap(), "A\nB");
+    assert_eq!(*seen.lock().unwrap(), b"a\nb\na\nb\n");
+}
+
+#[test]
+fn transform() {
+    use std::sync::Mutex;
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let mut a = cmd!("echo", "token=secret" => "cat");
+    a.transform(0, |chunk| {
+        String::from_utf8_lossy(chunk)
+            .replace("secret", "***")
+            .into_bytes()
+    })
+    .tap(0, {
+        let seen = Arc::clone(&seen);
+        move |chunk| seen.lock().unwrap().extend_from_slice(chunk)
+    });
+    assert_eq!(a.read().unwrap(), "token=***");
+    assert_eq!(*seen.lock().unwrap(), b"token=***\n");
+}
+
+#[cfg(feature = "encoding")]
+#[test]
+fn encoding() {
+    use procmd::encoding_rs::{UTF_16LE, WINDOWS_1252};
+
+    let text = "café crème, naïve";
+    let mut a = cmd!("cat" => "cat");
+    a.input_encoded(text, WINDOWS_1252).unwrap();
+    let output = a.output().unwrap();
+    assert_eq!(output.stdout.len(), text.chars().count());
+    assert_eq!(output.stdout[3], 0xe9);
+
+    a.input_encoded(text, WINDOWS_1252).unwrap();
+    assert_eq!(a.output_string_with(WINDOWS_1252).unwrap(), text);
+
+    let error = a.input_encoded("€ ✓", WINDOWS_1252).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    let error = a.input_encoded(text, UTF_16LE).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+/// Returns a pipeline whose last command writes the targets of its stdout and stderr to `path`.
+#[cfg(target_os = "linux")]
+fn stdio_probe(path: &std::path::Path) -> PipeCommand<2> {
+    let script = "cat >/dev/null; targets=$(readlink /proc/$$/fd/1 /proc/$$/fd/2); \
+                  echo \"$targets\" >\"$0\"";
+    cmd!("echo", "test" => "sh", "-c", script, path)
+}
+
+/// Asserts that the last command of a pipeline created by [`stdio_probe`] inherits the stdout and
+/// stderr of the current process when it's run with `status`.
+#[cfg(target_os = "linux")]
+fn assert_tail_inherits(a: &mut PipeCommand<2>, path: &std::path::Path) {
+    assert!(a.status().unwrap().success());
+    let expected = format!(
+        "{}\n{}\n",
+        fs::read_link("/proc/self/fd/1").unwrap().display(),
+        fs::read_link("/proc/self/fd/2").unwrap().display()
+    );
+    assert_eq!(fs::read_to_string(path).unwrap(), expected);
+}
+
+#[test]
+fn output_after_tail_reset() {
+    let mut a = cmd!("echo", "test" => "sh", "-c", "cat; echo error >&2");
+    assert_eq!(a.read().unwrap(), "test");
+    assert_eq!(a.output().unwrap().stdout, b"test\n");
+    a.run_detailed().unwrap();
+    let output = a.output().unwrap();
+    assert_eq!(output.stdout, b"test\n");
+    assert_eq!(output.stderr, b"error\n");
+
+    let mut b: DynPipeCommand = "echo test | sh -c 'cat; echo error >&2'".parse().unwrap();
+    b.run_detailed().unwrap();
+    let output = b.output().unwrap();
+    assert_eq!(output.stdout, b"test\n");
+    assert_eq!(output.stderr, b"error\n");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn tail_stdio_restored() {
+    let path = temp_path("tail_stdio_restored");
+    let mut a = stdio_probe(&path);
+    assert_tail_inherits(&mut a, &path);
+
+    a.read().unwrap();
+    assert_tail_inherits(&mut a, &path);
+    let (tx, _rx) = mpsc::sync_channel(16);
+    a.stream_to_channel(tx).unwrap();
+    assert_tail_inherits(&mut a, &path);
+    let mut pipe_child = a.spawn_duplex().unwrap();
+    drop(pipe_child.take_stdin());
+    pipe_child.wait_all().unwrap();
+    assert_tail_inherits(&mut a, &path);
+    a.run_detailed().unwrap();
+    assert_tail_inherits(&mut a, &path);
+    a.output_tee(std::io::sink()).unwrap();
+    assert_tail_inherits(&mut a, &path);
+    let (mut stdout, mut stderr) = a.split_output().unwrap();
+    stdout.read_to_end(&mut Vec::new()).unwrap();
+    stderr.read_to_end(&mut Vec::new()).unwrap();
+    assert_tail_inherits(&mut a, &path);
+    a.output_timeout(Duration::from_secs(5)).unwrap();
+    assert_tail_inherits(&mut a, &path);
+    a.run_or_panic();
+    assert_tail_inherits(&mut a, &path);
+
+    #[cfg(feature = "async")]
+    {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(a.run_async()).unwrap();
+        assert_tail_inherits(&mut a, &path);
+    }
+    fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,7 @@
+use procmd::PipeCommand;
+use std::process::{Command, Stdio};
+
+fn main() {
+    let mut pipe_cmd = PipeCommand::new([Command::new("ls")]);
+    pipe_cmd.intermediate_stderr(Stdio::null);
+}
@@ -0,0 +1,5 @@
+use procmd::cmd;
+
+fn main() {
+    let _ = cmd!("ls" => "grep", "example", stdin: std::process::Stdio::null());
+}
@@ -9,33 +9,100 @@ use proc_macro::TokenStream;
 use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
 use syn::parse::{Parse, ParseStream};
-use syn::{parse_macro_input, punctuated::Punctuated, Token};
+use syn::{parse_macro_input, Token};
 use vec1::Vec1;
 
+enum Arg {
+    Single(syn::Expr),
+    Many(syn::Expr),
+}
+
 struct Command {
     program: syn::Expr,
-    args: Vec<syn::Expr>,
+    args: Vec<Arg>,
+    cwd: Option<syn::Expr>,
+    envs: Vec<syn::Expr>,
+    ok_codes: Option<syn::Expr>,
+    stdin: Option<syn::Expr>,
+    timeout: Option<syn::Expr>,
 }
 
-impl Parse for Command {
-    fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
-        let mut exprs =
-            Punctuated::<syn::Expr, Token![,]>::parse_separated_nonempty(input)?.into_iter();
-        Ok(Command {
-            program: exprs.next().unwrap(),
-            args: exprs.collect(),
-        })
+impl Command {
+    /// Parses a command, where `first` is whether it is the first command of a pipe.
+    fn parse(input: ParseStream<'_>, first: bool) -> syn::Result<Self> {
+        let mut command = Command {
+            program: input.parse()?,
+            args: Vec::new(),
+            cwd: None,
+            envs: Vec::new(),
+            ok_codes: None,
+            stdin: None,
+            timeout: None,
+        };
+        while input.peek(Token![,]) {
+            <Token![,]>::parse(input)?;
+            if peek_clause(input) {
+                command.parse_clause(input, first)?;
+            } else {
+                command.args.push(Arg::Single(input.parse()?));
+            }
+        }
+        Ok(command)
+    }
+
+    fn parse_clause(&mut self, input: ParseStream<'_>, first: bool) -> syn::Result<()> {
+        let name = input.parse::<syn::Ident>()?;
+        <Token![:]>::parse(input)?;
+        let value = input.parse::<syn::Expr>()?;
+        let slot =
+            match name.to_string().as_str() {
+                "args" => {
+                    self.args.push(Arg::Many(value));
+                    return Ok(());
+                }
+                "cwd" => &mut self.cwd,
+                "env" => {
+                    self.envs.push(value);
+                    return Ok(());
+                }
+                "ok_codes" => &mut self.ok_codes,
+                "stdin" if !first => return Err(syn::Error::new(
+                    name.span(),
+                    "the `stdin` clause can only be used on the first command of a pipe, since \
+                     the stdin of the other commands is the stdout of the previous command",
+                )),
+                "stdin" => &mut self.stdin,
+                "timeout" => &mut self.timeout,
+                _ => {
+                    return Err(syn::Error::new(
+                        name.span(),
+                        format!("unknown clause `{}`", name),
+                    ))
+                }
+            };
+        if slot.is_some() {
+            return Err(syn::Error::new(
+                name.span(),
+                format!("duplicate clause `{}`", name),
+            ));
+        }
+        *slot = Some(value);
+        Ok(())
     }
 }
 
+fn peek_clause(input: ParseStream<'_>) -> bool {
+    input.peek(syn::Ident) && input.peek2(Token![:]) && !input.peek2(Token![::])
+}
+
 struct Commands(Vec1<Command>);
 
 impl Parse for Commands {
     fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
-        let mut commands = Vec1::new(Command::parse(input)?);
+        let mut commands = Vec1::new(Command::parse(input, true)?);
         while !input.is_empty() {
             <Token![=>]>::parse(input)?;
-            commands.push(Command::parse(input)?);
+            commands.push(Command::parse(input, false)?);
         }
         Ok(Self(commands))
     }
@@ -43,26 +110,62 @@ impl Parse for Commands {
 
 impl Commands {
     fn into_token_stream(self) -> TokenStream2 {
-        let mut i = 0usize;
+        let mut setup = self
+            .0
+            .iter()
+            .enumerate()
+            .map(|(i, command)| {
+                let ok_codes = command.ok_codes.iter();
+                let timeout = command.timeout.iter();
+                quote! {
+                    #(pipe_cmd.allow_exit_codes(#i, #ok_codes);)*
+                    #(pipe_cmd.stage_timeout(#i, #timeout);)*
+                }
+            })
+            .filter(|ts| !ts.is_empty())
+            .collect::<Vec<_>>();
+        // A pipe command sets the stdin with `head_stdin`, so it takes precedence over the policy.
+        let is_pipe = self.0.len() > 1 || !setup.is_empty();
+        if is_pipe {
+            let stdin = self.0.first().stdin.iter();
+            setup.push(quote! { #(pipe_cmd.head_stdin(#stdin);)* });
+            setup.retain(|ts| !ts.is_empty());
+        }
         let ts = self.0.mapped_ref(|command| {
             let program = &command.program;
-            let args = &command.args;
-            i += 1;
+            let args = command.args.iter().map(|arg| match arg {
+                Arg::Single(arg) => quote! { cmd.arg(#arg); },
+                Arg::Many(args) => quote! { cmd.args(#args); },
+            });
+            let cwd = command.cwd.iter();
+            let envs = command.envs.iter();
+            let stdin = command.stdin.iter().filter(|_| !is_pipe);
             quote! {{
                 let mut cmd = ::std::process::Command::new(#program);
-                #(cmd.arg(#args);)*
+                #(#args)*
+                #(cmd.current_dir(#cwd);)*
+                #(cmd.envs(#envs);)*
+                #(cmd.stdin(#stdin);)*
                 cmd
             }}
         });
         match ts.split_off_first() {
-            (first, rest) if rest.is_empty() => first,
+            (first, rest) if rest.is_empty() && setup.is_empty() => first,
             (first, rest) => {
                 let ts = rest.into_iter().fold(first, |mut acc, x| {
                     acc.extend(quote! {,});
                     acc.extend(x);
                     acc
                 });
-                quote! { ::procmd::PipeCommand::new([#ts]) }
+                if setup.is_empty() {
+                    quote! { ::procmd::PipeCommand::new([#ts]) }
+                } else {
+                    quote! {{
+                        let mut pipe_cmd = ::procmd::PipeCommand::new([#ts]);
+                        #(#setup)*
+                        pipe_cmd
+                    }}
+                }
             }
         }
     }